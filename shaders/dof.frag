@@ -0,0 +1,62 @@
+#version 450
+
+layout(location = 0) in vec2 v_texcoord;
+layout(location = 0) out vec4 f_color;
+
+layout(set = 0, binding = 0) uniform DofData {
+    mat4 inverse_proj;
+    vec2 texel_size;
+    // Scene units along the view direction
+    float focus_distance;
+    // A point at depth d is blurred into coc_scale * |d - focus_distance| / d pixels
+    float coc_scale;
+    // Largest blur diameter in pixels
+    float max_coc;
+} dof;
+
+layout(set = 0, binding = 1) uniform sampler2D color_map;
+layout(set = 0, binding = 2) uniform sampler2D depth_map;
+
+const int SAMPLES = 48;
+const float GOLDEN_ANGLE = 2.39996323;
+
+// Distance along the view direction, the cleared reverse-Z depth of the background is infinitely far
+float view_depth(vec2 uv) {
+    float depth = texture(depth_map, uv).r;
+    if (depth <= 0.0) {
+        return 1e30;
+    }
+    vec4 p = dof.inverse_proj * vec4(uv * 2.0 - 1.0, depth, 1.0);
+    return -p.z / p.w;
+}
+
+float coc(float depth) {
+    return min(dof.coc_scale * abs(depth - dof.focus_distance) / max(depth, 1e-6), dof.max_coc);
+}
+
+// Gathers a disk as large as the largest blur. Each sample counts when its own blur reaches this
+// pixel, so blurred foreground spreads over sharp background but sharp foreground stays crisp.
+void main() {
+    float center_depth = view_depth(v_texcoord);
+    float center_coc = coc(center_depth);
+
+    vec3 sum = texture(color_map, v_texcoord).rgb;
+    float weight = 1.0;
+    for (int i = 1; i < SAMPLES; i++) {
+        float radius = sqrt(float(i) / float(SAMPLES)) * dof.max_coc * 0.5;
+        float angle = float(i) * GOLDEN_ANGLE;
+        vec2 uv = v_texcoord + vec2(cos(angle), sin(angle)) * radius * dof.texel_size;
+
+        float depth = view_depth(uv);
+        float sample_coc = coc(depth);
+        // Background cannot blur over this pixel more than the pixel itself is blurred
+        if (depth > center_depth) {
+            sample_coc = min(sample_coc, center_coc);
+        }
+        float w = smoothstep(radius - 1.0, radius + 1.0, sample_coc * 0.5);
+        sum += texture(color_map, uv).rgb * w;
+        weight += w;
+    }
+
+    f_color = vec4(sum / weight, 1.0);
+}
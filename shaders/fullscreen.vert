@@ -0,0 +1,10 @@
+#version 450
+
+// A single triangle covering the screen, the texture coordinates are 0 to 1 across the viewport
+layout(location = 0) in vec2 position;
+layout(location = 0) out vec2 v_texcoord;
+
+void main() {
+    v_texcoord = position * 0.5 + 0.5;
+    gl_Position = vec4(position, 0.0, 1.0);
+}
@@ -0,0 +1,18 @@
+layout(set = 0, binding = 1) uniform MaterialData {
+    vec4 base_color;
+    vec4 uv_tiling_offset;
+    float metallic;
+    float roughness;
+    float normal_strength;
+    float normal_green_sign;
+} material;
+
+layout(set = 0, binding = 2) uniform sampler2D albedo_map;
+layout(set = 0, binding = 3) uniform sampler2D normal_map;
+layout(set = 0, binding = 4) uniform sampler2D ao_map;
+layout(set = 0, binding = 5) uniform sampler2D metallic_map;
+layout(set = 0, binding = 6) uniform sampler2D roughness_map;
+
+vec2 material_uv(vec2 texcoord) {
+    return texcoord * material.uv_tiling_offset.xy + material.uv_tiling_offset.zw;
+}
@@ -0,0 +1,7 @@
+layout(set = 0, binding = 0) uniform Data {
+    mat4 world;
+    mat4 view;
+    mat4 proj;
+    // Scale from the lit surface color to the displayed color, 1 without a physical camera
+    float exposure;
+} uniforms;
@@ -0,0 +1,22 @@
+#version 450
+#extension GL_GOOGLE_include_directive : require
+
+#include <include/uniforms.glsl>
+#include <include/material.glsl>
+
+layout(location = 0) in vec3 v_normal;
+layout(location = 1) in vec2 v_texcoord;
+layout(location = 0) out vec4 f_color;
+
+const vec3 LIGHT = vec3(0.0, 0.0, 1.0);
+
+void main() {
+    vec2 uv = material_uv(v_texcoord);
+    vec4 base_color = material.base_color * texture(albedo_map, uv);
+    float ao = texture(ao_map, uv).r;
+
+    float brightness = dot(normalize(v_normal), normalize(LIGHT));
+    vec3 dark_color = base_color.rgb * 0.6;
+    vec3 regular_color = base_color.rgb;
+    f_color = vec4(mix(dark_color, regular_color, brightness) * ao * uniforms.exposure, base_color.a);
+}
@@ -0,0 +1,17 @@
+#version 450
+#extension GL_GOOGLE_include_directive : require
+
+#include <include/uniforms.glsl>
+
+layout(location = 0) in vec3 position;
+layout(location = 1) in vec3 normal;
+layout(location = 2) in vec2 texcoord;
+layout(location = 0) out vec3 v_normal;
+layout(location = 1) out vec2 v_texcoord;
+
+void main() {
+    mat4 worldview = uniforms.view * uniforms.world;
+    v_normal = transpose(inverse(mat3(worldview))) * normal;
+    v_texcoord = texcoord;
+    gl_Position = uniforms.proj * worldview * vec4(position, 1.0);
+}
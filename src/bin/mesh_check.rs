@@ -0,0 +1,47 @@
+extern crate vulkan_test;
+
+use vulkan_test::obj_loader;
+use vulkan_test::obj_loader::validate::{self, RepairOptions};
+
+use std::env;
+use std::path::PathBuf;
+use std::process;
+
+// Validates an OBJ file and prints what is wrong with it, exits with 1 when problems were found
+// so it can run as a check over an asset folder.
+//
+// usage: mesh_check <model.obj> [--repair]
+//
+// With --repair the safe repairs are applied and the repaired mesh is validated again,
+// showing what is left for the artist to fix.
+
+fn main() {
+    let mut input: Option<PathBuf> = None;
+    let mut repair = false;
+
+    for arg in env::args().skip(1) {
+        match arg.as_str() {
+            "--repair" => repair = true,
+            _ => input = Some(PathBuf::from(arg))
+        }
+    }
+
+    let input = input.unwrap_or_else(|| {
+        println!("usage: mesh_check <model.obj> [--repair]");
+        process::exit(1);
+    });
+
+    let mut model = obj_loader::parse_obj(&input);
+    let mut report = validate::validate(&model);
+    print!("{}:\n{}", input.display(), report);
+
+    if repair && !report.is_clean() {
+        println!("repair:\n{}", validate::repair(&mut model, &RepairOptions::all()));
+        report = validate::validate(&model);
+        print!("after repair:\n{}", report);
+    }
+
+    if !report.is_clean() {
+        process::exit(1);
+    }
+}
@@ -0,0 +1,65 @@
+extern crate vulkan_test;
+
+use vulkan_test::material::MaterialSource;
+use vulkan_test::material::scan;
+use vulkan_test::material::textures::cook_textures;
+
+use std::env;
+use std::path::{Path, PathBuf};
+use std::process;
+
+// Cooks the textures of a material into block compressed KTX2 files in the texture cache,
+// where the viewer picks them up instead of generating mips on every start.
+//
+// usage: texture_cook <material.toml | megascans.json | asset folder or base name> [--max-size N]
+
+const DEFAULT_MAX_SIZE: u32 = 2048;
+
+fn material_source(input: &Path) -> MaterialSource {
+    let extension = input.extension().map(|e| e.to_string_lossy().to_lowercase());
+    match extension.as_ref().map(|e| e.as_str()) {
+        Some("toml") => MaterialSource::new(input),
+        Some("json") => MaterialSource::megascans(input),
+        _ => match scan::find_files(input, &["json"]).into_iter().next() {
+            Some(json) => MaterialSource::megascans(json),
+            None => MaterialSource::scan(input)
+        }
+    }
+}
+
+fn main() {
+    let mut input: Option<PathBuf> = None;
+    let mut max_size = DEFAULT_MAX_SIZE;
+
+    let mut args = env::args().skip(1);
+    while let Some(arg) = args.next() {
+        match arg.as_str() {
+            "--max-size" => {
+                max_size = args.next().and_then(|v| v.parse().ok()).expect("--max-size needs a size in pixels");
+            },
+            _ => input = Some(PathBuf::from(arg))
+        }
+    }
+
+    let input = input.unwrap_or_else(|| {
+        println!("usage: texture_cook <material.toml | megascans.json | asset> [--max-size N]");
+        process::exit(1);
+    });
+
+    let material = material_source(&input).load().unwrap_or_else(|err| {
+        println!("{}", err);
+        process::exit(1);
+    });
+
+    match cook_textures(&material, max_size) {
+        Ok(written) => {
+            for (slot, path) in written {
+                println!("{}: {}", slot, path.display());
+            }
+        },
+        Err(err) => {
+            println!("{}", err);
+            process::exit(1);
+        }
+    }
+}
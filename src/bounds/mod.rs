@@ -0,0 +1,412 @@
+use cgmath::{EuclideanSpace, InnerSpace, Matrix4, Point3, Transform, Vector3};
+
+use std::f32;
+
+// Bounding volumes for culling, camera framing and picking
+
+#[derive(Copy, Clone, Debug, PartialEq)]
+pub struct Ray {
+    pub origin: Point3<f32>,
+    pub direction: Vector3<f32>
+}
+
+impl Ray {
+    pub fn new(origin: Point3<f32>, direction: Vector3<f32>) -> Ray {
+        Ray { origin: origin, direction: direction }
+    }
+
+    pub fn at(&self, t: f32) -> Point3<f32> {
+        self.origin + self.direction * t
+    }
+
+    // Distance along the ray to a triangle seen from either side (Moller-Trumbore), None when it
+    // is missed or lies behind the origin
+    pub fn intersect_triangle(&self, a: Point3<f32>, b: Point3<f32>, c: Point3<f32>) -> Option<f32> {
+        let (ab, ac) = (b - a, c - a);
+        let p = self.direction.cross(ac);
+        let det = ab.dot(p);
+        if det.abs() < 1e-12 {
+            return None;
+        }
+
+        let to_origin = self.origin - a;
+        let u = to_origin.dot(p) / det;
+        if u < 0.0 || u > 1.0 {
+            return None;
+        }
+        let q = to_origin.cross(ab);
+        let v = self.direction.dot(q) / det;
+        if v < 0.0 || u + v > 1.0 {
+            return None;
+        }
+
+        let t = ac.dot(q) / det;
+        if t >= 0.0 { Some(t) } else { None }
+    }
+}
+
+// Axis aligned bounding box. The empty box has min above max so that any union replaces it.
+#[derive(Copy, Clone, Debug, PartialEq)]
+pub struct Aabb {
+    pub min: Point3<f32>,
+    pub max: Point3<f32>
+}
+
+impl Aabb {
+    pub fn new(min: Point3<f32>, max: Point3<f32>) -> Aabb {
+        Aabb { min: min, max: max }
+    }
+
+    pub fn empty() -> Aabb {
+        Aabb {
+            min: Point3::new(f32::INFINITY, f32::INFINITY, f32::INFINITY),
+            max: Point3::new(f32::NEG_INFINITY, f32::NEG_INFINITY, f32::NEG_INFINITY)
+        }
+    }
+
+    pub fn from_points<I: IntoIterator<Item = Point3<f32>>>(points: I) -> Aabb {
+        points.into_iter().fold(Aabb::empty(), |aabb, p| aabb.extend(p))
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.min.x > self.max.x || self.min.y > self.max.y || self.min.z > self.max.z
+    }
+
+    pub fn center(&self) -> Point3<f32> {
+        self.min.midpoint(self.max)
+    }
+
+    // Half the size along each axis
+    pub fn extent(&self) -> Vector3<f32> {
+        (self.max - self.min) / 2.0
+    }
+
+    pub fn size(&self) -> Vector3<f32> {
+        self.max - self.min
+    }
+
+    pub fn diagonal(&self) -> f32 {
+        self.size().magnitude()
+    }
+
+    pub fn extend(&self, p: Point3<f32>) -> Aabb {
+        Aabb {
+            min: Point3::new(self.min.x.min(p.x), self.min.y.min(p.y), self.min.z.min(p.z)),
+            max: Point3::new(self.max.x.max(p.x), self.max.y.max(p.y), self.max.z.max(p.z))
+        }
+    }
+
+    pub fn union(&self, other: &Aabb) -> Aabb {
+        Aabb {
+            min: Point3::new(self.min.x.min(other.min.x), self.min.y.min(other.min.y), self.min.z.min(other.min.z)),
+            max: Point3::new(self.max.x.max(other.max.x), self.max.y.max(other.max.y), self.max.z.max(other.max.z))
+        }
+    }
+
+    pub fn contains(&self, p: Point3<f32>) -> bool {
+        p.x >= self.min.x && p.x <= self.max.x &&
+            p.y >= self.min.y && p.y <= self.max.y &&
+            p.z >= self.min.z && p.z <= self.max.z
+    }
+
+    pub fn corners(&self) -> [Point3<f32>; 8] {
+        let (a, b) = (self.min, self.max);
+        [
+            Point3::new(a.x, a.y, a.z), Point3::new(b.x, a.y, a.z),
+            Point3::new(a.x, b.y, a.z), Point3::new(b.x, b.y, a.z),
+            Point3::new(a.x, a.y, b.z), Point3::new(b.x, a.y, b.z),
+            Point3::new(a.x, b.y, b.z), Point3::new(b.x, b.y, b.z)
+        ]
+    }
+
+    // The box around the transformed box, from the transformed center and the absolute
+    // matrix applied to the extent (Arvo), which is exact for affine transforms
+    pub fn transform(&self, m: &Matrix4<f32>) -> Aabb {
+        if self.is_empty() {
+            return *self;
+        }
+
+        let center = m.transform_point(self.center());
+        let e = self.extent();
+        let extent = Vector3::new(
+            m.x.x.abs() * e.x + m.y.x.abs() * e.y + m.z.x.abs() * e.z,
+            m.x.y.abs() * e.x + m.y.y.abs() * e.y + m.z.y.abs() * e.z,
+            m.x.z.abs() * e.x + m.y.z.abs() * e.y + m.z.z.abs() * e.z);
+
+        Aabb { min: center - extent, max: center + extent }
+    }
+
+    // Entry and exit distance along the ray (slab test). The entry is negative when the ray
+    // starts inside, None when the box is missed or lies behind the origin.
+    pub fn intersect_ray(&self, ray: &Ray) -> Option<(f32, f32)> {
+        let mut near = f32::NEG_INFINITY;
+        let mut far = f32::INFINITY;
+
+        for axis in 0..3 {
+            let (origin, direction) = (ray.origin[axis], ray.direction[axis]);
+            let (min, max) = (self.min[axis], self.max[axis]);
+
+            if direction == 0.0 {
+                if origin < min || origin > max {
+                    return None;
+                }
+                continue;
+            }
+
+            let (t0, t1) = ((min - origin) / direction, (max - origin) / direction);
+            near = near.max(t0.min(t1));
+            far = far.min(t0.max(t1));
+        }
+
+        if near <= far && far >= 0.0 {
+            Some((near, far))
+        } else {
+            None
+        }
+    }
+
+    // The sphere through the corners, looser than Sphere::from_points but free to compute
+    pub fn bounding_sphere(&self) -> Sphere {
+        Sphere { center: self.center(), radius: self.extent().magnitude() }
+    }
+}
+
+#[derive(Copy, Clone, Debug, PartialEq)]
+pub struct Sphere {
+    pub center: Point3<f32>,
+    pub radius: f32
+}
+
+impl Sphere {
+    pub fn new(center: Point3<f32>, radius: f32) -> Sphere {
+        Sphere { center: center, radius: radius }
+    }
+
+    // Ritter's bounding sphere: start from two far apart points and grow the sphere over every
+    // point left outside. Within a few percent of the minimal sphere in practice. Points with NaN
+    // or infinite coordinates are skipped, like they are left out of a box.
+    pub fn from_points(points: &[Point3<f32>]) -> Sphere {
+        let points: Vec<Point3<f32>> = points.iter().cloned()
+            .filter(|p| p.x.is_finite() && p.y.is_finite() && p.z.is_finite())
+            .collect();
+        if points.is_empty() {
+            return Sphere::new(Point3::origin(), 0.0);
+        }
+
+        let farthest = |from: Point3<f32>| *points.iter()
+            .max_by(|a, b| (**a - from).magnitude2().partial_cmp(&(**b - from).magnitude2()).unwrap())
+            .unwrap();
+        let a = farthest(points[0]);
+        let b = farthest(a);
+
+        let mut sphere = Sphere::new(a.midpoint(b), (b - a).magnitude() / 2.0);
+        for &p in &points {
+            sphere = sphere.extend(p);
+        }
+        sphere
+    }
+
+    // The smallest sphere containing this one and the point
+    pub fn extend(&self, p: Point3<f32>) -> Sphere {
+        let offset = p - self.center;
+        let distance = offset.magnitude();
+        if distance <= self.radius {
+            return *self;
+        }
+
+        let radius = (self.radius + distance) / 2.0;
+        Sphere::new(self.center + offset * ((radius - self.radius) / distance), radius)
+    }
+
+    pub fn union(&self, other: &Sphere) -> Sphere {
+        let offset = other.center - self.center;
+        let distance = offset.magnitude();
+        if distance + other.radius <= self.radius {
+            return *self;
+        }
+        if distance + self.radius <= other.radius {
+            return *other;
+        }
+
+        let radius = (self.radius + distance + other.radius) / 2.0;
+        Sphere::new(self.center + offset * ((radius - self.radius) / distance), radius)
+    }
+
+    pub fn contains(&self, p: Point3<f32>) -> bool {
+        (p - self.center).magnitude2() <= self.radius * self.radius
+    }
+
+    // Scales the radius by the largest axis scale, so the result stays conservative under
+    // non-uniform scaling
+    pub fn transform(&self, m: &Matrix4<f32>) -> Sphere {
+        let scale = m.x.truncate().magnitude().max(m.y.truncate().magnitude()).max(m.z.truncate().magnitude());
+        Sphere::new(m.transform_point(self.center), self.radius * scale)
+    }
+
+    // Entry and exit distance along the ray, see Aabb::intersect_ray
+    pub fn intersect_ray(&self, ray: &Ray) -> Option<(f32, f32)> {
+        let a = ray.direction.magnitude2();
+        if a == 0.0 {
+            return None;
+        }
+
+        let offset = ray.origin - self.center;
+        let b = offset.dot(ray.direction);
+        let c = offset.magnitude2() - self.radius * self.radius;
+        let discriminant = b * b - a * c;
+        if discriminant < 0.0 {
+            return None;
+        }
+
+        let root = discriminant.sqrt();
+        let (near, far) = ((-b - root) / a, (-b + root) / a);
+        if far >= 0.0 {
+            Some((near, far))
+        } else {
+            None
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    use cgmath::{Deg, InnerSpace, Matrix4, Point3, Transform, Vector3};
+
+    fn unit_box() -> Aabb {
+        Aabb::new(Point3::new(-1.0, -1.0, -1.0), Point3::new(1.0, 1.0, 1.0))
+    }
+
+    fn close(a: f32, b: f32) -> bool {
+        (a - b).abs() < 1e-5
+    }
+
+    fn close_point(a: Point3<f32>, b: Point3<f32>) -> bool {
+        close(a.x, b.x) && close(a.y, b.y) && close(a.z, b.z)
+    }
+
+    #[test]
+    fn center_extent_and_size() {
+        let aabb = Aabb::new(Point3::new(1.0, 2.0, 3.0), Point3::new(3.0, 6.0, 11.0));
+        assert_eq!(aabb.center(), Point3::new(2.0, 4.0, 7.0));
+        assert_eq!(aabb.extent(), Vector3::new(1.0, 2.0, 4.0));
+        assert_eq!(aabb.size(), Vector3::new(2.0, 4.0, 8.0));
+        assert!(close(aabb.diagonal(), (4.0f32 + 16.0 + 64.0).sqrt()));
+    }
+
+    #[test]
+    fn empty_box_is_the_identity_of_union() {
+        let empty = Aabb::empty();
+        assert!(empty.is_empty());
+        assert!(!unit_box().is_empty());
+        assert_eq!(empty.union(&unit_box()), unit_box());
+        assert_eq!(unit_box().union(&empty), unit_box());
+        assert_eq!(Aabb::from_points(Vec::<Point3<f32>>::new()), empty);
+        assert_eq!(empty.transform(&Matrix4::from_scale(2.0)), empty);
+    }
+
+    #[test]
+    fn union_and_points() {
+        let a = Aabb::new(Point3::new(0.0, 0.0, 0.0), Point3::new(1.0, 1.0, 1.0));
+        let b = Aabb::new(Point3::new(-2.0, 0.5, 0.5), Point3::new(0.5, 3.0, 0.75));
+        let union = a.union(&b);
+        assert_eq!(union, Aabb::new(Point3::new(-2.0, 0.0, 0.0), Point3::new(1.0, 3.0, 1.0)));
+        assert!(union.contains(Point3::new(-1.0, 2.0, 0.5)));
+        assert!(!union.contains(Point3::new(-1.0, 2.0, 1.5)));
+
+        let points = vec![Point3::new(1.0, -1.0, 0.0), Point3::new(-3.0, 2.0, 5.0), Point3::new(0.0, 0.0, -1.0)];
+        assert_eq!(Aabb::from_points(points), Aabb::new(Point3::new(-3.0, -1.0, -1.0), Point3::new(1.0, 2.0, 5.0)));
+    }
+
+    #[test]
+    fn transform_matches_transformed_corners() {
+        let aabb = Aabb::new(Point3::new(-1.0, 0.0, 2.0), Point3::new(3.0, 1.0, 4.0));
+        let m = Matrix4::from_translation(Vector3::new(5.0, -2.0, 1.0)) *
+            Matrix4::from_angle_y(Deg(30.0)) * Matrix4::from_nonuniform_scale(2.0, 1.0, 0.5);
+
+        let expected = Aabb::from_points(aabb.corners().iter().map(|&c| m.transform_point(c)));
+        let transformed = aabb.transform(&m);
+        assert!(close_point(transformed.min, expected.min), "{:?} != {:?}", transformed, expected);
+        assert!(close_point(transformed.max, expected.max), "{:?} != {:?}", transformed, expected);
+    }
+
+    #[test]
+    fn ray_hits_misses_and_starts_inside() {
+        let aabb = unit_box();
+
+        let hit = aabb.intersect_ray(&Ray::new(Point3::new(-5.0, 0.0, 0.0), Vector3::new(1.0, 0.0, 0.0)));
+        assert_eq!(hit, Some((4.0, 6.0)));
+
+        let diagonal = aabb.intersect_ray(&Ray::new(Point3::new(-3.0, -3.0, 0.5), Vector3::new(1.0, 1.0, 0.0))).unwrap();
+        assert!(close(diagonal.0, 2.0) && close(diagonal.1, 4.0));
+
+        // Parallel to a slab and outside of it
+        assert_eq!(aabb.intersect_ray(&Ray::new(Point3::new(-5.0, 2.0, 0.0), Vector3::new(1.0, 0.0, 0.0))), None);
+        // Pointing away
+        assert_eq!(aabb.intersect_ray(&Ray::new(Point3::new(-5.0, 0.0, 0.0), Vector3::new(-1.0, 0.0, 0.0))), None);
+
+        let (near, far) = aabb.intersect_ray(&Ray::new(Point3::new(0.0, 0.0, 0.0), Vector3::new(0.0, 0.0, 2.0))).unwrap();
+        assert!(near < 0.0 && close(far, 0.5));
+    }
+
+    #[test]
+    fn ray_hits_triangles_from_both_sides() {
+        let (a, b, c) = (Point3::new(0.0, 0.0, 0.0), Point3::new(1.0, 0.0, 0.0), Point3::new(0.0, 1.0, 0.0));
+
+        let front = Ray::new(Point3::new(0.25, 0.25, 2.0), Vector3::new(0.0, 0.0, -1.0));
+        assert!(close(front.intersect_triangle(a, b, c).unwrap(), 2.0));
+        let back = Ray::new(Point3::new(0.25, 0.25, -3.0), Vector3::new(0.0, 0.0, 2.0));
+        assert!(close(back.intersect_triangle(a, b, c).unwrap(), 1.5));
+
+        // Outside of the hypotenuse, parallel to the plane and behind the origin
+        assert_eq!(Ray::new(Point3::new(0.75, 0.75, 2.0), Vector3::new(0.0, 0.0, -1.0)).intersect_triangle(a, b, c), None);
+        assert_eq!(Ray::new(Point3::new(0.25, 0.25, 2.0), Vector3::new(1.0, 0.0, 0.0)).intersect_triangle(a, b, c), None);
+        assert_eq!(Ray::new(Point3::new(0.25, 0.25, 2.0), Vector3::new(0.0, 0.0, 1.0)).intersect_triangle(a, b, c), None);
+    }
+
+    #[test]
+    fn ritter_sphere_contains_all_points() {
+        let points: Vec<Point3<f32>> = (0..200).map(|i| {
+            let t = i as f32 * 0.37;
+            Point3::new(3.0 * t.sin(), 1.0 + t.cos() * (0.5 * t).sin(), -2.0 + 0.5 * (1.3 * t).cos())
+        }).collect();
+
+        let sphere = Sphere::from_points(&points);
+        for &p in &points {
+            assert!((p - sphere.center).magnitude() <= sphere.radius * (1.0 + 1e-5), "{:?} outside {:?}", p, sphere);
+        }
+        // Tighter than the sphere around the box
+        assert!(sphere.radius <= Aabb::from_points(points.iter().cloned()).bounding_sphere().radius);
+
+        // Broken points are left out instead of poisoning the sphere
+        let mut broken = points.clone();
+        broken.insert(0, Point3::new(f32::NAN, 0.0, 0.0));
+        broken.push(Point3::new(0.0, f32::INFINITY, 0.0));
+        assert_eq!(Sphere::from_points(&broken), sphere);
+        assert_eq!(Sphere::from_points(&[Point3::new(f32::NAN, f32::NAN, f32::NAN)]).radius, 0.0);
+    }
+
+    #[test]
+    fn ritter_sphere_of_a_segment_is_minimal() {
+        let sphere = Sphere::from_points(&[Point3::new(0.0, 0.0, 0.0), Point3::new(4.0, 0.0, 0.0), Point3::new(2.0, 1.0, 0.0)]);
+        assert!(close_point(sphere.center, Point3::new(2.0, 0.0, 0.0)));
+        assert!(close(sphere.radius, 2.0));
+    }
+
+    #[test]
+    fn sphere_union_and_ray() {
+        let a = Sphere::new(Point3::new(0.0, 0.0, 0.0), 1.0);
+        let b = Sphere::new(Point3::new(4.0, 0.0, 0.0), 1.0);
+        let union = a.union(&b);
+        assert!(close_point(union.center, Point3::new(2.0, 0.0, 0.0)) && close(union.radius, 3.0));
+        assert_eq!(union.union(&a), union);
+
+        let (near, far) = a.intersect_ray(&Ray::new(Point3::new(0.0, 0.0, -5.0), Vector3::new(0.0, 0.0, 1.0))).unwrap();
+        assert!(close(near, 4.0) && close(far, 6.0));
+        assert_eq!(a.intersect_ray(&Ray::new(Point3::new(0.0, 2.0, -5.0), Vector3::new(0.0, 0.0, 1.0))), None);
+
+        let transformed = a.transform(&(Matrix4::from_translation(Vector3::new(1.0, 2.0, 3.0)) * Matrix4::from_nonuniform_scale(1.0, 3.0, 2.0)));
+        assert!(close_point(transformed.center, Point3::new(1.0, 2.0, 3.0)) && close(transformed.radius, 3.0));
+    }
+}
@@ -2,6 +2,14 @@ use cgmath;
 use cgmath::prelude::*;
 use cgmath::{Basis3, Matrix3, Matrix4, Rad, Vector2, Vector3, Quaternion};
 
+use bounds::Aabb;
+
+// Fitted clip planes keep this fraction of the depth range as slack around the bounds
+const DEPTH_MARGIN: f32 = 0.01;
+// The near plane never gets closer than this fraction of the far plane, which reverse-Z float
+// depth still resolves well
+const MIN_NEAR_RATIO: f32 = 1e-5;
+
 pub fn model_view_projection<T: cgmath::BaseFloat>(
     model: Matrix4<T>,
     view: Matrix4<T>,
@@ -73,20 +81,167 @@ impl<T: cgmath::BaseFloat + Copy> Camera<T> {
     }
 }
 
+// All projections map a right-handed view space looking down -Z to Vulkan clip space, where
+// Y points down and depth runs from 0 to 1
 impl<T: cgmath::BaseFloat + Copy> CameraPerspective<T> {
+    fn focal_length(&self) -> T {
+        let pi: T = Rad::turn_div_2().0;
+        let _360 = T::from(360.0f64).unwrap();
+        T::one() / (self.fov * (pi / _360)).tan()
+    }
+
+    // Depth 0 on the near plane and 1 on the far plane
     pub fn projection(&self) -> Matrix4<T> {
         let _0 = T::zero();
         let _1 = T::one();
-        let _2 = _1 + _1;
-        let pi: T = Rad::turn_div_2().0;
-        let _360 = T::from(360.0f64).unwrap();
-        let f = _1 / (self.fov * (pi / _360)).tan();
+        let f = self.focal_length();
+        let (far, near) = (self.far_clip, self.near_clip);
+        Matrix4::from([
+            [f / self.aspect_ratio, _0, _0, _0],
+            [_0, -f, _0, _0],
+            [_0, _0, far / (near - far), -_1],
+            [_0, _0, far * near / (near - far), _0]
+        ])
+    }
+
+    // Reverse-Z: depth is 1 on the near plane and 0 on the far plane, which spreads float depth
+    // precision evenly over distance. An infinite far clip gives the limit of the same matrix.
+    pub fn projection_reverse_z(&self) -> Matrix4<T> {
+        let _0 = T::zero();
+        let _1 = T::one();
+        let f = self.focal_length();
         let (far, near) = (self.far_clip, self.near_clip);
+        let (a, b) = if far.is_infinite() {
+            (_0, near)
+        } else {
+            (near / (far - near), far * near / (far - near))
+        };
         Matrix4::from([
             [f / self.aspect_ratio, _0, _0, _0],
-            [_0, f, _0, _0],
-            [_0, _0, (far + near) / (near - far), -_1],
-            [_0, _0, (_2 * far * near) / (near - far), _0]
+            [_0, -f, _0, _0],
+            [_0, _0, a, -_1],
+            [_0, _0, b, _0]
+        ])
+    }
+
+    // Parallel projection showing a view space box of the given height, the field of view is
+    // ignored. Depth 0 on the near plane and 1 on the far plane.
+    pub fn orthographic(&self, height: T) -> Matrix4<T> {
+        let _0 = T::zero();
+        let _1 = T::one();
+        let _2 = _1 + _1;
+        let (far, near) = (self.far_clip, self.near_clip);
+        Matrix4::from([
+            [_2 / (height * self.aspect_ratio), _0, _0, _0],
+            [_0, -_2 / height, _0, _0],
+            [_0, _0, -_1 / (far - near), _0],
+            [_0, _0, -near / (far - near), _1]
+        ])
+    }
+
+    // The orthographic projection with reversed depth, the far clip has to be finite
+    pub fn orthographic_reverse_z(&self, height: T) -> Matrix4<T> {
+        let _0 = T::zero();
+        let _1 = T::one();
+        let _2 = _1 + _1;
+        let (far, near) = (self.far_clip, self.near_clip);
+        Matrix4::from([
+            [_2 / (height * self.aspect_ratio), _0, _0, _0],
+            [_0, -_2 / height, _0, _0],
+            [_0, _0, _1 / (far - near), _0],
+            [_0, _0, far / (far - near), _1]
         ])
     }
-}
\ No newline at end of file
+}
+
+impl CameraPerspective<f32> {
+    // Distance at which a sphere of the given radius fills the narrower of the two fields of view
+    pub fn framing_distance(&self, radius: f32) -> f32 {
+        let fov_y = self.fov.to_radians();
+        let fov_x = 2.0 * ((fov_y / 2.0).tan() * self.aspect_ratio).atan();
+        radius / (fov_y.min(fov_x) / 2.0).sin()
+    }
+
+    // Fits near and far around the bounds as seen through the view matrix. For perspective views
+    // near is kept off zero so the camera can move into the bounds, and the planes stay as they are
+    // while the bounds are behind the camera. Orthographic views may put the near plane behind the
+    // camera instead.
+    pub fn fit_clip_planes(&mut self, bounds: &Aabb, view: &Matrix4<f32>, orthographic: bool) {
+        let view_bounds = bounds.transform(view);
+        let (near, far) = (-view_bounds.max.z, -view_bounds.min.z);
+        if view_bounds.is_empty() {
+            return;
+        }
+
+        if orthographic {
+            // Flat bounds facing the camera still need some depth range
+            let margin = ((far - near) * DEPTH_MARGIN).max(MIN_NEAR_RATIO * far.abs()).max(1e-6);
+            self.near_clip = near - margin;
+            self.far_clip = far + margin;
+        } else if far > 0.0 {
+            self.far_clip = far * (1.0 + DEPTH_MARGIN);
+            self.near_clip = (near * (1.0 - DEPTH_MARGIN)).max(self.far_clip * MIN_NEAR_RATIO);
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    use cgmath::{Matrix4, Point3, Vector4};
+
+    use std::f32;
+
+    // A view space point in normalized device coordinates
+    fn ndc(m: &Matrix4<f32>, p: Point3<f32>) -> Point3<f32> {
+        let clip = *m * Vector4::new(p.x, p.y, p.z, 1.0);
+        Point3::new(clip.x / clip.w, clip.y / clip.w, clip.z / clip.w)
+    }
+
+    fn assert_close(a: Point3<f32>, b: Point3<f32>) {
+        assert!((a.x - b.x).abs() < 1e-5 && (a.y - b.y).abs() < 1e-5 && (a.z - b.z).abs() < 1e-5, "{:?} != {:?}", a, b);
+    }
+
+    // 90 degrees vertically, so the frustum is as high as it is far away and twice as wide
+    fn perspective() -> CameraPerspective<f32> {
+        CameraPerspective { fov: 90.0, near_clip: 1.0, far_clip: 10.0, aspect_ratio: 2.0 }
+    }
+
+    #[test]
+    fn perspective_corners() {
+        let m = perspective().projection();
+        // Top right on the near plane, top is -1 in Vulkan
+        assert_close(ndc(&m, Point3::new(2.0, 1.0, -1.0)), Point3::new(1.0, -1.0, 0.0));
+        assert_close(ndc(&m, Point3::new(-2.0, -1.0, -1.0)), Point3::new(-1.0, 1.0, 0.0));
+        // Bottom left on the far plane
+        assert_close(ndc(&m, Point3::new(-20.0, -10.0, -10.0)), Point3::new(-1.0, 1.0, 1.0));
+        assert_close(ndc(&m, Point3::new(20.0, 10.0, -10.0)), Point3::new(1.0, -1.0, 1.0));
+        assert_close(ndc(&m, Point3::new(0.0, 0.0, -1.0)), Point3::new(0.0, 0.0, 0.0));
+    }
+
+    #[test]
+    fn reverse_z_corners() {
+        let m = perspective().projection_reverse_z();
+        assert_close(ndc(&m, Point3::new(2.0, 1.0, -1.0)), Point3::new(1.0, -1.0, 1.0));
+        assert_close(ndc(&m, Point3::new(-20.0, -10.0, -10.0)), Point3::new(-1.0, 1.0, 0.0));
+
+        let infinite = CameraPerspective { far_clip: f32::INFINITY, ..perspective() }.projection_reverse_z();
+        assert_close(ndc(&infinite, Point3::new(2.0, 1.0, -1.0)), Point3::new(1.0, -1.0, 1.0));
+        let far = ndc(&infinite, Point3::new(-2.0e6, -1.0e6, -1.0e6));
+        assert_close(Point3::new(far.x, far.y, 0.0), Point3::new(-1.0, 1.0, 0.0));
+        assert!(far.z > 0.0 && far.z < 1e-5);
+    }
+
+    #[test]
+    fn orthographic_corners() {
+        let camera = perspective();
+        let m = camera.orthographic(4.0);
+        assert_close(ndc(&m, Point3::new(4.0, 2.0, -1.0)), Point3::new(1.0, -1.0, 0.0));
+        assert_close(ndc(&m, Point3::new(-4.0, -2.0, -10.0)), Point3::new(-1.0, 1.0, 1.0));
+
+        let m = camera.orthographic_reverse_z(4.0);
+        assert_close(ndc(&m, Point3::new(4.0, 2.0, -1.0)), Point3::new(1.0, -1.0, 1.0));
+        assert_close(ndc(&m, Point3::new(-4.0, -2.0, -10.0)), Point3::new(-1.0, 1.0, 0.0));
+    }
+}
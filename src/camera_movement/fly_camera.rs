@@ -0,0 +1,292 @@
+use cgmath;
+use cgmath::prelude::*;
+use cgmath::{Rad, Vector2, Vector3};
+
+use camera_movement::camera::Camera;
+
+pub struct FlyCameraSettings<T: cgmath::BaseFloat> {
+    // Units per second
+    pub move_speed: T,
+    // Radians per pixel of mouse movement
+    pub look_speed: T,
+    pub fast_multiplier: T,
+    pub slow_multiplier: T
+}
+
+impl<T: cgmath::BaseFloat> FlyCameraSettings<T> {
+    pub fn default() -> FlyCameraSettings<T> {
+        FlyCameraSettings {
+            move_speed: T::one(),
+            look_speed: T::from(0.005f32).unwrap(),
+            fast_multiplier: T::from(4.0f32).unwrap(),
+            slow_multiplier: T::from(0.25f32).unwrap()
+        }
+    }
+
+    pub fn move_speed(self, s: T) -> FlyCameraSettings<T> {
+        FlyCameraSettings {
+            move_speed: s,
+            .. self
+        }
+    }
+
+    pub fn look_speed(self, s: T) -> FlyCameraSettings<T> {
+        FlyCameraSettings {
+            look_speed: s,
+            .. self
+        }
+    }
+
+    pub fn fast_multiplier(self, m: T) -> FlyCameraSettings<T> {
+        FlyCameraSettings {
+            fast_multiplier: m,
+            .. self
+        }
+    }
+
+    pub fn slow_multiplier(self, m: T) -> FlyCameraSettings<T> {
+        FlyCameraSettings {
+            slow_multiplier: m,
+            .. self
+        }
+    }
+}
+
+#[derive(Copy, Clone, Debug, PartialEq)]
+pub enum Movement {
+    Forward,
+    Back,
+    Left,
+    Right,
+    Up,
+    Down
+}
+
+const MOVEMENTS: usize = 6;
+
+pub struct FlyCamera<T: cgmath::BaseFloat> {
+    prev_mouse: Vector2<T>,
+    position: Vector3<T>,
+    yaw: T,
+    pitch: T,
+    settings: FlyCameraSettings<T>,
+    looking: bool,
+    // Held movement keys, indexed by Movement
+    moving: [bool; MOVEMENTS],
+    fast: bool,
+    slow: bool
+}
+
+impl<T: cgmath::BaseFloat> FlyCamera<T> {
+    pub fn new(settings: FlyCameraSettings<T>) -> FlyCamera<T> {
+        FlyCamera {
+            prev_mouse: Vector2::zero(),
+            position: Vector3::zero(),
+            yaw: T::zero(),
+            pitch: T::zero(),
+            settings: settings,
+            looking: false,
+            moving: [false; MOVEMENTS],
+            fast: false,
+            slow: false
+        }
+    }
+
+    pub fn camera(&self) -> Camera<T> {
+        let mut camera = Camera::new(self.position);
+        camera.set_yaw_pitch(self.yaw, self.pitch);
+        camera
+    }
+
+    // Takes over the position and viewing direction of another camera, roll is dropped
+    pub fn look_from(&mut self, camera: &Camera<T>) -> &mut Self {
+        let forward = camera.forward.normalize();
+        self.position = camera.position;
+        self.yaw = forward.x.atan2(forward.z);
+        self.pitch = forward.y.max(-T::one()).min(T::one()).asin();
+        self
+    }
+
+    pub fn set_position(&mut self, position: Vector3<T>) -> &mut Self {
+        self.position = position;
+        self
+    }
+
+    pub fn set_move_speed(&mut self, speed: T) -> &mut Self {
+        self.settings.move_speed = speed;
+        self
+    }
+
+    pub fn set_look_speed(&mut self, speed: T) -> &mut Self {
+        self.settings.look_speed = speed;
+        self
+    }
+
+    // Drops all held movement and speed keys, e.g. ones released while another camera was active
+    pub fn stop(&mut self) -> &mut Self {
+        self.moving = [false; MOVEMENTS];
+        self.fast = false;
+        self.slow = false;
+        self
+    }
+
+    pub fn set_movement(&mut self, movement: Movement, active: bool) {
+        self.moving[movement as usize] = active;
+    }
+
+    pub fn set_fast(&mut self, fast: bool) {
+        self.fast = fast;
+    }
+
+    pub fn set_slow(&mut self, slow: bool) {
+        self.slow = slow;
+    }
+
+    pub fn look_start(&mut self, pos: Vector2<T>) {
+        self.looking = true;
+        self.prev_mouse = pos;
+    }
+
+    pub fn look_end(&mut self) {
+        self.looking = false;
+    }
+
+    pub fn update(&mut self, cur_mouse: Vector2<T>) {
+        if self.looking {
+            let delta = (cur_mouse - self.prev_mouse) * self.settings.look_speed;
+            // Stop short of straight up and down, where yaw loses its meaning
+            let limit = Rad::<T>::turn_div_4().0 * T::from(0.99f32).unwrap();
+
+            self.yaw = self.yaw - delta.x;
+            self.pitch = (self.pitch + delta.y).max(-limit).min(limit);
+            self.prev_mouse = cur_mouse;
+        }
+    }
+
+    // Moves along the held directions, dt is the frame time in seconds
+    pub fn advance(&mut self, dt: T) {
+        let camera = self.camera();
+        let moving = self.moving;
+        let axis = |movement: Movement, direction: Vector3<T>| {
+            if moving[movement as usize] { direction } else { Vector3::zero() }
+        };
+
+        // The camera looks down its negative forward axis, up and down follow the world
+        let direction = axis(Movement::Forward, -camera.forward) + axis(Movement::Back, camera.forward) +
+            axis(Movement::Right, camera.right) + axis(Movement::Left, -camera.right) +
+            axis(Movement::Up, Vector3::unit_y()) + axis(Movement::Down, -Vector3::unit_y());
+        if direction.magnitude2() == T::zero() {
+            return;
+        }
+
+        let mut speed = self.settings.move_speed;
+        if self.fast {
+            speed = speed * self.settings.fast_multiplier;
+        }
+        if self.slow {
+            speed = speed * self.settings.slow_multiplier;
+        }
+        self.position += direction.normalize_to(speed * dt);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    use std::f32;
+
+    fn assert_close(a: Vector3<f32>, b: Vector3<f32>) {
+        assert!((a - b).magnitude() < 1e-5, "{:?} != {:?}", a, b);
+    }
+
+    fn fly() -> FlyCamera<f32> {
+        FlyCamera::new(FlyCameraSettings::default().move_speed(2.0))
+    }
+
+    #[test]
+    fn look_from_round_trips_position_and_direction() {
+        let mut original = fly();
+        original.set_position(Vector3::new(1.0, 2.0, 3.0));
+        original.look_start(Vector2::new(0.0, 0.0));
+        original.update(Vector2::new(-140.0, -60.0));
+        let camera = original.camera();
+
+        let mut copy = fly();
+        copy.look_from(&camera);
+        let round_trip = copy.camera();
+        assert_close(round_trip.position, camera.position);
+        assert_close(round_trip.forward, camera.forward);
+        assert_close(round_trip.up, camera.up);
+        assert!((copy.yaw - 0.7).abs() < 1e-5 && (copy.pitch + 0.3).abs() < 1e-5);
+
+        // A rolled camera keeps its direction, the horizon is leveled
+        let mut rolled = Camera::new(Vector3::zero());
+        rolled.forward = Vector3::new(0.0, 0.0, 1.0);
+        rolled.up = Vector3::new(1.0, 1.0, 0.0).normalize();
+        rolled.update_right();
+        let level = fly().look_from(&rolled).camera();
+        assert_close(level.forward, rolled.forward);
+        assert_close(level.up, Vector3::unit_y());
+    }
+
+    #[test]
+    fn movement_scales_with_frame_time() {
+        let moved = |steps: usize, dt: f32| {
+            let mut camera = fly();
+            camera.set_movement(Movement::Forward, true);
+            for _ in 0..steps {
+                camera.advance(dt);
+            }
+            camera.position
+        };
+
+        // The camera looks down -z, two units per second
+        assert_close(moved(1, 0.5), Vector3::new(0.0, 0.0, -1.0));
+        assert_close(moved(8, 0.0625), moved(1, 0.5));
+        assert_close(moved(60, 1.0 / 60.0), Vector3::new(0.0, 0.0, -2.0));
+
+        // Diagonals are not faster, Shift and Ctrl scale the speed
+        let mut camera = fly();
+        camera.set_movement(Movement::Forward, true);
+        camera.set_movement(Movement::Right, true);
+        camera.advance(1.0);
+        assert!((camera.position.magnitude() - 2.0).abs() < 1e-5);
+
+        camera.set_position(Vector3::zero()).stop();
+        camera.set_movement(Movement::Up, true);
+        camera.set_fast(true);
+        camera.advance(0.5);
+        assert_close(camera.position, Vector3::new(0.0, 4.0, 0.0));
+        camera.set_fast(false);
+        camera.set_slow(true);
+        camera.advance(2.0);
+        assert_close(camera.position, Vector3::new(0.0, 5.0, 0.0));
+
+        // Opposite keys cancel out, stopping drops everything held
+        camera.set_movement(Movement::Down, true);
+        camera.advance(1.0);
+        camera.set_movement(Movement::Left, true);
+        camera.stop().advance(1.0);
+        assert_close(camera.position, Vector3::new(0.0, 5.0, 0.0));
+    }
+
+    #[test]
+    fn pitch_stops_short_of_straight_up_and_down() {
+        let limit = f32::consts::FRAC_PI_2 * 0.99;
+        let mut camera = fly();
+        camera.look_start(Vector2::new(0.0, 0.0));
+        camera.update(Vector2::new(100.0, 10000.0));
+        assert!((camera.pitch - limit).abs() < 1e-6);
+        assert!((camera.yaw + 0.5).abs() < 1e-6);
+        assert!((camera.camera().forward.y - limit.sin()).abs() < 1e-6);
+
+        camera.update(Vector2::new(100.0, -20000.0));
+        assert!((camera.pitch + limit).abs() < 1e-6);
+
+        // Without a button held the mouse does not look around
+        camera.look_end();
+        camera.update(Vector2::new(0.0, 0.0));
+        assert!((camera.pitch + limit).abs() < 1e-6 && (camera.yaw + 0.5).abs() < 1e-6);
+    }
+}
@@ -1,2 +1,6 @@
 pub mod camera;
-pub mod orbit_camera;
\ No newline at end of file
+pub mod fly_camera;
+pub mod orbit_camera;
+pub mod path;
+pub mod physical;
+pub mod view;
\ No newline at end of file
@@ -1,23 +1,72 @@
 use cgmath;
 use cgmath::prelude::*;
-use cgmath::{Basis3, Matrix3, Matrix4, Rad, Vector2, Vector3, Quaternion};
+use cgmath::{Basis3, Matrix3, Matrix4, Point3, Rad, Vector2, Vector3, Quaternion};
 
-use camera_movement::camera::Camera;
+use bounds::{Ray, Sphere};
+use camera_movement::camera::{Camera, CameraPerspective};
+use camera_movement::view::CameraView;
+
+// How quickly the tracked drag velocity follows the mouse, per second. Averages over a few frames
+// so a frame without mouse events right before releasing does not cancel the inertia.
+const VELOCITY_SMOOTHING: f32 = 30.0;
+
+#[derive(Copy, Clone, Debug, PartialEq)]
+pub enum RotationMode {
+    // Yaw around the world up axis and pitch, the horizon stays level
+    Turntable,
+    // Shoemake's arcball, drags roll the view around a virtual sphere and can tumble freely
+    Arcball
+}
 
 pub struct OrbitZoomCameraSettings<T: cgmath::BaseFloat> {
+    // Radians per pixel of mouse movement
     pub orbit_speed: T,
+    // Turntable pitch relative to the orbit speed
     pub pitch_speed: T,
+    // Distance scales by exp(zoom_speed) per pixel
     pub zoom_speed: T,
-    pub pan_speed: T
+    // Fraction of the distance to the target per pixel
+    pub pan_speed: T,
+    // Distance scales by exp(wheel_zoom_speed) per wheel line
+    pub wheel_zoom_speed: T,
+    // Zoom the wheel towards the point under the cursor instead of the target
+    pub zoom_to_cursor: bool,
+    // Rate in 1/s at which motion coasts to a stop after releasing the mouse, larger stops sooner
+    pub damping: T,
+    // Seconds taken by framing and snapping to a view
+    pub transition_time: T,
+    pub rotation_mode: RotationMode,
+    // Largest turntable pitch in radians either way, None lets the view go over the top
+    pub pitch_limit: Option<T>
 }
 
 impl<T: cgmath::BaseFloat> OrbitZoomCameraSettings<T> {
     pub fn default() -> OrbitZoomCameraSettings<T> {
         OrbitZoomCameraSettings {
-            orbit_speed: T::from(0.05f32).unwrap(),
-            pitch_speed: T::from(0.1f32).unwrap(),
-            pan_speed: T::from(0.01f32).unwrap(),
+            orbit_speed: T::from(0.01f32).unwrap(),
+            pitch_speed: T::one(),
+            pan_speed: T::from(0.002f32).unwrap(),
             zoom_speed: T::from(0.01f32).unwrap(),
+            wheel_zoom_speed: T::from(0.15f32).unwrap(),
+            zoom_to_cursor: false,
+            damping: T::from(6.0f32).unwrap(),
+            transition_time: T::from(0.3f32).unwrap(),
+            rotation_mode: RotationMode::Turntable,
+            pitch_limit: None
+        }
+    }
+
+    pub fn rotation_mode(self, mode: RotationMode) -> OrbitZoomCameraSettings<T> {
+        OrbitZoomCameraSettings {
+            rotation_mode: mode,
+            .. self
+        }
+    }
+
+    pub fn pitch_limit(self, limit: Option<T>) -> OrbitZoomCameraSettings<T> {
+        OrbitZoomCameraSettings {
+            pitch_limit: limit,
+            .. self
         }
     }
 
@@ -48,6 +97,136 @@ impl<T: cgmath::BaseFloat> OrbitZoomCameraSettings<T> {
             .. self
         }
     }
+
+    pub fn wheel_zoom_speed(self, s: T) -> OrbitZoomCameraSettings<T> {
+        OrbitZoomCameraSettings {
+            wheel_zoom_speed: s,
+            .. self
+        }
+    }
+
+    pub fn zoom_to_cursor(self, enabled: bool) -> OrbitZoomCameraSettings<T> {
+        OrbitZoomCameraSettings {
+            zoom_to_cursor: enabled,
+            .. self
+        }
+    }
+
+    pub fn damping(self, d: T) -> OrbitZoomCameraSettings<T> {
+        OrbitZoomCameraSettings {
+            damping: d,
+            .. self
+        }
+    }
+
+    pub fn transition_time(self, t: T) -> OrbitZoomCameraSettings<T> {
+        OrbitZoomCameraSettings {
+            transition_time: t,
+            .. self
+        }
+    }
+}
+
+// An amount of camera movement, per event or per second
+#[derive(Copy, Clone, Debug)]
+struct Motion<T: cgmath::BaseFloat> {
+    // Turntable yaw and pitch
+    turn: Vector2<T>,
+    // Arcball rotation of the camera as axis times angle, in view space
+    tumble: Vector3<T>,
+    // Target movement along the view right and up axes, in multiples of the distance
+    pan: Vector2<T>,
+    // Natural logarithm of the distance scale
+    zoom: T
+}
+
+impl<T: cgmath::BaseFloat> Motion<T> {
+    fn zero() -> Motion<T> {
+        Motion {
+            turn: Vector2::zero(),
+            tumble: Vector3::zero(),
+            pan: Vector2::zero(),
+            zoom: T::zero()
+        }
+    }
+
+    fn scale(&self, s: T) -> Motion<T> {
+        Motion {
+            turn: self.turn * s,
+            tumble: self.tumble * s,
+            pan: self.pan * s,
+            zoom: self.zoom * s
+        }
+    }
+
+    fn add(&self, other: &Motion<T>) -> Motion<T> {
+        Motion {
+            turn: self.turn + other.turn,
+            tumble: self.tumble + other.tumble,
+            pan: self.pan + other.pan,
+            zoom: self.zoom + other.zoom
+        }
+    }
+
+    fn is_small(&self) -> bool {
+        let epsilon = T::from(1e-5f32).unwrap();
+        self.turn.magnitude() < epsilon && self.tumble.magnitude() < epsilon &&
+            self.pan.magnitude() < epsilon && self.zoom.abs() < epsilon
+    }
+}
+
+// An animated move of the orbit to a new target, distance and rotation
+#[derive(Copy, Clone)]
+struct Transition<T: cgmath::BaseFloat> {
+    from_target: Vector3<T>,
+    from_distance: T,
+    from_rotation: Quaternion<T>,
+    target: Vector3<T>,
+    distance: T,
+    rotation: Quaternion<T>,
+    // Turntable angles at the end
+    yaw: T,
+    pitch: T,
+    elapsed: T
+}
+
+// The six views along the world axes, named after the side of the model they show
+#[derive(Copy, Clone, Debug, PartialEq)]
+pub enum AxisView {
+    Front,
+    Back,
+    Right,
+    Left,
+    Top,
+    Bottom
+}
+
+impl AxisView {
+    // The same axis seen from the other side
+    pub fn opposite(self) -> AxisView {
+        match self {
+            AxisView::Front => AxisView::Back,
+            AxisView::Back => AxisView::Front,
+            AxisView::Right => AxisView::Left,
+            AxisView::Left => AxisView::Right,
+            AxisView::Top => AxisView::Bottom,
+            AxisView::Bottom => AxisView::Top
+        }
+    }
+
+    // Orbit angles in radians, front looks down -Z and top has -Z pointing up on screen
+    fn yaw_pitch<T: cgmath::BaseFloat>(self) -> (T, T) {
+        let quarter: T = Rad::turn_div_4().0;
+        let half: T = Rad::turn_div_2().0;
+        match self {
+            AxisView::Front => (T::zero(), T::zero()),
+            AxisView::Back => (half, T::zero()),
+            AxisView::Right => (quarter, T::zero()),
+            AxisView::Left => (-quarter, T::zero()),
+            AxisView::Top => (T::zero(), -quarter),
+            AxisView::Bottom => (T::zero(), quarter)
+        }
+    }
 }
 
 pub struct OrbitCamera<T: cgmath::BaseFloat> {
@@ -57,10 +236,18 @@ pub struct OrbitCamera<T: cgmath::BaseFloat> {
     pitch: T,
     yaw: T,
     distance: T,
+    // Window size in pixels, the arcball fills the shorter side
+    viewport: Vector2<T>,
     settings: OrbitZoomCameraSettings<T>,
     rotating: bool,
     panning: bool,
-    zooming: bool
+    zooming: bool,
+    orthographic: bool,
+    // Motion applied by mouse events since the last advance
+    moved: Motion<T>,
+    // Per second, tracked while dragging and coasting after release
+    velocity: Motion<T>,
+    transition: Option<Transition<T>>
 }
 
 impl<T: cgmath::BaseFloat> OrbitCamera<T> {
@@ -72,10 +259,15 @@ impl<T: cgmath::BaseFloat> OrbitCamera<T> {
             pitch: T::zero(),
             yaw: T::zero(),
             distance: T::zero(),
+            viewport: Vector2::new(T::one(), T::one()),
             settings: settings,
             rotating: false,
             panning: false,
-            zooming: false
+            zooming: false,
+            orthographic: false,
+            moved: Motion::zero(),
+            velocity: Motion::zero(),
+            transition: None
         }
     }
 
@@ -102,11 +294,161 @@ impl<T: cgmath::BaseFloat> OrbitCamera<T> {
         self
     }
 
+    pub fn distance(&self) -> T {
+        self.distance
+    }
+
+    // Orbits around the point the other camera looks at, at the current distance, so the view
+    // stays the same apart from roll
+    pub fn look_from(&mut self, camera: &Camera<T>) -> &mut Self {
+        self.stop();
+        let forward = camera.forward.normalize();
+        self.target = camera.position - forward * self.distance;
+        self.set_yaw_pitch(forward.x.atan2(forward.z), -forward.y.max(-T::one()).min(T::one()).asin())
+    }
+
+    pub fn set_yaw_pitch(&mut self, yaw: T, pitch: T) -> &mut Self {
+        self.yaw = yaw;
+        self.pitch = pitch;
+        self.rotation = Quaternion::from_axis_angle(Vector3::unit_y(), Rad(yaw)) *
+            Quaternion::from_axis_angle(Vector3::unit_x(), Rad(pitch));
+        self
+    }
+
+    // Turns to look at the target along a world axis with an orthographic projection
+    pub fn snap(&mut self, view: AxisView) -> &mut Self {
+        let (yaw, pitch) = view.yaw_pitch();
+        let rotation = Quaternion::from_axis_angle(Vector3::unit_y(), Rad(yaw)) *
+            Quaternion::from_axis_angle(Vector3::unit_x(), Rad(pitch));
+        let end = self.end_orbit();
+        self.orthographic = true;
+        self.animate_to(Transition { rotation: rotation, yaw: yaw, pitch: pitch, .. end })
+    }
+
+    // Where the camera ends up, a running transition counts as finished
+    fn end_orbit(&self) -> Transition<T> {
+        match self.transition {
+            Some(transition) => transition,
+            None => Transition {
+                from_target: self.target,
+                from_distance: self.distance,
+                from_rotation: self.rotation,
+                target: self.target,
+                distance: self.distance,
+                rotation: self.rotation,
+                yaw: self.yaw,
+                pitch: self.pitch,
+                elapsed: T::zero()
+            }
+        }
+    }
+
+    // Moves from the current orbit to the end of the given transition over the transition time,
+    // along the shorter way around
+    fn animate_to(&mut self, end: Transition<T>) -> &mut Self {
+        let rotation = end.rotation;
+        let from_rotation = if self.rotation.dot(rotation) < T::zero() { -self.rotation } else { self.rotation };
+
+        self.velocity = Motion::zero();
+        self.transition = Some(Transition {
+            from_target: self.target,
+            from_distance: self.distance,
+            from_rotation: from_rotation,
+            elapsed: T::zero(),
+            .. end
+        });
+        if self.settings.transition_time <= T::zero() {
+            self.finish_transition();
+        }
+        self
+    }
+
+    // Jumps to the end of a running transition
+    pub fn finish_transition(&mut self) -> &mut Self {
+        if let Some(transition) = self.transition.take() {
+            self.target = transition.target;
+            self.distance = transition.distance;
+            self.rotation = transition.rotation;
+            self.yaw = transition.yaw;
+            self.pitch = transition.pitch;
+        }
+        self
+    }
+
+    // Cancels transitions and inertia, leaving the camera where it is
+    pub fn stop(&mut self) -> &mut Self {
+        if self.transition.take().is_some() && self.settings.rotation_mode == RotationMode::Turntable {
+            self.sync_yaw_pitch();
+        }
+        self.velocity = Motion::zero();
+        self.moved = Motion::zero();
+        self
+    }
+
+    // Derives the turntable angles from the rotation, dropping roll
+    fn sync_yaw_pitch(&mut self) {
+        let forward = self.rotation.rotate_vector(Vector3::unit_z());
+        let (yaw, pitch) = (forward.x.atan2(forward.z), -forward.y.max(-T::one()).min(T::one()).asin());
+        self.set_yaw_pitch(yaw, pitch);
+    }
+
+    pub fn is_orthographic(&self) -> bool {
+        self.orthographic
+    }
+
+    pub fn set_orthographic(&mut self, orthographic: bool) -> &mut Self {
+        self.orthographic = orthographic;
+        self
+    }
+
+    // Height of the orthographic view volume. The distance acts as the zoom, and the height
+    // matches what the perspective projection shows at the target, so toggling keeps the framing.
+    pub fn ortho_height(&self, perspective: &CameraPerspective<T>) -> T {
+        let _2 = T::one() + T::one();
+        _2 * self.distance * (perspective.fov.to_radians() / _2).tan()
+    }
+
+    pub fn projection(&self, perspective: &CameraPerspective<T>) -> Matrix4<T> {
+        if self.orthographic {
+            perspective.orthographic_reverse_z(self.ortho_height(perspective))
+        } else {
+            perspective.projection_reverse_z()
+        }
+    }
+
     pub fn set_target(&mut self, target: Vector3<T>) -> &mut Self {
         self.target = target;
         self
     }
 
+    pub fn target(&self) -> Vector3<T> {
+        self.target
+    }
+
+    pub fn set_viewport(&mut self, viewport: Vector2<T>) -> &mut Self {
+        self.viewport = viewport;
+        self
+    }
+
+    pub fn rotation_mode(&self) -> RotationMode {
+        self.settings.rotation_mode
+    }
+
+    // Switching to turntable levels the horizon, keeping the viewing direction
+    pub fn set_rotation_mode(&mut self, mode: RotationMode) -> &mut Self {
+        self.stop();
+        self.settings.rotation_mode = mode;
+        if mode == RotationMode::Turntable {
+            self.sync_yaw_pitch();
+        }
+        self
+    }
+
+    pub fn set_pitch_limit(&mut self, limit: Option<T>) -> &mut Self {
+        self.settings.pitch_limit = limit;
+        self
+    }
+
     pub fn set_orbit_speed(&mut self, speed: T) -> &mut Self {
         self.settings.orbit_speed = speed;
         self
@@ -122,7 +464,28 @@ impl<T: cgmath::BaseFloat> OrbitCamera<T> {
         self
     }
 
+    pub fn zoom_to_cursor(&self) -> bool {
+        self.settings.zoom_to_cursor
+    }
+
+    pub fn set_zoom_to_cursor(&mut self, enabled: bool) -> &mut Self {
+        self.settings.zoom_to_cursor = enabled;
+        self
+    }
+
+    pub fn set_damping(&mut self, damping: T) -> &mut Self {
+        self.settings.damping = damping;
+        self
+    }
+
+    pub fn set_transition_time(&mut self, time: T) -> &mut Self {
+        self.settings.transition_time = time;
+        self
+    }
+
+    // Grabbing the camera stops whatever it was doing
     pub fn rotate_start(&mut self, pos: Vector2<T>) {
+        self.stop();
         self.rotating = true;
         self.prev_mouse = pos;
     }
@@ -132,6 +495,7 @@ impl<T: cgmath::BaseFloat> OrbitCamera<T> {
     }
 
     pub fn pan_start(&mut self, pos: Vector2<T>) {
+        self.stop();
         self.panning = true;
         self.prev_mouse = pos;
     }
@@ -141,6 +505,7 @@ impl<T: cgmath::BaseFloat> OrbitCamera<T> {
     }
 
     pub fn zoom_start(&mut self, pos: Vector2<T>) {
+        self.stop();
         self.zooming = true;
         self.prev_mouse = pos;
     }
@@ -149,29 +514,329 @@ impl<T: cgmath::BaseFloat> OrbitCamera<T> {
         self.zooming = false;
     }
 
-    pub fn update(&mut self, cur_mouse: Vector2<T>) {
-        if self.rotating {
-            let mouse_vec = -(cur_mouse - self.prev_mouse).normalize_to(self.settings.pan_speed);
+    // The mouse position on the arcball in view space: a unit hemisphere facing the viewer over
+    // the center of the window, positions outside of it lie on its rim
+    fn arcball_point(&self, mouse: Vector2<T>) -> Vector3<T> {
+        let _2 = T::one() + T::one();
+        let radius = self.viewport.x.min(self.viewport.y) / _2;
+        let x = (mouse.x - self.viewport.x / _2) / radius;
+        let y = (self.viewport.y / _2 - mouse.y) / radius;
 
-            self.yaw = self.yaw + mouse_vec.x;
-            self.pitch = self.pitch + mouse_vec.y * self.settings.pitch_speed;
+        let d = x * x + y * y;
+        if d <= T::one() {
+            Vector3::new(x, y, (T::one() - d).sqrt())
+        } else {
+            let length = d.sqrt();
+            Vector3::new(x / length, y / length, T::zero())
+        }
+    }
 
-            self.rotation = Quaternion::from_axis_angle(Vector3::unit_y(), Rad(self.yaw)) *
-                Quaternion::from_axis_angle(Vector3::unit_x(), Rad(self.pitch));
-            self.prev_mouse = cur_mouse;
-        } else if self.panning {
-            // Note that the direction of target point movement is the reverse of the direction of mouse movement
-            let mouse_vec = -(cur_mouse - self.prev_mouse).normalize_to(self.settings.pan_speed);
+    // Dollies towards the target by a number of wheel lines, positive zooms in
+    pub fn scroll(&mut self, lines: T) {
+        self.stop();
+        self.distance = self.distance * (-lines * self.settings.wheel_zoom_speed).exp();
+    }
+
+    // Dollies towards a point instead of the target. Camera and target both move along their line
+    // to the point, which keeps the point at the same place on screen.
+    pub fn scroll_at(&mut self, lines: T, point: Vector3<T>) {
+        self.stop();
+        let scale = (-lines * self.settings.wheel_zoom_speed).exp();
+        self.target = point + (self.target - point) * scale;
+        self.distance = self.distance * scale;
+    }
 
-            let left_vec = self.rotation.rotate_vector(Vector3::unit_x() * mouse_vec.x);
-            let up_vec = self.rotation.rotate_vector(Vector3::unit_y() * mouse_vec.y);
-            self.target += left_vec + up_vec;
-            self.prev_mouse = cur_mouse;
+    // Applies the mouse movement since the last event to the active drag
+    pub fn update(&mut self, cur_mouse: Vector2<T>) {
+        if !(self.rotating || self.panning || self.zooming) {
+            return;
+        }
+
+        let delta = cur_mouse - self.prev_mouse;
+        let mut motion = Motion::zero();
+        if self.rotating && self.settings.rotation_mode == RotationMode::Arcball {
+            // The quaternion between the two points turns the model by twice their angle, which
+            // makes the rotation depend only on where the drag starts and ends. The camera turns
+            // the opposite way.
+            let from = self.arcball_point(self.prev_mouse);
+            let to = self.arcball_point(cur_mouse);
+            let axis = from.cross(to);
+            let sin = axis.magnitude();
+            if sin > T::zero() {
+                let angle = (T::one() + T::one()) * sin.atan2(from.dot(to));
+                motion.tumble = -axis / sin * angle;
+            }
+        } else if self.rotating {
+            // Dragging right turns the model right, dragging down tilts its top towards the viewer
+            motion.turn = Vector2::new(-delta.x, -delta.y * self.settings.pitch_speed) * self.settings.orbit_speed;
+        } else if self.panning {
+            // The model follows the mouse, so the target moves against it
+            motion.pan = Vector2::new(-delta.x, delta.y) * self.settings.pan_speed;
         } else if self.zooming {
-            let mouse_vec = -(cur_mouse - self.prev_mouse).normalize_to(self.settings.zoom_speed);
-            self.distance = (self.distance + mouse_vec.y).max(T::zero());
-            self.prev_mouse = cur_mouse;
+            // Dragging down zooms in
+            motion.zoom = -delta.y * self.settings.zoom_speed;
+        }
+
+        self.apply(&motion);
+        self.moved = self.moved.add(&motion);
+        self.prev_mouse = cur_mouse;
+    }
+
+    fn apply(&mut self, motion: &Motion<T>) {
+        if motion.turn != Vector2::zero() {
+            let mut pitch = self.pitch + motion.turn.y;
+            if let Some(limit) = self.settings.pitch_limit {
+                pitch = pitch.max(-limit).min(limit);
+            }
+            let yaw = self.yaw + motion.turn.x;
+            self.set_yaw_pitch(yaw, pitch);
+        }
+
+        let angle = motion.tumble.magnitude();
+        if angle > T::zero() {
+            let turn = Quaternion::from_axis_angle(motion.tumble / angle, Rad(angle));
+            self.rotation = (self.rotation * turn).normalize();
+        }
+
+        if motion.pan != Vector2::zero() {
+            let offset = self.rotation.rotate_vector(Vector3::new(motion.pan.x, motion.pan.y, T::zero()));
+            self.target += offset * self.distance;
+        }
+
+        self.distance = self.distance * motion.zoom.exp();
+    }
+
+    // Steps transitions and inertia, dt is the frame time in seconds
+    pub fn advance(&mut self, dt: T) {
+        if dt <= T::zero() {
+            return;
+        }
+
+        if let Some(mut transition) = self.transition.take() {
+            transition.elapsed = transition.elapsed + dt;
+            let t = (transition.elapsed / self.settings.transition_time).min(T::one());
+            if t >= T::one() {
+                self.transition = Some(transition);
+                self.finish_transition();
+            } else {
+                // Smoothstep, starting and ending at rest
+                let _2 = T::one() + T::one();
+                let s = t * t * (_2 + T::one() - _2 * t);
+                self.target = transition.from_target + (transition.target - transition.from_target) * s;
+                // Geometric so zooming in and out runs at an even pace
+                self.distance = if transition.from_distance > T::zero() {
+                    transition.from_distance * (transition.distance / transition.from_distance).powf(s)
+                } else {
+                    transition.distance * s
+                };
+                self.rotation = transition.from_rotation.slerp(transition.rotation, s);
+                self.transition = Some(transition);
+            }
+            return;
+        }
+
+        if self.rotating || self.panning || self.zooming {
+            let follow = T::one() - (-T::from(VELOCITY_SMOOTHING).unwrap() * dt).exp();
+            let measured = self.moved.scale(T::one() / dt);
+            self.velocity = self.velocity.add(&measured.add(&self.velocity.scale(-T::one())).scale(follow));
+            self.moved = Motion::zero();
+        } else if !self.velocity.is_small() && self.settings.damping > T::zero() {
+            // The exact integral of the exponentially decaying velocity over the frame, so the
+            // camera coasts the same total distance at any frame rate
+            let decay = (-self.settings.damping * dt).exp();
+            let step = self.velocity.scale((T::one() - decay) / self.settings.damping);
+            self.apply(&step);
+            self.velocity = self.velocity.scale(decay);
+        } else {
+            self.velocity = Motion::zero();
         }
     }
 }
 
+impl OrbitCamera<f32> {
+    // The view at the end of a running transition, together with the field of view
+    pub fn view(&self, perspective: &CameraPerspective<f32>) -> CameraView {
+        let end = self.end_orbit();
+        let rotation = end.rotation.normalize();
+        CameraView {
+            target: end.target.into(),
+            rotation: [rotation.s, rotation.v.x, rotation.v.y, rotation.v.z],
+            distance: end.distance,
+            fov: perspective.fov,
+            orthographic: self.orthographic
+        }
+    }
+
+    // Jumps to a saved view. The rotation is kept as it is, including roll, until the next
+    // turntable drag.
+    pub fn set_view(&mut self, view: &CameraView, perspective: &mut CameraPerspective<f32>) {
+        self.stop();
+        let r = view.rotation;
+        self.target = view.target.into();
+        self.distance = view.distance.max(0.0);
+        self.rotation = Quaternion::new(r[0], r[1], r[2], r[3]).normalize();
+        self.orthographic = view.orthographic;
+        perspective.fov = view.fov;
+
+        let forward = self.rotation.rotate_vector(Vector3::unit_z());
+        self.yaw = forward.x.atan2(forward.z);
+        self.pitch = -forward.y.max(-1.0).min(1.0).asin();
+    }
+
+    // The ray through a pixel, from the camera for perspective and from the camera plane for
+    // orthographic views. The direction is not normalized.
+    pub fn ray(&self, mouse: Vector2<f32>, perspective: &CameraPerspective<f32>) -> Ray {
+        let camera = self.camera();
+        let x = mouse.x / self.viewport.x * 2.0 - 1.0;
+        let y = 1.0 - mouse.y / self.viewport.y * 2.0;
+
+        if self.orthographic {
+            let half_height = self.ortho_height(perspective) / 2.0;
+            let offset = camera.right * (x * half_height * perspective.aspect_ratio) + camera.up * (y * half_height);
+            Ray::new(Point3::from_vec(camera.position + offset), -camera.forward)
+        } else {
+            let tan = (perspective.fov.to_radians() / 2.0).tan();
+            let direction = -camera.forward + camera.right * (x * tan * perspective.aspect_ratio) + camera.up * (y * tan);
+            Ray::new(Point3::from_vec(camera.position), direction)
+        }
+    }
+
+    // Moves the target to the center of the sphere and backs off until it fills the narrower of
+    // the two fields of view, keeping the current orbit angles. Near and far are left to
+    // CameraPerspective::fit_clip_planes, which runs every frame.
+    pub fn frame(&mut self, sphere: &Sphere, perspective: &CameraPerspective<f32>) {
+        let radius = sphere.radius.max(1e-4);
+        let distance = perspective.framing_distance(radius);
+
+        let end = self.end_orbit();
+        self.animate_to(Transition { target: sphere.center.to_vec(), distance: distance, .. end });
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    use std::f32::consts::PI;
+
+    fn assert_close(a: Vector3<f32>, b: Vector3<f32>) {
+        assert!((a - b).magnitude() < 1e-4, "{:?} != {:?}", a, b);
+    }
+
+    fn orbit(mode: RotationMode) -> OrbitCamera<f32> {
+        let mut camera = OrbitCamera::new(OrbitZoomCameraSettings::default().rotation_mode(mode));
+        camera.set_viewport(Vector2::new(200.0, 200.0)).set_distance(2.0);
+        camera
+    }
+
+    fn drag(camera: &mut OrbitCamera<f32>, from: Vector2<f32>, to: Vector2<f32>) {
+        camera.rotate_start(from);
+        camera.update(to);
+        camera.rotate_end();
+    }
+
+    #[test]
+    fn arcball_drag_from_the_center_to_the_rim_turns_half_way_around() {
+        let mut camera = orbit(RotationMode::Arcball);
+        drag(&mut camera, Vector2::new(100.0, 100.0), Vector2::new(200.0, 100.0));
+
+        // A half turn around the vertical axis puts the camera behind the target
+        assert!(Quaternion::one().dot(camera.rotation).abs() < 1e-5, "{:?}", camera.rotation);
+        assert_close(camera.camera().forward, -Vector3::unit_z());
+        assert_close(camera.camera().up, Vector3::unit_y());
+    }
+
+    #[test]
+    fn pitch_limit_clamps_turntable_drags() {
+        let mut camera = OrbitCamera::new(OrbitZoomCameraSettings::default().pitch_limit(Some(0.5)));
+        drag(&mut camera, Vector2::new(0.0, 0.0), Vector2::new(0.0, 1000.0));
+        assert_eq!(camera.pitch, -0.5);
+        drag(&mut camera, Vector2::new(0.0, 0.0), Vector2::new(0.0, -3000.0));
+        assert_eq!(camera.pitch, 0.5);
+
+        camera.set_pitch_limit(None);
+        drag(&mut camera, Vector2::new(0.0, 0.0), Vector2::new(0.0, -200.0));
+        assert!((camera.pitch - 2.5).abs() < 1e-5);
+    }
+
+    #[test]
+    fn switching_to_turntable_levels_the_roll() {
+        // An eighth of the way around the rim rolls by a quarter turn, then tilt the view
+        let mut camera = orbit(RotationMode::Arcball);
+        let diagonal = 100.0 * (PI / 4.0).cos();
+        drag(&mut camera, Vector2::new(200.0, 100.0), Vector2::new(100.0 + diagonal, 100.0 - diagonal));
+        drag(&mut camera, Vector2::new(100.0, 100.0), Vector2::new(100.0, 130.0));
+        let rolled = camera.camera();
+        assert!(rolled.right.y.abs() > 0.1, "{:?}", rolled.right);
+
+        camera.set_rotation_mode(RotationMode::Turntable);
+        let level = camera.camera();
+        assert!(level.right.y.abs() < 1e-5, "{:?}", level.right);
+        assert_close(level.forward, rolled.forward);
+        assert_close(level.position, rolled.position);
+    }
+
+    // Yaw turned while coasting from a release at one radian per second
+    fn coast(dt: f32) -> f32 {
+        let mut camera = orbit(RotationMode::Turntable);
+        camera.velocity.turn = Vector2::new(1.0, 0.0);
+        let mut elapsed = 0.0;
+        while elapsed < 4.0 {
+            camera.advance(dt);
+            elapsed += dt;
+        }
+        assert!(camera.velocity.is_small());
+        camera.yaw
+    }
+
+    #[test]
+    fn coasting_covers_the_same_distance_at_any_frame_rate() {
+        let (slow, fast) = (coast(1.0 / 30.0), coast(1.0 / 240.0));
+        assert!((slow - fast).abs() < 1e-5, "{} != {}", slow, fast);
+        // The integral of the decaying velocity, 1 / damping
+        assert!((slow - 1.0 / 6.0).abs() < 1e-5, "{}", slow);
+    }
+
+    #[test]
+    fn transition_ends_exactly_at_its_target() {
+        let perspective = CameraPerspective { fov: 60.0, near_clip: 0.1, far_clip: 100.0, aspect_ratio: 1.5 };
+        let sphere = Sphere::new(Point3::new(1.0, 2.0, 3.0), 0.7);
+        let mut camera = orbit(RotationMode::Turntable);
+        camera.set_yaw_pitch(0.3, -0.2);
+        camera.frame(&sphere, &perspective);
+
+        let mut steps = 0;
+        // The last step overshoots the transition time and has to land on the end, not past it
+        while camera.transition.is_some() {
+            camera.advance(1.0 / 7.0);
+            steps += 1;
+        }
+        assert_eq!(steps, 3);
+        assert_eq!(camera.target(), sphere.center.to_vec());
+        assert_eq!(camera.distance(), perspective.framing_distance(0.7));
+        assert_eq!((camera.yaw, camera.pitch), (0.3, -0.2));
+    }
+
+    // Where a world space point ends up in normalized device coordinates
+    fn screen(camera: &OrbitCamera<f32>, perspective: &CameraPerspective<f32>, p: Vector3<f32>) -> Vector2<f32> {
+        let clip = camera.projection(perspective) * camera.camera().orthogonal() * p.extend(1.0);
+        Vector2::new(clip.x / clip.w, clip.y / clip.w)
+    }
+
+    #[test]
+    fn scrolling_at_a_point_keeps_it_in_place_on_screen() {
+        let perspective = CameraPerspective { fov: 60.0, near_clip: 0.1, far_clip: 100.0, aspect_ratio: 2.0 };
+        for &orthographic in &[false, true] {
+            let mut camera = orbit(RotationMode::Turntable);
+            camera.set_viewport(Vector2::new(400.0, 200.0)).set_orthographic(orthographic).set_yaw_pitch(0.4, -0.3);
+            let point = camera.ray(Vector2::new(300.0, 60.0), &perspective).at(1.5).to_vec();
+            let before = screen(&camera, &perspective, point);
+
+            camera.scroll_at(3.0, point);
+            assert!(camera.distance() < 2.0);
+            let after = screen(&camera, &perspective, point);
+            assert!((after - before).magnitude() < 1e-4, "{:?} moved to {:?}", before, after);
+            assert!((before - Vector2::new(0.5, -0.4)).magnitude() < 1e-4, "{:?}", before);
+        }
+    }
+}
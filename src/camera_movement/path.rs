@@ -0,0 +1,436 @@
+use cgmath::prelude::*;
+use cgmath::{Quaternion, Rad, Vector3};
+
+use bounds::Sphere;
+use camera_movement::camera::Camera;
+use camera_movement::view::CameraView;
+
+use std::fs::File;
+use std::io::{Read, Write};
+use std::path::{Path, PathBuf};
+
+// Keyframed camera motion. Positions follow a Catmull-Rom spline through the keyframes, rotations
+// a squad curve, so both move without kinks through every keyframe. Sampling only depends on the
+// time, which makes playback at a fixed frame rate deterministic.
+
+// Turntable keyframes are this far apart, close enough that the spline stays within 0.01% of the
+// circle
+const TURNTABLE_STEP_DEGREES: f32 = 15.0;
+
+#[derive(Copy, Clone, Debug, PartialEq)]
+pub struct Keyframe {
+    // Seconds from the start of the path
+    pub time: f32,
+    // Played back with a perspective projection, the projection mode of the view is ignored
+    pub view: CameraView
+}
+
+impl Keyframe {
+    pub fn new(time: f32, view: CameraView) -> Keyframe {
+        Keyframe { time: time, view: view }
+    }
+
+    fn rotation(&self) -> Quaternion<f32> {
+        let r = self.view.rotation;
+        Quaternion::new(r[0], r[1], r[2], r[3]).normalize()
+    }
+
+    fn position(&self) -> Vector3<f32> {
+        let target: Vector3<f32> = self.view.target.into();
+        target + self.rotation().rotate_vector(Vector3::new(0.0, 0.0, self.view.distance))
+    }
+
+    // Same camera up to float noise, e.g. a turntable after a full turn
+    fn same_view(&self, other: &Keyframe) -> bool {
+        (self.position() - other.position()).magnitude() <= 1e-4 * self.position().magnitude().max(1.0) &&
+            self.rotation().dot(other.rotation()).abs() >= 1.0 - 1e-6 &&
+            (self.view.distance - other.view.distance).abs() <= 1e-4 * self.view.distance.abs().max(1.0) &&
+            (self.view.fov - other.view.fov).abs() <= 1e-4
+    }
+}
+
+// The camera at one point in time
+#[derive(Copy, Clone, Debug)]
+pub struct Pose {
+    pub position: Vector3<f32>,
+    pub rotation: Quaternion<f32>,
+    // Vertical field of view in degrees
+    pub fov: f32,
+    // Orbit distance of the keyframe views, so an orbit camera following the path keeps its target
+    pub distance: f32
+}
+
+impl Pose {
+    pub fn camera(&self) -> Camera<f32> {
+        let mut camera = Camera::new(self.position);
+        camera.set_rotation(self.rotation);
+        camera
+    }
+}
+
+// Logarithm of a unit quaternion: the rotation axis times half the angle
+fn log(q: Quaternion<f32>) -> Vector3<f32> {
+    let sin = q.v.magnitude();
+    if sin < 1e-7 {
+        return Vector3::zero();
+    }
+    q.v * (sin.atan2(q.s) / sin)
+}
+
+fn exp(v: Vector3<f32>) -> Quaternion<f32> {
+    let angle = v.magnitude();
+    if angle < 1e-7 {
+        return Quaternion::from_sv(1.0, v);
+    }
+    Quaternion::from_sv(angle.cos(), v * (angle.sin() / angle))
+}
+
+// Unlike Quaternion::slerp this never flips to the shorter way around, squad needs the curve
+// between its control points as given
+fn slerp(a: Quaternion<f32>, b: Quaternion<f32>, t: f32) -> Quaternion<f32> {
+    let angle = a.dot(b).max(-1.0).min(1.0).acos();
+    let sin = angle.sin();
+    if sin.abs() < 1e-5 {
+        return (a * (1.0 - t) + b * t).normalize();
+    }
+    (a * ((1.0 - t) * angle).sin() + b * (t * angle).sin()) / sin
+}
+
+// b or -b, whichever is closer to a. Both describe the same rotation.
+fn align(a: Quaternion<f32>, b: Quaternion<f32>) -> Quaternion<f32> {
+    if a.dot(b) < 0.0 { -b } else { b }
+}
+
+// Inner control point of squad at q, between its neighbors
+fn squad_control(previous: Quaternion<f32>, q: Quaternion<f32>, next: Quaternion<f32>) -> Quaternion<f32> {
+    let inverse = q.conjugate();
+    q * exp(-(log(inverse * next) + log(inverse * previous)) / 4.0)
+}
+
+fn squad(q1: Quaternion<f32>, q2: Quaternion<f32>, s1: Quaternion<f32>, s2: Quaternion<f32>, u: f32) -> Quaternion<f32> {
+    slerp(slerp(q1, q2, u), slerp(s1, s2, u), 2.0 * u * (1.0 - u))
+}
+
+// Cubic Hermite segment, tangents are per segment
+fn hermite(p1: Vector3<f32>, m1: Vector3<f32>, p2: Vector3<f32>, m2: Vector3<f32>, u: f32) -> Vector3<f32> {
+    let (u2, u3) = (u * u, u * u * u);
+    p1 * (2.0 * u3 - 3.0 * u2 + 1.0) + m1 * (u3 - 2.0 * u2 + u) + p2 * (3.0 * u2 - 2.0 * u3) + m2 * (u3 - u2)
+}
+
+#[derive(Clone)]
+pub struct CameraPath {
+    keyframes: Vec<Keyframe>,
+    // The last keyframe repeats the first one and the motion continues through it, for loops.
+    // Closing a path appends that keyframe when it is missing.
+    closed: bool
+}
+
+impl CameraPath {
+    pub fn new() -> CameraPath {
+        CameraPath {
+            keyframes: Vec::new(),
+            closed: false
+        }
+    }
+
+    // One full turn around the sphere at a fixed pitch, starting from the front and looking at
+    // its center
+    pub fn turntable(sphere: &Sphere, distance: f32, pitch: f32, fov: f32, duration: f32) -> CameraPath {
+        let steps = (360.0 / TURNTABLE_STEP_DEGREES).round() as u32;
+        let mut path = CameraPath::new();
+        path.set_closed(true);
+
+        for step in 0..steps + 1 {
+            let t = step as f32 / steps as f32;
+            let rotation = Quaternion::from_axis_angle(Vector3::unit_y(), Rad::full_turn() * t) *
+                Quaternion::from_axis_angle(Vector3::unit_x(), Rad(pitch));
+            path.add(Keyframe::new(t * duration, CameraView {
+                target: sphere.center.into(),
+                rotation: [rotation.s, rotation.v.x, rotation.v.y, rotation.v.z],
+                distance: distance,
+                fov: fov,
+                orthographic: false
+            }));
+        }
+        path
+    }
+
+    pub fn path_for(model: &Path) -> PathBuf {
+        model.with_extension("path")
+    }
+
+    // One "<time> <view>" line per keyframe, a line "closed" makes the path loop back to its first
+    // keyframe. Files may list that keyframe again at the end, otherwise it follows one average
+    // keyframe spacing after the last.
+    pub fn load(path: &Path) -> Result<CameraPath, String> {
+        let mut source = String::new();
+        File::open(path).and_then(|mut f| f.read_to_string(&mut source))
+            .map_err(|e| format!("could not read {}: {}", path.display(), e))?;
+
+        let mut camera_path = CameraPath::new();
+        for (number, line) in source.lines().enumerate() {
+            let line = line.trim();
+            if line.is_empty() || line.starts_with('#') {
+                continue;
+            }
+            if line == "closed" {
+                camera_path.set_closed(true);
+                continue;
+            }
+
+            let mut split = line.splitn(2, char::is_whitespace);
+            let time = split.next().and_then(|t| t.parse::<f32>().ok())
+                .ok_or_else(|| format!("{}:{}: expected a time and a view", path.display(), number + 1))?;
+            let view = split.next().unwrap_or("").parse::<CameraView>()
+                .map_err(|err| format!("{}:{}: {}", path.display(), number + 1, err))?;
+            camera_path.add(Keyframe::new(time, view));
+        }
+        camera_path.close_loop();
+        Ok(camera_path)
+    }
+
+    pub fn save(&self, path: &Path) -> Result<(), String> {
+        let mut out = String::new();
+        if self.closed {
+            out.push_str("closed\n");
+        }
+        for keyframe in self.keyframes.iter() {
+            out.push_str(&format!("{} {}\n", keyframe.time, keyframe.view));
+        }
+        File::create(path).and_then(|mut f| f.write_all(out.as_bytes()))
+            .map_err(|e| format!("could not write {}: {}", path.display(), e))
+    }
+
+    pub fn set_closed(&mut self, closed: bool) -> &mut Self {
+        self.closed = closed;
+        self.close_loop();
+        self
+    }
+
+    fn has_closing_keyframe(&self) -> bool {
+        self.keyframes.len() >= 2 && self.keyframes[0].same_view(self.keyframes.last().unwrap())
+    }
+
+    // Appends the first keyframe again to a closed path that does not end with it
+    fn close_loop(&mut self) {
+        if !self.closed || self.keyframes.len() < 2 || self.has_closing_keyframe() {
+            return;
+        }
+
+        let (first, last) = (self.keyframes[0], *self.keyframes.last().unwrap());
+        let spacing = (last.time - first.time) / (self.keyframes.len() - 1) as f32;
+        self.keyframes.push(Keyframe::new(last.time + spacing, first.view));
+    }
+
+    // Keeps the keyframes ordered by time, a keyframe at the time of another one replaces it. On a
+    // closed path keyframes after the closing one take its place, and the path closes again after them.
+    pub fn add(&mut self, keyframe: Keyframe) -> &mut Self {
+        let reclose = self.closed && self.has_closing_keyframe() && keyframe.time > self.duration();
+        if reclose {
+            self.keyframes.pop();
+        }
+
+        match self.keyframes.iter().position(|k| k.time >= keyframe.time) {
+            Some(i) if self.keyframes[i].time == keyframe.time => self.keyframes[i] = keyframe,
+            Some(i) => self.keyframes.insert(i, keyframe),
+            None => self.keyframes.push(keyframe)
+        }
+
+        if reclose {
+            self.close_loop();
+        }
+        self
+    }
+
+    pub fn keyframes(&self) -> &[Keyframe] {
+        &self.keyframes
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.keyframes.is_empty()
+    }
+
+    // Time of the last keyframe
+    pub fn duration(&self) -> f32 {
+        self.keyframes.last().map_or(0.0, |k| k.time)
+    }
+
+    // Frames rendered at the given rate. A closed path leaves out its last frame, which is the
+    // first one again.
+    pub fn frame_count(&self, fps: f32) -> u32 {
+        let frames = (self.duration() * fps).round() as u32;
+        if self.closed { frames.max(1) } else { frames + 1 }
+    }
+
+    fn wraps(&self) -> bool {
+        self.closed && self.keyframes.len() >= 3
+    }
+
+    // Keyframe i, indices past either end repeat the end keyframes, or continue into the next or
+    // previous lap of a closed path
+    fn key(&self, i: isize) -> Keyframe {
+        let n = self.keyframes.len() as isize;
+        if !self.wraps() {
+            return self.keyframes[i.max(0).min(n - 1) as usize];
+        }
+
+        let distinct = n - 1;
+        let lap = self.keyframes[distinct as usize].time - self.keyframes[0].time;
+        let wrapped = ((i % distinct) + distinct) % distinct;
+        let mut key = self.keyframes[wrapped as usize];
+        key.time += ((i - wrapped) / distinct) as f32 * lap;
+        key
+    }
+
+    // Velocity at keyframe i from its neighbors
+    fn tangent(&self, i: isize) -> Vector3<f32> {
+        let (previous, next) = (self.key(i - 1), self.key(i + 1));
+        let span = next.time - previous.time;
+        if span > 0.0 { (next.position() - previous.position()) / span } else { Vector3::zero() }
+    }
+
+    // The camera at a time in seconds, clamped to the keyframes
+    pub fn sample(&self, time: f32) -> Pose {
+        assert!(!self.keyframes.is_empty(), "sampling a camera path without keyframes");
+
+        let last = self.keyframes.len() - 1;
+        let i = match self.keyframes.iter().rposition(|k| k.time <= time) {
+            Some(i) => i.min(last.saturating_sub(1)),
+            None => 0
+        } as isize;
+        let (k1, k2) = (self.key(i), self.key(i + 1));
+        let span = k2.time - k1.time;
+        let u = if span > 0.0 { ((time - k1.time) / span).max(0.0).min(1.0) } else { 0.0 };
+
+        let position = hermite(k1.position(), self.tangent(i) * span, k2.position(), self.tangent(i + 1) * span, u);
+
+        // All four rotations on one hemisphere, otherwise squad takes the long way. Past the ends
+        // of an open path the rotation continues at the rate of the end segment, as the position
+        // does with its tangent.
+        let q1 = k1.rotation();
+        let q2 = align(q1, k2.rotation());
+        let q0 = if self.wraps() || i > 0 { align(q1, self.key(i - 1).rotation()) } else { q1 * q2.conjugate() * q1 };
+        let q3 = if self.wraps() || i + 2 <= last as isize { align(q2, self.key(i + 2).rotation()) } else { q2 * q1.conjugate() * q2 };
+        let rotation = squad(q1, q2, squad_control(q0, q1, q2), squad_control(q1, q2, q3), u).normalize();
+
+        Pose {
+            position: position,
+            rotation: rotation,
+            fov: k1.view.fov + (k2.view.fov - k1.view.fov) * u,
+            distance: k1.view.distance + (k2.view.distance - k1.view.distance) * u
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    use cgmath::{Point3, Quaternion, Rad, Vector3};
+
+    use std::env;
+    use std::fs;
+    use std::process;
+
+    fn view(target: [f32; 3], yaw: f32, distance: f32) -> CameraView {
+        let rotation = Quaternion::from_axis_angle(Vector3::unit_y(), Rad(yaw));
+        CameraView {
+            target: target,
+            rotation: [rotation.s, rotation.v.x, rotation.v.y, rotation.v.z],
+            distance: distance,
+            fov: 60.0,
+            orthographic: false
+        }
+    }
+
+    fn close(a: f32, b: f32) -> bool {
+        (a - b).abs() < 1e-4
+    }
+
+    #[test]
+    fn passes_through_the_keyframes() {
+        let mut path = CameraPath::new();
+        path.add(Keyframe::new(0.0, view([0.0, 0.0, 0.0], 0.0, 2.0)))
+            .add(Keyframe::new(3.0, view([1.0, 0.0, 0.0], 1.0, 4.0)))
+            .add(Keyframe::new(1.0, view([0.0, 1.0, 0.0], 0.5, 3.0)));
+
+        for keyframe in path.keyframes().to_vec() {
+            let pose = path.sample(keyframe.time);
+            assert!((pose.position - keyframe.position()).magnitude() < 1e-4, "{:?} at {}", pose, keyframe.time);
+            assert!(close(pose.rotation.dot(keyframe.rotation()).abs(), 1.0));
+            assert!(close(pose.distance, keyframe.view.distance));
+        }
+        assert!(close(path.sample(2.0).distance, 3.5));
+
+        // Clamped outside of the keyframes
+        assert!((path.sample(-1.0).position - path.keyframes()[0].position()).magnitude() < 1e-4);
+        assert!((path.sample(10.0).position - path.keyframes()[2].position()).magnitude() < 1e-4);
+    }
+
+    #[test]
+    fn turntable_circles_the_sphere_looking_at_its_center() {
+        let sphere = Sphere::new(Point3::new(1.0, 2.0, 3.0), 1.0);
+        let path = CameraPath::turntable(&sphere, 5.0, -0.3, 45.0, 8.0);
+        assert_eq!(path.frame_count(30.0), 240);
+
+        for frame in 0..97 {
+            let pose = path.sample(frame as f32 / 12.0);
+            let offset = pose.position - sphere.center.to_vec();
+            assert!((offset.magnitude() - 5.0).abs() < 1e-3, "distance {} at frame {}", offset.magnitude(), frame);
+
+            let forward = pose.rotation.rotate_vector(Vector3::unit_z());
+            assert!(forward.dot(offset.normalize()) > 0.9999, "looks away at frame {}", frame);
+        }
+    }
+
+    #[test]
+    fn closed_paths_loop_back_to_their_first_keyframe() {
+        let file = env::temp_dir().join(format!("vulkan-test-closed-{}.path", process::id()));
+        let (a, b, c) = (view([0.0, 0.0, 0.0], 0.0, 2.0), view([1.0, 0.0, 0.0], 1.0, 3.0), view([0.0, 1.0, 0.0], 2.0, 4.0));
+        fs::write(&file, format!("closed\n0 {}\n1 {}\n3 {}\n", a, b, c)).unwrap();
+        let mut path = CameraPath::load(&file).unwrap();
+        fs::remove_file(&file).unwrap();
+
+        // The first keyframe follows one average spacing after the last
+        assert_eq!(path.keyframes().len(), 4);
+        assert_eq!(path.duration(), 4.5);
+        assert!(path.keyframes()[3].same_view(&path.keyframes()[0]));
+        assert_eq!(path.frame_count(10.0), 45);
+
+        // The loop is seamless, in position and in its velocity
+        let (start, end) = (path.sample(0.0), path.sample(4.5));
+        assert!((start.position - end.position).magnitude() < 1e-4);
+        let leaving = (path.sample(1e-3).position - start.position) / 1e-3;
+        let arriving = (end.position - path.sample(4.5 - 1e-3).position) / 1e-3;
+        assert!((leaving - arriving).magnitude() < 0.05 * leaving.magnitude(), "{:?} != {:?}", leaving, arriving);
+
+        // Saving keeps the closing keyframe, loading it again does not add another one
+        path.save(&file).unwrap();
+        let reloaded = CameraPath::load(&file).unwrap();
+        fs::remove_file(&file).unwrap();
+        assert_eq!(reloaded.keyframes(), path.keyframes());
+
+        // A keyframe added at the end goes before the closing one
+        path.add(Keyframe::new(6.0, view([0.0, 0.0, 1.0], 3.0, 2.0)));
+        let times: Vec<f32> = path.keyframes().iter().map(|k| k.time).collect();
+        assert_eq!(times, vec![0.0, 1.0, 3.0, 6.0, 8.0]);
+        assert!(path.keyframes()[4].same_view(&path.keyframes()[0]));
+
+        let mut open = CameraPath::new();
+        open.add(Keyframe::new(0.0, a)).add(Keyframe::new(2.0, b));
+        open.set_closed(true);
+        assert_eq!(open.keyframes().len(), 3);
+        assert_eq!(open.duration(), 4.0);
+    }
+
+    #[test]
+    fn squad_between_two_keyframes_is_slerp() {
+        let mut path = CameraPath::new();
+        path.add(Keyframe::new(0.0, view([0.0, 0.0, 0.0], 0.0, 1.0)))
+            .add(Keyframe::new(1.0, view([0.0, 0.0, 0.0], 2.0, 1.0)));
+
+        let pose = path.sample(0.25);
+        let expected = Quaternion::from_axis_angle(Vector3::unit_y(), Rad(0.5));
+        assert!(close(pose.rotation.dot(expected).abs(), 1.0), "{:?}", pose.rotation);
+    }
+}
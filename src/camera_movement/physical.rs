@@ -0,0 +1,147 @@
+use std::f32::consts::PI;
+use std::fmt;
+
+// Camera settings in the units of a real camera. The focal length and sensor size give the field of
+// view, aperture, shutter speed and ISO give the exposure, and together with the focus distance they
+// give the depth of field. Scene units are taken to be meters, as for scaled Megascans assets.
+
+// The viewer's light stands for direct sunlight of this many lux. With the sunny 16 settings
+// (f/16, 1/125 s, ISO 100) a white surface facing it comes out at about 0.83.
+const LIGHT_ILLUMINANCE: f32 = 100000.0;
+
+#[derive(Copy, Clone, Debug, PartialEq)]
+pub struct PhysicalCamera {
+    // Sensor size in millimeters
+    pub sensor_width: f32,
+    pub sensor_height: f32,
+    // Millimeters
+    pub focal_length: f32,
+    // f-number
+    pub aperture: f32,
+    // Seconds
+    pub shutter: f32,
+    pub iso: f32,
+    // Distance from the camera to the plane in focus along the view direction, in scene units
+    pub focus_distance: f32
+}
+
+impl PhysicalCamera {
+    // A full frame camera with a normal lens, exposed for sunlight
+    pub fn default() -> PhysicalCamera {
+        PhysicalCamera {
+            sensor_width: 36.0,
+            sensor_height: 24.0,
+            focal_length: 50.0,
+            aperture: 16.0,
+            shutter: 1.0 / 125.0,
+            iso: 100.0,
+            focus_distance: 1.0
+        }
+    }
+
+    // Height in millimeters of the sensor area that fills the viewport height. The whole sensor
+    // stays visible, a window with another aspect ratio shows more of the scene around it.
+    fn visible_height(&self, aspect_ratio: f32) -> f32 {
+        self.sensor_height.max(self.sensor_width / aspect_ratio)
+    }
+
+    // Vertical field of view in degrees
+    pub fn fov(&self, aspect_ratio: f32) -> f32 {
+        (2.0 * (self.visible_height(aspect_ratio) / (2.0 * self.focal_length)).atan()).to_degrees()
+    }
+
+    // Exposure value at ISO 100
+    pub fn ev100(&self) -> f32 {
+        (self.aperture * self.aperture / self.shutter * 100.0 / self.iso).log2()
+    }
+
+    // Scale from the lit surface color to the displayed color. The sensor saturates at a luminance of
+    // 1.2 * 2^EV100 (Lagarde and de Rousiers, Moving Frostbite to PBR).
+    pub fn exposure(&self) -> f32 {
+        LIGHT_ILLUMINANCE / PI / (1.2 * 2f32.powf(self.ev100()))
+    }
+
+    // Thin lens circle of confusion: a point at depth d is blurred into a disk of
+    // coc_scale * |d - focus| / d pixels in diameter
+    pub fn coc_scale(&self, aspect_ratio: f32, viewport_height: f32) -> f32 {
+        let f = self.focal_length;
+        // Nothing closer than the focal length can be focused on
+        let focus = (self.focus_distance * 1000.0).max(f * 1.001);
+        let diameter = f / self.aperture;
+        diameter * f / (focus - f) / self.visible_height(aspect_ratio) * viewport_height
+    }
+}
+
+// e.g. "50mm f/16 1/125s ISO 100, EV 15.0"
+impl fmt::Display for PhysicalCamera {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        let shutter = if self.shutter < 1.0 {
+            format!("1/{}s", (1.0 / self.shutter).round())
+        } else {
+            format!("{}s", self.shutter)
+        };
+        write!(f, "{}mm f/{} {} ISO {}, EV {:.1}", self.focal_length, self.aperture, shutter, self.iso, self.ev100())
+    }
+}
+
+// Reads "1/125" or "0.5"
+pub fn parse_shutter(s: &str) -> Option<f32> {
+    let mut split = s.splitn(2, '/');
+    let first = split.next().and_then(|v| v.trim().parse::<f32>().ok());
+    let shutter = match split.next() {
+        Some(denominator) => first.and_then(|n| denominator.trim().parse::<f32>().ok().map(|d| n / d)),
+        None => first
+    };
+    shutter.and_then(|t| if t > 0.0 && t.is_finite() { Some(t) } else { None })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn assert_close(a: f32, b: f32, epsilon: f32) {
+        assert!((a - b).abs() < epsilon, "{} != {}", a, b);
+    }
+
+    #[test]
+    fn focal_length_gives_the_field_of_view() {
+        let camera = PhysicalCamera::default();
+        // The 3:2 sensor fills a 3:2 window, 24mm high behind a 50mm lens
+        assert_close(camera.fov(1.5), 26.99, 0.01);
+        // A square window shows the full 36mm width
+        assert_close(camera.fov(1.0), 39.6, 0.01);
+        // A wider window shows the full height again
+        assert_close(camera.fov(2.0), camera.fov(1.5), 1e-5);
+    }
+
+    #[test]
+    fn sunny_16_exposure() {
+        let camera = PhysicalCamera::default();
+        assert_close(camera.ev100(), 14.97, 0.01);
+        assert_close(camera.exposure(), 0.83, 0.01);
+
+        // One stop more light on the sensor doubles the exposure
+        let brighter = PhysicalCamera { shutter: camera.shutter * 2.0, ..camera };
+        assert_close(brighter.exposure(), camera.exposure() * 2.0, 1e-4);
+        let faster = PhysicalCamera { iso: 200.0, ..camera };
+        assert_close(faster.exposure(), brighter.exposure(), 1e-4);
+    }
+
+    #[test]
+    fn circle_of_confusion_grows_with_the_aperture() {
+        let camera = PhysicalCamera { aperture: 2.0, focus_distance: 2.0, ..PhysicalCamera::default() };
+        // A 25mm wide aperture focused at 2m blurs the background into 0.64mm on the sensor,
+        // 28.8 pixels of 1080 over the 24mm height
+        assert_close(camera.coc_scale(1.5, 1080.0), 25.0 * 50.0 / 1950.0 / 24.0 * 1080.0, 1e-3);
+        let stopped_down = PhysicalCamera { aperture: 8.0, ..camera };
+        assert_close(stopped_down.coc_scale(1.5, 1080.0) * 4.0, camera.coc_scale(1.5, 1080.0), 1e-3);
+    }
+
+    #[test]
+    fn shutter_speeds() {
+        assert_eq!(parse_shutter("1/125"), Some(1.0 / 125.0));
+        assert_eq!(parse_shutter("0.5"), Some(0.5));
+        assert_eq!(parse_shutter("1/0"), None);
+        assert_eq!(parse_shutter("fast"), None);
+    }
+}
@@ -0,0 +1,176 @@
+use std::collections::BTreeMap;
+use std::fmt;
+use std::fs::File;
+use std::io::{Read, Write};
+use std::path::{Path, PathBuf};
+use std::str::FromStr;
+
+// A saved orbit camera view as a single line of text, so it can be passed on the command line:
+//
+//     target=0,0.5,0 rotation=1,0,0,0 distance=3.2 fov=60 projection=perspective
+//
+// The rotation is a quaternion with the scalar part first. Floats are written with the shortest
+// representation that reads back to the same value, so a view round-trips exactly.
+
+#[derive(Copy, Clone, Debug, PartialEq)]
+pub struct CameraView {
+    pub target: [f32; 3],
+    pub rotation: [f32; 4],
+    pub distance: f32,
+    // Vertical field of view in degrees
+    pub fov: f32,
+    pub orthographic: bool
+}
+
+fn join(values: &[f32]) -> String {
+    values.iter().map(|v| v.to_string()).collect::<Vec<_>>().join(",")
+}
+
+fn parse_floats(key: &str, value: &str, out: &mut [f32]) -> Result<(), String> {
+    let parts: Vec<&str> = value.split(',').collect();
+    if parts.len() != out.len() {
+        return Err(format!("{} expects {} comma separated numbers, got '{}'", key, out.len(), value));
+    }
+    for (part, out) in parts.iter().zip(out.iter_mut()) {
+        *out = part.trim().parse().map_err(|_| format!("{} has an invalid number '{}'", key, part))?;
+    }
+    Ok(())
+}
+
+impl fmt::Display for CameraView {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "target={} rotation={} distance={} fov={} projection={}",
+               join(&self.target), join(&self.rotation), self.distance, self.fov,
+               if self.orthographic { "orthographic" } else { "perspective" })
+    }
+}
+
+impl FromStr for CameraView {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<CameraView, String> {
+        let (mut target, mut rotation, mut distance, mut fov, mut orthographic) = (None, None, None, None, None);
+
+        for pair in s.split_whitespace() {
+            let mut split = pair.splitn(2, '=');
+            let key = split.next().unwrap();
+            let value = split.next().ok_or_else(|| format!("expected key=value, got '{}'", pair))?;
+            match key {
+                "target" => {
+                    let mut v = [0.0; 3];
+                    parse_floats(key, value, &mut v)?;
+                    target = Some(v);
+                },
+                "rotation" => {
+                    let mut v = [0.0; 4];
+                    parse_floats(key, value, &mut v)?;
+                    rotation = Some(v);
+                },
+                "distance" | "fov" => {
+                    let mut v = [0.0; 1];
+                    parse_floats(key, value, &mut v)?;
+                    if key == "distance" { distance = Some(v[0]) } else { fov = Some(v[0]) }
+                },
+                "projection" => orthographic = Some(match value {
+                    "perspective" => false,
+                    "orthographic" => true,
+                    _ => return Err(format!("projection is perspective or orthographic, got '{}'", value))
+                }),
+                _ => return Err(format!("unknown view key '{}'", key))
+            }
+        }
+
+        let missing = |name: &str| format!("view is missing {}", name);
+        Ok(CameraView {
+            target: target.ok_or_else(|| missing("target"))?,
+            rotation: rotation.ok_or_else(|| missing("rotation"))?,
+            distance: distance.ok_or_else(|| missing("distance"))?,
+            fov: fov.ok_or_else(|| missing("fov"))?,
+            orthographic: orthographic.unwrap_or(false)
+        })
+    }
+}
+
+// Numbered views of one asset, stored next to the model as one "<slot> <view>" line per slot
+pub struct Bookmarks {
+    path: PathBuf,
+    slots: BTreeMap<u32, CameraView>
+}
+
+impl Bookmarks {
+    pub fn path_for(model: &Path) -> PathBuf {
+        model.with_extension("views")
+    }
+
+    // A missing file gives no bookmarks, lines that cannot be read are reported and skipped
+    pub fn load(model: &Path) -> Bookmarks {
+        let path = Bookmarks::path_for(model);
+        let mut slots = BTreeMap::new();
+
+        let mut source = String::new();
+        if File::open(&path).and_then(|mut f| f.read_to_string(&mut source)).is_ok() {
+            for (number, line) in source.lines().enumerate() {
+                let line = line.trim();
+                if line.is_empty() || line.starts_with('#') {
+                    continue;
+                }
+                let mut split = line.splitn(2, char::is_whitespace);
+                let slot = split.next().and_then(|s| s.parse::<u32>().ok());
+                match (slot, split.next().map(|view| view.parse::<CameraView>())) {
+                    (Some(slot), Some(Ok(view))) => {
+                        slots.insert(slot, view);
+                    },
+                    (_, Some(Err(err))) => println!("{}:{}: {}", path.display(), number + 1, err),
+                    _ => println!("{}:{}: expected a slot number and a view", path.display(), number + 1)
+                }
+            }
+        }
+
+        Bookmarks { path: path, slots: slots }
+    }
+
+    pub fn get(&self, slot: u32) -> Option<CameraView> {
+        self.slots.get(&slot).cloned()
+    }
+
+    // Stores the view in the slot and rewrites the file
+    pub fn set(&mut self, slot: u32, view: CameraView) -> Result<(), String> {
+        self.slots.insert(slot, view);
+
+        let mut out = String::new();
+        for (slot, view) in self.slots.iter() {
+            out.push_str(&format!("{} {}\n", slot, view));
+        }
+        File::create(&self.path).and_then(|mut f| f.write_all(out.as_bytes()))
+            .map_err(|e| format!("could not write {}: {}", self.path.display(), e))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn view_round_trips_exactly() {
+        let view = CameraView {
+            target: [0.1, -2.5, 1e-7],
+            rotation: [0.9238795, 0.0, 0.38268343, 0.0],
+            distance: 3.3333333,
+            fov: 60.0,
+            orthographic: true
+        };
+        let text = view.to_string();
+        assert_eq!(text.parse::<CameraView>(), Ok(view));
+    }
+
+    #[test]
+    fn projection_defaults_to_perspective_and_errors_are_reported() {
+        let view: CameraView = "fov=45 distance=2 target=1,2,3 rotation=1,0,0,0".parse().unwrap();
+        assert_eq!(view.target, [1.0, 2.0, 3.0]);
+        assert!(!view.orthographic);
+
+        assert!("target=1,2 rotation=1,0,0,0 distance=2 fov=45".parse::<CameraView>().is_err());
+        assert!("target=1,2,3 rotation=1,0,0,0 fov=45".parse::<CameraView>().is_err());
+        assert!("target=1,2,3 rotation=1,0,0,0 distance=2 fov=45 zoom=3".parse::<CameraView>().is_err());
+    }
+}
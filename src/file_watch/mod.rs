@@ -0,0 +1,62 @@
+use std::fs;
+use std::path::{Path, PathBuf};
+use std::time::SystemTime;
+
+use time;
+
+// Polling the file system every frame is wasteful, twice a second is plenty for editing
+const POLL_INTERVAL: f64 = 0.5;
+
+fn modified(path: &Path) -> Option<SystemTime> {
+    fs::metadata(path).and_then(|m| m.modified()).ok()
+}
+
+pub struct FileWatcher {
+    files: Vec<(PathBuf, Option<SystemTime>)>,
+    last_poll: f64
+}
+
+impl FileWatcher {
+    pub fn new() -> FileWatcher {
+        FileWatcher {
+            files: Vec::new(),
+            last_poll: time::precise_time_s()
+        }
+    }
+
+    pub fn watch<P: AsRef<Path>>(&mut self, path: P) {
+        let path = path.as_ref();
+        if self.files.iter().any(|&(ref p, _)| p == path) {
+            return;
+        }
+
+        self.files.push((path.to_path_buf(), modified(path)));
+    }
+
+    pub fn clear(&mut self) {
+        self.files.clear();
+    }
+
+    pub fn files(&self) -> Vec<PathBuf> {
+        self.files.iter().map(|&(ref p, _)| p.clone()).collect()
+    }
+
+    pub fn changed(&mut self) -> bool {
+        let now = time::precise_time_s();
+        if now - self.last_poll < POLL_INTERVAL {
+            return false;
+        }
+        self.last_poll = now;
+
+        let mut changed = false;
+        for &mut (ref path, ref mut stamp) in self.files.iter_mut() {
+            let current = modified(path);
+            if current != *stamp {
+                *stamp = current;
+                changed = true;
+            }
+        }
+
+        changed
+    }
+}
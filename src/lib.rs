@@ -0,0 +1,32 @@
+#![allow(dead_code)]
+
+#[macro_use]
+extern crate vulkano;
+extern crate vulkano_win;
+
+extern crate winit;
+extern crate cgmath;
+extern crate time;
+extern crate tobj;
+extern crate find_folder;
+extern crate image;
+extern crate shaderc;
+extern crate serde;
+#[macro_use]
+extern crate serde_derive;
+extern crate toml;
+extern crate serde_json;
+extern crate bcndecode;
+extern crate intel_tex;
+extern crate memmap;
+
+pub mod bounds;
+pub mod obj_loader;
+pub mod camera_movement;
+pub mod renderer;
+pub mod file_watch;
+pub mod material;
+pub mod megascans;
+pub mod options;
+pub mod texture;
+pub mod loader;
@@ -0,0 +1,304 @@
+use find_folder::Search;
+
+use material::{Material, MaterialMaps, MaterialSource};
+use material::scan;
+use material::textures::{prepare_textures_with_progress, MaterialTextureData};
+use megascans::MegascansAsset;
+use obj_loader;
+use obj_loader::optimize;
+use obj_loader::validate::{self, RepairOptions};
+use obj_loader::simplify::{lod_chain, Lod, LodSettings};
+use obj_loader::Model;
+
+use std::any::Any;
+use std::panic;
+use std::path::{Path, PathBuf};
+use std::sync::mpsc::{channel, Receiver, Sender};
+use std::thread;
+
+// Loads meshes and textures on worker threads, the render loop polls for results and uploads
+// them once they arrive. Everything here is CPU side, GPU resources are created by the caller.
+
+pub enum LoadEvent {
+    Mesh(Model),
+    // Simplified levels of the last mesh, the coarsest last
+    Lods(Vec<Lod>),
+    // Sent once the material source is known, so the viewer can watch it for changes
+    MaterialSource(MaterialSource),
+    Material(Material, MaterialTextureData),
+    Failed(String)
+}
+
+enum Message {
+    Progress(String),
+    Event(LoadEvent),
+    // Prepared textures, tagged with the generation of the job that made them
+    Material(u64, Material, MaterialTextureData),
+    // Sent by every job when it ends, whether it succeeded or not
+    Finished
+}
+
+// What to load for an asset, resolved up front since it only needs a few small files
+pub struct AssetPaths {
+    pub model: PathBuf,
+    pub megascans: Option<MegascansAsset>,
+    pub material: MaterialSource
+}
+
+impl AssetPaths {
+    // Opens an asset folder or a base name like assets/Aset_wood_stump_M_okfch. A Megascans JSON sidecar
+    // decides mesh, maps and scale, otherwise the first obj and the textures found by naming convention are used.
+    pub fn resolve(asset: &Path, lod: u32) -> Result<AssetPaths, String> {
+        let megascans = scan::find_files(asset, &["json"]).into_iter()
+            .filter_map(|json| match MegascansAsset::from_file(&json) {
+                Ok(megascans) => Some((json, megascans)),
+                Err(err) => {
+                    println!("{}", err);
+                    None
+                }
+            })
+            .find(|&(_, ref megascans)| megascans.mesh(lod).is_some());
+
+        let model = match megascans {
+            Some((_, ref megascans)) => megascans.mesh(lod).unwrap().to_path_buf(),
+            None => scan::find_files(asset, &["obj"]).into_iter().next()
+                .ok_or_else(|| format!("no obj file found for {}", asset.display()))?
+        };
+
+        let material_path = model.with_extension("material.toml");
+        let (material, megascans) = if material_path.is_file() {
+            (MaterialSource::new(material_path), megascans.map(|(_, m)| m))
+        } else if let Some((json, megascans)) = megascans {
+            (MaterialSource::megascans(json), Some(megascans))
+        } else {
+            (MaterialSource::scan(asset), None)
+        };
+
+        Ok(AssetPaths {
+            model: model,
+            megascans: megascans,
+            material: material
+        })
+    }
+
+    pub fn default_asset() -> AssetPaths {
+        let path = Search::ParentsThenKids(3, 3).for_folder("assets").unwrap();
+        AssetPaths {
+            model: path.join("stump.obj"),
+            megascans: None,
+            material: MaterialSource::new(path.join("stump.material.toml"))
+        }
+    }
+}
+
+// Parsing the mesh and loading the material source, the texture slots come on top
+const ASSET_STEPS: usize = 2;
+const TEXTURE_STEPS: usize = 5;
+
+pub struct AssetLoader {
+    sender: Sender<Message>,
+    messages: Receiver<Message>,
+    // Jobs that have not sent their result yet
+    pending: usize,
+    optimize: bool,
+    validate: bool,
+    repair: bool,
+    lod_levels: u32,
+    // Incremented for every material job, only the results of the latest one are used
+    material_generation: u64,
+    done: usize,
+    total: usize,
+    stage: String
+}
+
+fn panic_message(panic: Box<Any + Send>) -> String {
+    match panic.downcast::<String>() {
+        Ok(message) => *message,
+        Err(panic) => panic.downcast_ref::<&str>().map_or("unknown error".to_string(), |m| m.to_string())
+    }
+}
+
+// Runs the job on its own thread, a panic while loading is reported instead of taking the viewer down
+fn spawn<F: FnOnce(&Sender<Message>) + Send + 'static>(sender: &Sender<Message>, job: F) {
+    let sender = sender.clone();
+    thread::spawn(move || {
+        if let Err(panic) = panic::catch_unwind(panic::AssertUnwindSafe(|| job(&sender))) {
+            sender.send(Message::Event(LoadEvent::Failed(panic_message(panic)))).ok();
+        }
+        sender.send(Message::Finished).ok();
+    });
+}
+
+fn prepare_material(material: Material, generation: u64, sender: &Sender<Message>) {
+    let progress = |slot: &'static str| {
+        sender.send(Message::Progress(format!("preparing {} texture", slot))).ok();
+    };
+
+    let textures = prepare_textures_with_progress(&material, &progress).unwrap_or_else(|err| {
+        println!("{}\nfalling back to the material factors", err);
+        prepare_textures_with_progress(&Material { maps: MaterialMaps::default(), ..material.clone() }, &|_| ()).unwrap()
+    });
+    sender.send(Message::Material(generation, material, textures)).ok();
+}
+
+impl AssetLoader {
+    pub fn new() -> AssetLoader {
+        let (sender, messages) = channel();
+        AssetLoader {
+            sender: sender,
+            messages: messages,
+            pending: 0,
+            optimize: false,
+            validate: false,
+            repair: false,
+            lod_levels: 0,
+            material_generation: 0,
+            done: 0,
+            total: 0,
+            stage: String::new()
+        }
+    }
+
+    // Runs the mesh optimization stage on every loaded mesh
+    pub fn set_optimize(&mut self, optimize: bool) -> &mut Self {
+        self.optimize = optimize;
+        self
+    }
+
+    // Prints a validation report for every loaded mesh
+    pub fn set_validate(&mut self, validate: bool) -> &mut Self {
+        self.validate = validate;
+        self
+    }
+
+    // Applies the safe repairs to every loaded mesh before anything else touches it
+    pub fn set_repair(&mut self, repair: bool) -> &mut Self {
+        self.repair = repair;
+        self
+    }
+
+    // Generates this many simplified levels of detail for every loaded mesh
+    pub fn set_lod_levels(&mut self, levels: u32) -> &mut Self {
+        self.lod_levels = levels;
+        self
+    }
+
+    // Loads mesh and material in parallel, each is sent as soon as it is ready
+    pub fn load(&mut self, asset: AssetPaths, lod: u32) {
+        self.total += ASSET_STEPS + TEXTURE_STEPS + if self.optimize { 1 } else { 0 } +
+            if self.validate || self.repair { 1 } else { 0 } + if self.lod_levels > 0 { 1 } else { 0 };
+        self.pending += 2;
+
+        let AssetPaths { model, megascans, material } = asset;
+        let mut material_source = material;
+        let run_optimize = self.optimize;
+        let run_validate = self.validate;
+        let run_repair = self.repair;
+        let lod_levels = self.lod_levels;
+        self.material_generation += 1;
+        let generation = self.material_generation;
+
+        spawn(&self.sender, move |sender| {
+            sender.send(Message::Progress(format!("parsing {}", model.display()))).ok();
+            println!("loading {} (lod {})", model.display(), lod);
+
+            let mut model = obj_loader::load_model_from_path(&model);
+            if let Some(ref megascans) = megascans {
+                if let Some(scale) = megascans.physical_scale(&model.bounds) {
+                    println!("scaling {} by {} to real-world size", megascans.name, scale);
+                    model.scale(scale);
+                }
+            }
+            if run_validate || run_repair {
+                sender.send(Message::Progress("validating mesh".to_string())).ok();
+                print!("mesh validation:\n{}", validate::validate(&model));
+                if run_repair {
+                    println!("mesh repair:\n{}", validate::repair(&mut model, &RepairOptions::all()));
+                }
+            }
+            if run_optimize {
+                sender.send(Message::Progress("optimizing mesh".to_string())).ok();
+                println!("mesh optimization:\n{}", optimize::optimize(&mut model, 0.0));
+            }
+            if lod_levels == 0 {
+                sender.send(Message::Event(LoadEvent::Mesh(model))).ok();
+                return;
+            }
+
+            // The full resolution mesh is shown while the levels are generated
+            sender.send(Message::Event(LoadEvent::Mesh(model.clone()))).ok();
+            sender.send(Message::Progress(format!("generating {} levels of detail", lod_levels))).ok();
+
+            let mut lods: Vec<Lod> = lod_chain(&model, &LodSettings::default().levels(lod_levels)).into_iter().skip(1).collect();
+            for (level, lod) in lods.iter_mut().enumerate() {
+                if run_optimize {
+                    optimize::optimize(&mut lod.model, 0.0);
+                }
+                println!("LOD {}: {} triangles, error {}", level + 1, lod.triangle_count(), lod.error);
+            }
+            sender.send(Message::Event(LoadEvent::Lods(lods))).ok();
+        });
+
+        spawn(&self.sender, move |sender| {
+            sender.send(Message::Progress("loading material".to_string())).ok();
+            let material = material_source.load().unwrap_or_else(|err| {
+                println!("{}\nfalling back to the default material", err);
+                Material::default()
+            });
+            sender.send(Message::Event(LoadEvent::MaterialSource(material_source))).ok();
+
+            prepare_material(material, generation, sender);
+        });
+    }
+
+    // Prepares the textures of an edited material, the previous ones stay in use until then.
+    // Jobs for earlier edits may still be running, whichever finishes last only the latest edit is shown.
+    pub fn reload_material(&mut self, material: Material) {
+        self.total += TEXTURE_STEPS;
+        self.pending += 1;
+        self.material_generation += 1;
+        let generation = self.material_generation;
+        spawn(&self.sender, move |sender| prepare_material(material, generation, sender));
+    }
+
+    // Returns the results that arrived since the last call, never blocks
+    pub fn poll(&mut self) -> Vec<LoadEvent> {
+        let mut events = Vec::new();
+        while let Ok(message) = self.messages.try_recv() {
+            match message {
+                Message::Progress(stage) => {
+                    self.done += 1;
+                    println!("[{}/{}] {}", self.done, self.total, stage);
+                    self.stage = stage;
+                },
+                Message::Event(event) => events.push(event),
+                Message::Material(generation, material, textures) => {
+                    if generation == self.material_generation {
+                        events.push(LoadEvent::Material(material, textures));
+                    }
+                },
+                Message::Finished => self.pending -= 1
+            }
+        }
+
+        // Failed jobs never report their remaining steps, so the count starts over once all are through
+        if self.pending == 0 {
+            self.done = 0;
+            self.total = 0;
+        }
+        events
+    }
+
+    pub fn is_busy(&self) -> bool {
+        self.pending > 0
+    }
+
+    // e.g. "3/7 preparing normal texture"
+    pub fn status(&self) -> Option<String> {
+        if self.is_busy() {
+            Some(format!("{}/{} {}", self.done, self.total, self.stage))
+        } else {
+            None
+        }
+    }
+}
@@ -2,34 +2,148 @@
 
 #[macro_use]
 extern crate vulkano;
-#[macro_use]
-extern crate vulkano_shader_derive;
 extern crate vulkano_win;
 
 extern crate winit;
 extern crate cgmath;
-extern crate time;
-extern crate tobj;
-extern crate find_folder;
-extern crate image;
-
-mod obj_loader;
-mod camera_movement;
-mod renderer;
-
-use renderer::vulkan_init::VulkanInit;
+extern crate vulkan_test;
+
+use vulkan_test::obj_loader;
+use vulkan_test::bounds::{Aabb, Sphere};
+use vulkan_test::obj_loader::simplify::Lod;
+use vulkan_test::renderer;
+use vulkan_test::renderer::vulkan_init::VulkanInit;
+use vulkan_test::renderer::shader::ShaderProgram;
+use vulkan_test::renderer::model_pipeline;
+use vulkan_test::renderer::renderer::{MaterialTextures, MeshBuffers};
+use vulkan_test::renderer::capture;
+use vulkan_test::renderer::depth_of_field::{DepthOfField, DofData};
+use vulkan_test::material::{Material, MaterialSource};
+use vulkan_test::material::textures::prepare_textures;
+use vulkan_test::loader::{AssetLoader, AssetPaths, LoadEvent};
+use vulkan_test::options::Options;
 
 use vulkano_win::VkSurfaceBuild;
 use vulkano::sync::GpuFuture;
-use vulkano::image::ImmutableImage;
-
-use camera_movement::orbit_camera::OrbitCamera;
-use camera_movement::orbit_camera::OrbitZoomCameraSettings;
-use cgmath::Vector2;
 
-use find_folder::Search;
+use vulkan_test::camera_movement::camera::CameraPerspective;
+use vulkan_test::camera_movement::fly_camera::{FlyCamera, FlyCameraSettings, Movement};
+use vulkan_test::camera_movement::orbit_camera::{AxisView, OrbitCamera, RotationMode};
+use vulkan_test::camera_movement::orbit_camera::OrbitZoomCameraSettings;
+use vulkan_test::camera_movement::path::{CameraPath, Keyframe};
+use vulkan_test::camera_movement::physical::PhysicalCamera;
+use vulkan_test::camera_movement::view::{Bookmarks, CameraView};
+use cgmath::{Vector2, InnerSpace, EuclideanSpace, SquareMatrix};
 
 use std::sync::Arc;
+use std::time::Instant;
+
+const TITLE: &'static str = "vulkan-test";
+
+// Vertical field of view in degrees
+const FOV: f32 = 60.0;
+// Trackpads scroll in pixels, this many make up one wheel line
+const PIXELS_PER_LINE: f32 = 20.0;
+// Keyframes added in the viewer follow the last one after this many seconds
+const KEYFRAME_SPACING: f32 = 2.0;
+// The turntable preset looks down on the model from this pitch in degrees
+const TURNTABLE_PITCH: f32 = -20.0;
+const TURNTABLE_SECONDS: f32 = 8.0;
+// A left button press and release closer together than this many pixels is a click, which focuses
+// the physical camera instead of orbiting
+const CLICK_DISTANCE: f32 = 3.0;
+
+// Automatic LOD selection keeps the geometric error of the chosen level below this many pixels
+const MAX_SCREEN_ERROR: f32 = 1.0;
+
+struct LodMesh {
+    buffers: MeshBuffers,
+    triangles: usize,
+    error: f32
+}
+
+impl LodMesh {
+    fn upload(vulkan_init: &VulkanInit, lod: &Lod) -> (LodMesh, Box<GpuFuture>) {
+        let (buffers, future) = MeshBuffers::upload(&vulkan_init.transfer_queue, &lod.model);
+        (LodMesh {
+            buffers: buffers,
+            triangles: lod.triangle_count(),
+            error: lod.error
+        }, future)
+    }
+}
+
+#[derive(Copy, Clone, PartialEq)]
+enum LodMode {
+    Auto,
+    Manual(usize)
+}
+
+#[derive(Copy, Clone, PartialEq)]
+enum CameraMode {
+    Orbit,
+    Fly,
+    // Playing a camera path
+    Path
+}
+
+// The path to play: the keyframes of the asset, or a turntable around the model when there are not
+// enough of them or a turntable was asked for
+fn playback_path(keyframes: &CameraPath, turntable: Option<f32>, sphere: &Sphere, perspective: &CameraPerspective<f32>) -> CameraPath {
+    if turntable.is_none() && keyframes.keyframes().len() >= 2 {
+        return keyframes.clone();
+    }
+    let distance = perspective.framing_distance(sphere.radius.max(1e-4));
+    CameraPath::turntable(sphere, distance, TURNTABLE_PITCH.to_radians(), perspective.fov, turntable.unwrap_or(TURNTABLE_SECONDS))
+}
+
+// Bookmark slot of a top row digit key
+fn bookmark_slot(key: winit::VirtualKeyCode) -> Option<u32> {
+    match key {
+        winit::VirtualKeyCode::Key1 => Some(1),
+        winit::VirtualKeyCode::Key2 => Some(2),
+        winit::VirtualKeyCode::Key3 => Some(3),
+        winit::VirtualKeyCode::Key4 => Some(4),
+        winit::VirtualKeyCode::Key5 => Some(5),
+        winit::VirtualKeyCode::Key6 => Some(6),
+        winit::VirtualKeyCode::Key7 => Some(7),
+        winit::VirtualKeyCode::Key8 => Some(8),
+        winit::VirtualKeyCode::Key9 => Some(9),
+        winit::VirtualKeyCode::Key0 => Some(0),
+        _ => None
+    }
+}
+
+// Picks the coarsest level whose error, projected at the given distance, stays below MAX_SCREEN_ERROR pixels
+fn auto_lod(lods: &[LodMesh], distance: f32, viewport_height: f32, fov: f32) -> usize {
+    let pixels_per_unit = viewport_height / (2.0 * (fov / 2.0).tan() * distance.max(1e-4));
+    lods.iter().rposition(|lod| lod.error * pixels_per_unit <= MAX_SCREEN_ERROR).unwrap_or(0)
+}
+
+// The point under the mouse: the nearest surface of the model, or where the ray crosses the plane
+// through the orbit target facing the camera. The model is drawn without a world transform, so the
+// ray is tested in model space.
+fn cursor_point(camera: &OrbitCamera<f32>, perspective: &CameraPerspective<f32>, model: &obj_loader::Model, mouse: Vector2<f32>) -> cgmath::Vector3<f32> {
+    let mut ray = camera.ray(mouse, perspective);
+    if camera.is_orthographic() {
+        // The camera can sit inside the model when zoomed in, start the ray outside of it
+        ray.origin = ray.at(-model.bounds.diagonal());
+    }
+
+    let forward = camera.camera().forward;
+    let t = match model.raycast(&ray) {
+        Some(t) => t,
+        None => (camera.target() - ray.origin.to_vec()).dot(forward) / ray.direction.dot(forward)
+    };
+    ray.at(t).to_vec()
+}
+
+// A plain grey sphere is shown until the model and its textures have been loaded
+fn placeholder() -> (obj_loader::Model, Material) {
+    let mut material = Material::default();
+    material.factors.base_color = [0.5, 0.5, 0.5, 1.0];
+    (obj_loader::uv_sphere(1.0, 32, 64), material)
+}
 
 fn main() {
 
@@ -37,60 +151,68 @@ fn main() {
 
     let mut vulkan_init = VulkanInit::init(&events_loop);
 
-    let model = obj_loader::load_model("stump.obj"); //TODO make configurable
-
-    let mut path = Search::ParentsThenKids(3, 3).for_folder("assets").unwrap();
-    let (albedo, albedo_future) = {
-        let mut albedo_path = path.clone();
-        albedo_path.push("Aset_wood_stump_M_okfch_4K_Albedo.jpg");
-        let image = image::open(&albedo_path).unwrap().to_rgba();
-        let (width, height) = (image.width(), image.height());
-        let data = image.into_raw().clone();
-
-        ImmutableImage::from_iter(
-            data.iter().cloned(),
-            vulkano::image::Dimensions::Dim2d { width: width, height: height},
-            vulkano::format::R8G8B8A8Srgb,
-            Some(vulkan_init.queue.family()),
-            vulkan_init.queue.clone()
-        ).unwrap()
-    };
+    let options = Options::from_args();
 
-    println!("bounds are: {:?}", model.bounds);
-
-    let vertex_buffer = vulkano::buffer::cpu_access::CpuAccessibleBuffer
-    ::from_iter(vulkan_init.device.clone(), vulkano::buffer::BufferUsage::all(), Some(vulkan_init.queue.family()), model.vertices.iter().cloned())
-        .expect("failed to create buffer");
-
-    let normals_buffer = vulkano::buffer::cpu_access::CpuAccessibleBuffer
-    ::from_iter(vulkan_init.device.clone(), vulkano::buffer::BufferUsage::all(), Some(vulkan_init.queue.family()), model.normals.iter().cloned())
-        .expect("failed to create buffer");
-
-    let index_buffer = vulkano::buffer::cpu_access::CpuAccessibleBuffer
-    ::from_iter(vulkan_init.device.clone(), vulkano::buffer::BufferUsage::all(), Some(vulkan_init.queue.family()), model.indices.iter().cloned())
-        .expect("failed to create buffer");
-
-    // note: this teapot was meant for OpenGL where the origin is at the lower left
-    //       instead the origin is at the upper left in vulkan, so we reverse the Y axis
-    let mut proj = cgmath::perspective(cgmath::Rad(std::f32::consts::FRAC_PI_2), {  vulkan_init.dimensions[0] as f32 / vulkan_init.dimensions[1] as f32 }, 0.01, 100.0);
-    let view = cgmath::Matrix4::look_at(
-        cgmath::Point3::new(
-            (model.bounds.x.1 - model.bounds.x.0) / 2.0,
-            (model.bounds.y.1 - model.bounds.y.0) / 2.0,
-            model.bounds.z.1 + (model.bounds.z.1 - model.bounds.z.0) / 5.0),
-        cgmath::Point3::new(0.0, 0.0, 0.0),
-        cgmath::Vector3::new(0.0, -1.0, 0.0));
+    let asset = match options.asset {
+        Some(ref asset) => AssetPaths::resolve(asset, options.lod).unwrap_or_else(|err| panic!("{}", err)),
+        None => AssetPaths::default_asset()
+    };
+    let mut loader = AssetLoader::new();
+    loader.set_optimize(options.optimize).set_validate(options.validate).set_repair(options.repair)
+        .set_lod_levels(options.simplify);
+    let mut bookmarks = Bookmarks::load(&asset.model);
+    let path_file = options.camera_path.clone().unwrap_or_else(|| CameraPath::path_for(&asset.model));
+    let mut keyframes = if options.camera_path.is_some() || path_file.exists() {
+        CameraPath::load(&path_file).unwrap_or_else(|err| panic!("{}", err))
+    } else {
+        CameraPath::new()
+    };
+    loader.load(asset, options.lod);
+    let mut material_source: Option<MaterialSource> = None;
+    let mut title = String::new();
+
+    let (placeholder_model, mut material) = placeholder();
+    let (mut material_textures, textures_future) = MaterialTextures::upload(&vulkan_init.transfer_queue, &prepare_textures(&material).unwrap());
+    let (placeholder_lod, mesh_future) = LodMesh::upload(&vulkan_init, &Lod { model: placeholder_model.clone(), error: 0.0 });
+    let mut lods = vec![placeholder_lod];
+    let mut lod_mode = LodMode::Auto;
+    let mut bounds: Aabb = placeholder_model.bounds;
+    let mut sphere: Sphere = placeholder_model.bounding_sphere();
+    // The full detail model stays on the CPU for picking. Raycasts test every triangle, so the
+    // wheel, which picks on every notch, uses the coarsest level once it is there.
+    let mut pick_model = placeholder_model;
+    let mut coarse_pick_model: Option<obj_loader::Model> = None;
+    let sampler = renderer::texture::material_sampler(&vulkan_init.device, options.anisotropy);
+
+    let mut perspective = CameraPerspective {
+        fov: FOV,
+        near_clip: 0.01,
+        far_clip: 100.0,
+        aspect_ratio: vulkan_init.dimensions[0] as f32 / vulkan_init.dimensions[1] as f32
+    };
+    // With a physical camera the focal length sets the field of view. Until a distance is given or
+    // clicked, it focuses on the orbit target.
+    let mut physical = options.physical.map(|lens| PhysicalCamera { focus_distance: options.focus.unwrap_or(lens.focus_distance), ..lens });
+    let mut auto_focus = options.focus.is_none();
+    if let Some(ref lens) = physical {
+        perspective.fov = lens.fov(perspective.aspect_ratio);
+        println!("physical camera: {}", lens);
+    }
     let scale = cgmath::Matrix4::from_scale(1.0);
 
-    let uniform_buffer = vulkano::buffer::cpu_pool::CpuBufferPool::<vs::ty::Data>
+    let uniform_buffer = vulkano::buffer::cpu_pool::CpuBufferPool::<model_pipeline::Data>
     ::new(vulkan_init.device.clone(), vulkano::buffer::BufferUsage::all(), Some(vulkan_init.queue.family()));
 
-    let vs = vs::Shader::load(vulkan_init.device.clone()).expect("failed to create shader module");
-    let fs = fs::Shader::load(vulkan_init.device.clone()).expect("failed to create shader module");
+    let material_buffer = vulkano::buffer::cpu_pool::CpuBufferPool::<model_pipeline::MaterialData>
+    ::new(vulkan_init.device.clone(), vulkano::buffer::BufferUsage::all(), Some(vulkan_init.queue.family()));
 
-    let mut depth_buffer = vulkano::image::attachment::AttachmentImage::transient(vulkan_init.device.clone(), vulkan_init.dimensions, vulkano::format::D16Unorm).unwrap();
+    let mut shader_program = ShaderProgram::new("model.vert", "model.frag");
+    let (vs, fs) = match shader_program.load(&vulkan_init.device) {
+        Ok(modules) => modules,
+        Err(err) => panic!("failed to compile the model shaders:\n{}", err)
+    };
 
-    let renderpass = Arc::new(
+    let renderpass: Arc<vulkano::framebuffer::RenderPassAbstract + Send + Sync> = Arc::new(
         single_pass_renderpass!(vulkan_init.device.clone(),
             attachments: {
                 color: {
@@ -99,10 +221,11 @@ fn main() {
                     format: vulkan_init.swapchain.format(),
                     samples: 1,
                 },
+                // Stored for the depth of field pass
                 depth: {
                     load: Clear,
-                    store: DontCare,
-                    format: vulkano::format::Format::D16Unorm,
+                    store: Store,
+                    format: vulkano::format::Format::D32Sfloat,
                     samples: 1,
                 }
             },
@@ -113,53 +236,243 @@ fn main() {
         ).unwrap()
     );
 
-    let pipeline = Arc::new(vulkano::pipeline::GraphicsPipeline::start()
-        .vertex_input(vulkano::pipeline::vertex::TwoBuffersDefinition::new())
-        .vertex_shader(vs.main_entry_point(), ())
-        .triangle_list()
-        .viewports_dynamic_scissors_irrelevant(1)
-        .fragment_shader(fs.main_entry_point(), ())
-        .depth_stencil_simple_depth()
-        .render_pass(vulkano::framebuffer::Subpass::from(renderpass.clone(), 0).unwrap())
-        .build(vulkan_init.device.clone())
-        .unwrap());
+    let mut pipeline = model_pipeline::build(vulkan_init.device.clone(), &vs, &fs, renderpass.clone()).unwrap();
     let mut framebuffers: Option<Vec<Arc<vulkano::framebuffer::Framebuffer<_, _>>>> = None;
+    // With depth of field the scene is drawn offscreen and blurred into the swapchain image
+    let mut depth_of_field = if options.dof {
+        Some(DepthOfField::new(&vulkan_init, &renderpass).unwrap_or_else(|err| panic!("{}", err)))
+    } else {
+        None
+    };
+    // Without depth of field the scene is drawn straight into the swapchain images, with this depth buffer
+    let mut depth_buffer = match depth_of_field {
+        Some(_) => None,
+        None => Some(vulkano::image::attachment::AttachmentImage::transient(vulkan_init.device.clone(), vulkan_init.dimensions, vulkano::format::D32Sfloat).unwrap())
+    };
 
     let mut recreate_swapchain = false;
 
-    let mut previous_frame = Box::new(vulkano::sync::now(vulkan_init.device.clone())) as Box<GpuFuture>;
-
-    let mut camera: OrbitCamera<f32> = OrbitCamera::new(OrbitZoomCameraSettings::default());
+    // Uploads are joined into the frame chain, so drawing waits for them on the GPU instead of the CPU
+    let mut previous_frame = Box::new(vulkano::sync::now(vulkan_init.device.clone()).join(textures_future).join(mesh_future)) as Box<GpuFuture>;
+
+    // Turntable orbiting stops at the poles instead of flipping over them
+    let mut camera: OrbitCamera<f32> = OrbitCamera::new(OrbitZoomCameraSettings::default().pitch_limit(Some(std::f32::consts::FRAC_PI_2)));
+    camera.set_viewport(Vector2::new(vulkan_init.dimensions[0] as f32, vulkan_init.dimensions[1] as f32));
+    camera.frame(&sphere, &perspective);
+    camera.finish_transition();
+    // A view from the command line replaces framing the model once it has loaded
+    let mut keep_view = match options.view {
+        Some(ref view) => {
+            let view = match view.parse::<u32>() {
+                Ok(slot) => bookmarks.get(slot).unwrap_or_else(|| panic!("no view saved in slot {}", slot)),
+                Err(_) => view.parse::<CameraView>().unwrap_or_else(|err| panic!("{}", err))
+            };
+            camera.set_view(&view, &mut perspective);
+            true
+        },
+        None => false
+    };
+    // Flying crosses the model in about two seconds
+    let mut fly_camera: FlyCamera<f32> = FlyCamera::new(FlyCameraSettings::default().move_speed(sphere.radius));
+    let mut camera_mode = CameraMode::Orbit;
+    let mut playing = CameraPath::new();
+    let mut path_time = 0.0f32;
+    // While rendering an image sequence, the next frame to render
+    let mut export_frame: Option<u32> = None;
 
     let mut mouse_coords = Vector2::new(0.0f32, 0.0f32);
+    let mut click_start: Option<Vector2<f32>> = None;
+    let mut last_frame = Instant::now();
 
     loop {
         previous_frame.cleanup_finished();
 
+        let frame_time = {
+            let now = Instant::now();
+            let elapsed = now - last_frame;
+            last_frame = now;
+            // Long stalls, e.g. while the window is dragged, should not turn into a jump
+            (elapsed.as_secs() as f32 + elapsed.subsec_nanos() as f32 * 1e-9).min(0.1)
+        };
+
         let mut done = false;
         events_loop.poll_events(|ev| {
             match ev {
                 winit::Event::WindowEvent { event, .. } => {
                     match event {
                         winit::WindowEvent::Closed => done = true,
+                        // Rendering an image sequence plays the path on its own, input would end up in the frames
+                        winit::WindowEvent::KeyboardInput { .. } | winit::WindowEvent::MouseInput { .. } |
+                        winit::WindowEvent::MouseWheel { .. } if export_frame.is_some() => (),
+                        winit::WindowEvent::MouseInput { state, button, .. } if camera_mode == CameraMode::Fly => match (state, button) {
+                            (winit::ElementState::Pressed, winit::MouseButton::Left) => fly_camera.look_start(mouse_coords),
+                            (winit::ElementState::Released, winit::MouseButton::Left) => fly_camera.look_end(),
+                            _ => ()
+                        },
                         winit::WindowEvent::MouseInput { state, button, .. } => match state {
                             winit::ElementState::Pressed => match button {
-                                winit::MouseButton::Left => camera.rotate_start(mouse_coords),
+                                winit::MouseButton::Left => {
+                                    click_start = Some(mouse_coords);
+                                    camera.rotate_start(mouse_coords);
+                                },
                                 winit::MouseButton::Middle => camera.zoom_start(mouse_coords),
                                 winit::MouseButton::Right => camera.pan_start(mouse_coords),
                                 _ => ()
                             },
                             winit::ElementState::Released => match button {
-                                winit::MouseButton::Left => camera.rotate_end(),
+                                winit::MouseButton::Left => {
+                                    camera.rotate_end();
+                                    // Clicking focuses on the surface under the cursor
+                                    let clicked = click_start.take().map_or(false, |start| (mouse_coords - start).magnitude() < CLICK_DISTANCE);
+                                    if let (true, Some(lens)) = (clicked, physical.as_mut()) {
+                                        let point = cursor_point(&camera, &perspective, &pick_model, mouse_coords);
+                                        let view_camera = camera.camera();
+                                        lens.focus_distance = (point - view_camera.position).dot(-view_camera.forward).max(1e-3);
+                                        auto_focus = false;
+                                        println!("focused at {}m", lens.focus_distance);
+                                    }
+                                },
                                 winit::MouseButton::Middle => camera.zoom_end(),
                                 winit::MouseButton::Right => camera.pan_end(),
                                 _ => ()
                             }
                         },
+                        winit::WindowEvent::KeyboardInput { input: winit::KeyboardInput { state, virtual_keycode: Some(key), modifiers, .. }, .. } => match key {
+                            // Fly camera keys act while they are held, Shift speeds up and Ctrl slows down. Other
+                            // modes ignore them, flying starts with nothing held.
+                            winit::VirtualKeyCode::W | winit::VirtualKeyCode::S | winit::VirtualKeyCode::A |
+                            winit::VirtualKeyCode::D | winit::VirtualKeyCode::E | winit::VirtualKeyCode::Q |
+                            winit::VirtualKeyCode::LShift | winit::VirtualKeyCode::RShift |
+                            winit::VirtualKeyCode::LControl | winit::VirtualKeyCode::RControl if camera_mode != CameraMode::Fly => (),
+                            winit::VirtualKeyCode::W => fly_camera.set_movement(Movement::Forward, state == winit::ElementState::Pressed),
+                            winit::VirtualKeyCode::S => fly_camera.set_movement(Movement::Back, state == winit::ElementState::Pressed),
+                            winit::VirtualKeyCode::A => fly_camera.set_movement(Movement::Left, state == winit::ElementState::Pressed),
+                            winit::VirtualKeyCode::D => fly_camera.set_movement(Movement::Right, state == winit::ElementState::Pressed),
+                            winit::VirtualKeyCode::E => fly_camera.set_movement(Movement::Up, state == winit::ElementState::Pressed),
+                            winit::VirtualKeyCode::Q => fly_camera.set_movement(Movement::Down, state == winit::ElementState::Pressed),
+                            winit::VirtualKeyCode::LShift | winit::VirtualKeyCode::RShift => fly_camera.set_fast(state == winit::ElementState::Pressed),
+                            winit::VirtualKeyCode::LControl | winit::VirtualKeyCode::RControl => fly_camera.set_slow(state == winit::ElementState::Pressed),
+                            _ if state == winit::ElementState::Released => (),
+                            // Tab switches between orbiting and flying from the current view
+                            winit::VirtualKeyCode::Tab => camera_mode = match camera_mode {
+                                CameraMode::Orbit | CameraMode::Path => {
+                                    fly_camera.stop().look_from(&camera.camera());
+                                    CameraMode::Fly
+                                },
+                                CameraMode::Fly => {
+                                    camera.look_from(&fly_camera.camera());
+                                    CameraMode::Orbit
+                                }
+                            },
+                            // Page up/down step through the levels manually, L goes back to automatic selection
+                            winit::VirtualKeyCode::PageUp => lod_mode = match lod_mode {
+                                LodMode::Manual(level) => LodMode::Manual(level.saturating_sub(1)),
+                                LodMode::Auto => LodMode::Manual(0)
+                            },
+                            winit::VirtualKeyCode::PageDown => lod_mode = match lod_mode {
+                                LodMode::Manual(level) => LodMode::Manual((level + 1).min(lods.len() - 1)),
+                                LodMode::Auto => LodMode::Manual(1.min(lods.len() - 1))
+                            },
+                            winit::VirtualKeyCode::L => lod_mode = LodMode::Auto,
+                            // Frames the whole model
+                            winit::VirtualKeyCode::F => {
+                                camera.frame(&sphere, &perspective);
+                                camera_mode = CameraMode::Orbit;
+                            },
+                            // Numpad 1/3/7 snap to the front, right and top views, with Ctrl to the opposite side,
+                            // numpad 5 toggles between perspective and orthographic
+                            winit::VirtualKeyCode::Numpad1 | winit::VirtualKeyCode::Numpad3 | winit::VirtualKeyCode::Numpad7 => {
+                                let view = match key {
+                                    winit::VirtualKeyCode::Numpad1 => AxisView::Front,
+                                    winit::VirtualKeyCode::Numpad3 => AxisView::Right,
+                                    _ => AxisView::Top
+                                };
+                                camera.snap(if modifiers.ctrl { view.opposite() } else { view });
+                                camera_mode = CameraMode::Orbit;
+                            },
+                            winit::VirtualKeyCode::Numpad5 => {
+                                let orthographic = !camera.is_orthographic();
+                                camera.set_orthographic(orthographic);
+                            },
+                            // C switches the wheel between zooming to the target and to the cursor
+                            winit::VirtualKeyCode::C => {
+                                let enabled = !camera.zoom_to_cursor();
+                                camera.set_zoom_to_cursor(enabled);
+                            },
+                            // Digits load a bookmarked view, with Ctrl they save the current one. Bookmarks
+                            // are orbit views, flying continues as orbiting around the point in front.
+                            _ if bookmark_slot(key).is_some() => {
+                                let slot = bookmark_slot(key).unwrap();
+                                if camera_mode == CameraMode::Fly {
+                                    camera.look_from(&fly_camera.camera());
+                                }
+                                camera_mode = CameraMode::Orbit;
+                                if modifiers.ctrl {
+                                    let view = camera.view(&perspective);
+                                    match bookmarks.set(slot, view) {
+                                        Ok(()) => println!("saved view {}: {}", slot, view),
+                                        Err(err) => println!("{}", err)
+                                    }
+                                } else {
+                                    match bookmarks.get(slot) {
+                                        Some(view) => camera.set_view(&view, &mut perspective),
+                                        None => println!("no view saved in slot {}", slot)
+                                    }
+                                }
+                            },
+                            // K appends the current view to the keyframes of the asset, P plays them or, without
+                            // keyframes, a turntable, and stops again
+                            winit::VirtualKeyCode::K => {
+                                if camera_mode == CameraMode::Fly {
+                                    camera.look_from(&fly_camera.camera());
+                                }
+                                camera_mode = CameraMode::Orbit;
+                                let time = if keyframes.is_empty() { 0.0 } else { keyframes.duration() + KEYFRAME_SPACING };
+                                keyframes.add(Keyframe::new(time, camera.view(&perspective)));
+                                match keyframes.save(&path_file) {
+                                    Ok(()) => println!("added keyframe {} at {}s to {}", keyframes.keyframes().len(), time, path_file.display()),
+                                    Err(err) => println!("{}", err)
+                                }
+                            },
+                            winit::VirtualKeyCode::P => camera_mode = match camera_mode {
+                                CameraMode::Path => CameraMode::Orbit,
+                                _ => {
+                                    playing = playback_path(&keyframes, options.turntable, &sphere, &perspective);
+                                    path_time = 0.0;
+                                    CameraMode::Path
+                                }
+                            },
+                            // R switches orbiting between turntable and arcball rotation
+                            winit::VirtualKeyCode::R => {
+                                let mode = match camera.rotation_mode() {
+                                    RotationMode::Turntable => RotationMode::Arcball,
+                                    RotationMode::Arcball => RotationMode::Turntable
+                                };
+                                camera.set_rotation_mode(mode);
+                            },
+                            _ => ()
+                        },
+                        winit::WindowEvent::MouseWheel { delta, .. } if camera_mode == CameraMode::Orbit => {
+                            let lines = match delta {
+                                winit::MouseScrollDelta::LineDelta(_, y) => y,
+                                winit::MouseScrollDelta::PixelDelta(_, y) => y / PIXELS_PER_LINE
+                            };
+                            if camera.zoom_to_cursor() {
+                                let model = coarse_pick_model.as_ref().unwrap_or(&pick_model);
+                                let point = cursor_point(&camera, &perspective, model, mouse_coords);
+                                camera.scroll_at(lines, point);
+                            } else {
+                                camera.scroll(lines);
+                            }
+                        },
                         winit::WindowEvent::MouseMoved { position: (x, y), .. } => {
                             mouse_coords.x = x as f32;
                             mouse_coords.y = y as f32;
-                            camera.update(mouse_coords);
+                            match camera_mode {
+                                CameraMode::Orbit => camera.update(mouse_coords),
+                                CameraMode::Fly => fly_camera.update(mouse_coords),
+                                CameraMode::Path => ()
+                            }
                         },
                         _ => ()
                     }
@@ -168,6 +481,132 @@ fn main() {
             }
         });
 
+        if let Some((vs, fs)) = shader_program.reload(&vulkan_init.device) {
+            match model_pipeline::build(vulkan_init.device.clone(), &vs, &fs, renderpass.clone()) {
+                Ok(new_pipeline) => pipeline = new_pipeline,
+                Err(err) => println!("{}", err)
+            }
+        }
+        if let Some(ref mut dof) = depth_of_field {
+            dof.reload(&vulkan_init.device);
+        }
+
+        for event in loader.poll() {
+            match event {
+                LoadEvent::Mesh(model) => {
+                    println!("bounds are: {:?}", model.bounds);
+                    bounds = model.bounds;
+                    sphere = model.bounding_sphere();
+                    if keep_view {
+                        keep_view = false;
+                    } else {
+                        camera.frame(&sphere, &perspective);
+                    }
+                    fly_camera.set_move_speed(sphere.radius);
+                    let lod = Lod { model: model, error: 0.0 };
+                    let (new_mesh, upload) = LodMesh::upload(&vulkan_init, &lod);
+                    previous_frame = Box::new(previous_frame.join(upload)) as Box<_>;
+                    lods = vec![new_mesh];
+                    pick_model = lod.model;
+                    coarse_pick_model = None;
+                },
+                LoadEvent::Lods(mut new_lods) => {
+                    for lod in &new_lods {
+                        let (new_mesh, upload) = LodMesh::upload(&vulkan_init, lod);
+                        previous_frame = Box::new(previous_frame.join(upload)) as Box<_>;
+                        lods.push(new_mesh);
+                    }
+                    if let Some(coarsest) = new_lods.pop() {
+                        coarse_pick_model = Some(coarsest.model);
+                    }
+                },
+                LoadEvent::MaterialSource(source) => material_source = Some(source),
+                LoadEvent::Material(new_material, data) => {
+                    let (new_textures, upload) = MaterialTextures::upload(&vulkan_init.transfer_queue, &data);
+                    previous_frame = Box::new(previous_frame.join(upload)) as Box<_>;
+                    material_textures = new_textures;
+                    material = new_material;
+                },
+                LoadEvent::Failed(err) => println!("loading failed: {}", err)
+            }
+        }
+
+        if let Some(new_material) = material_source.as_mut().and_then(|source| source.reload()) {
+            loader.reload_material(new_material);
+        }
+
+        // Rendering an image sequence starts once everything has loaded and plays the path at a
+        // fixed frame rate instead of the clock
+        if let Some(ref directory) = options.render {
+            if export_frame.is_none() && loader.status().is_none() {
+                std::fs::create_dir_all(directory).unwrap_or_else(|err| panic!("could not create {}: {}", directory.display(), err));
+                playing = playback_path(&keyframes, options.turntable, &sphere, &perspective);
+                camera_mode = CameraMode::Path;
+                export_frame = Some(0);
+            }
+        }
+        if camera_mode == CameraMode::Path {
+            path_time = match export_frame {
+                Some(frame) => frame as f32 / options.fps,
+                // Previews loop
+                None if path_time + frame_time > playing.duration() => 0.0,
+                None => path_time + frame_time
+            };
+        }
+
+        let view_camera = match camera_mode {
+            CameraMode::Orbit => {
+                camera.advance(frame_time);
+                camera.camera()
+            },
+            CameraMode::Fly => {
+                fly_camera.advance(frame_time);
+                fly_camera.camera()
+            },
+            CameraMode::Path => {
+                let pose = playing.sample(path_time);
+                perspective.fov = pose.fov;
+                // The orbit camera follows along, so stopping continues from the current view around
+                // the target of the keyframes
+                camera.set_distance(pose.distance).look_from(&pose.camera());
+                pose.camera()
+            }
+        };
+        if let Some(ref mut lens) = physical {
+            perspective.fov = lens.fov(perspective.aspect_ratio);
+            // Paths keep the orbit camera at the distance of their keyframes, so it focuses there too
+            if auto_focus && camera_mode != CameraMode::Fly {
+                lens.focus_distance = camera.distance();
+            }
+        }
+        let orthographic = camera_mode == CameraMode::Orbit && camera.is_orthographic();
+        let view: cgmath::Matrix4<f32> = view_camera.orthogonal();
+        perspective.fit_clip_planes(&bounds, &view, orthographic);
+
+        let lod = match lod_mode {
+            LodMode::Auto => {
+                let distance = (view_camera.position - sphere.center.to_vec()).magnitude();
+                auto_lod(&lods, distance, vulkan_init.dimensions[1] as f32, perspective.fov.to_radians())
+            },
+            LodMode::Manual(level) => level.min(lods.len() - 1)
+        };
+
+        let new_title = {
+            let mode = if lod_mode == LodMode::Auto { "auto" } else { "manual" };
+            let mut new_title = format!("{} - LOD {}/{} ({}), {} triangles", TITLE, lod, lods.len() - 1, mode, lods[lod].triangles);
+            if let Some(ref lens) = physical {
+                new_title.push_str(&format!(" - {}", lens));
+            }
+            if let Some(status) = loader.status() {
+                new_title.push_str(&format!(" - loading {}", status));
+            }
+            new_title
+        };
+        if new_title != title {
+            vulkan_init.window.window().set_title(&new_title);
+            title = new_title;
+        }
+
         if recreate_swapchain {
             vulkan_init.dimensions = {
                 let (new_width, new_height) = vulkan_init.window.window().get_inner_size_pixels().unwrap();
@@ -185,38 +624,58 @@ fn main() {
             std::mem::replace(&mut vulkan_init.swapchain, new_swapchain);
             std::mem::replace(&mut vulkan_init.images, new_images);
 
-            let new_depth_buffer = vulkano::image::attachment::AttachmentImage::transient(vulkan_init.device.clone(), vulkan_init.dimensions, vulkano::format::D16Unorm).unwrap();
-            std::mem::replace(&mut depth_buffer, new_depth_buffer);
-
-            framebuffers = None;
+            match depth_of_field {
+                Some(ref mut dof) => dof.resize(&vulkan_init, &renderpass),
+                None => {
+                    let new_depth_buffer = vulkano::image::attachment::AttachmentImage::transient(vulkan_init.device.clone(), vulkan_init.dimensions, vulkano::format::D32Sfloat).unwrap();
+                    std::mem::replace(&mut depth_buffer, Some(new_depth_buffer));
+                    framebuffers = None;
+                }
+            }
 
-            proj = cgmath::perspective(cgmath::Rad(std::f32::consts::FRAC_PI_2), { vulkan_init.dimensions[0] as f32 / vulkan_init.dimensions[1] as f32 }, 0.01, 100.0);
+            perspective.aspect_ratio = vulkan_init.dimensions[0] as f32 / vulkan_init.dimensions[1] as f32;
+            camera.set_viewport(Vector2::new(vulkan_init.dimensions[0] as f32, vulkan_init.dimensions[1] as f32));
 
             recreate_swapchain = false;
         }
 
         if framebuffers.is_none() {
-            let new_framebuffers = Some(vulkan_init.images.iter().map(|image| {
-                Arc::new(vulkano::framebuffer::Framebuffer::start(renderpass.clone())
-                    .add(image.clone()).unwrap()
-                    .add(depth_buffer.clone()).unwrap()
-                    .build().unwrap())
-            }).collect::<Vec<_>>());
-            std::mem::replace(&mut framebuffers, new_framebuffers);
+            if let Some(ref depth_buffer) = depth_buffer {
+                let new_framebuffers = Some(vulkan_init.images.iter().map(|image| {
+                    Arc::new(vulkano::framebuffer::Framebuffer::start(renderpass.clone())
+                        .add(image.clone()).unwrap()
+                        .add(depth_buffer.clone()).unwrap()
+                        .build().unwrap())
+                }).collect::<Vec<_>>());
+                std::mem::replace(&mut framebuffers, new_framebuffers);
+            }
         }
 
+        let proj = match camera_mode {
+            CameraMode::Orbit => camera.projection(&perspective),
+            CameraMode::Fly | CameraMode::Path => perspective.projection_reverse_z()
+        };
         let uniform_buffer_subbuffer = {
-            let uniform_data = vs::ty::Data {
-                world: camera.camera().orthogonal().into(),
-                view: (view * scale).into(),
+            let uniform_data = model_pipeline::Data {
+                world: scale.into(),
+                view: view.into(),
                 proj: proj.into(),
+                exposure: physical.map_or(1.0, |lens| lens.exposure())
             };
 
             uniform_buffer.next(uniform_data)
         };
 
+        let material_subbuffer = material_buffer.next(model_pipeline::MaterialData::from(&material));
+
         let set = Arc::new(vulkano::descriptor::descriptor_set::PersistentDescriptorSet::start(pipeline.clone(), 0)
             .add_buffer(uniform_buffer_subbuffer).unwrap()
+            .add_buffer(material_subbuffer).unwrap()
+            .add_sampled_image(material_textures.albedo_map.clone(), sampler.clone()).unwrap()
+            .add_sampled_image(material_textures.normal_map.clone(), sampler.clone()).unwrap()
+            .add_sampled_image(material_textures.ao_map.clone(), sampler.clone()).unwrap()
+            .add_sampled_image(material_textures.metallic_map.clone(), sampler.clone()).unwrap()
+            .add_sampled_image(material_textures.roughness_map.clone(), sampler.clone()).unwrap()
             .build().unwrap()
         );
 
@@ -230,13 +689,16 @@ fn main() {
             Err(err) => panic!("{:?}", err)
         };
 
-        let command_buffer = vulkano::command_buffer::AutoCommandBufferBuilder::primary_one_time_submit(vulkan_init.device.clone(), vulkan_init.queue.family()).unwrap()
-            .begin_render_pass(
-                framebuffers.as_ref().unwrap()[image_num].clone(), false,
-                vec![
-                    [0.0, 0.0, 1.0, 1.0].into(),
-                    1f32.into()
-                ]).unwrap()
+        let command_buffer = vulkano::command_buffer::AutoCommandBufferBuilder::primary_one_time_submit(vulkan_init.device.clone(), vulkan_init.queue.family()).unwrap();
+        let clear_values = vec![
+            [0.0, 0.0, 1.0, 1.0].into(),
+            0f32.into()
+        ];
+        let command_buffer = match depth_of_field {
+            Some(ref dof) => command_buffer.begin_render_pass(dof.scene_framebuffer(), false, clear_values),
+            None => command_buffer.begin_render_pass(framebuffers.as_ref().unwrap()[image_num].clone(), false, clear_values)
+        }.unwrap();
+        let mut command_buffer = command_buffer
             .draw_indexed(
                 pipeline.clone(),
                 vulkano::command_buffer::DynamicState {
@@ -248,57 +710,46 @@ fn main() {
                     }]),
                     scissors: None,
                 },
-                (vertex_buffer.clone(), normals_buffer.clone()),
-                index_buffer.clone(), set.clone(), ()).unwrap()
-            .end_render_pass().unwrap()
-            .build().unwrap();
+                vec![lods[lod].buffers.vertices.clone() as Arc<vulkano::buffer::BufferAccess + Send + Sync>],
+                lods[lod].buffers.indices.clone(), set.clone(), ()).unwrap()
+            .end_render_pass().unwrap();
+        if let (Some(dof), Some(lens)) = (depth_of_field.as_ref(), physical) {
+            let coc_scale = lens.coc_scale(perspective.aspect_ratio, vulkan_init.dimensions[1] as f32);
+            let data = DofData::new(proj.invert().unwrap().into(), vulkan_init.dimensions, lens.focus_distance, coc_scale);
+            command_buffer = dof.draw(command_buffer, image_num, vulkan_init.dimensions, data);
+        }
+
+        // Exported frames are copied out of the swapchain image before it is presented
+        let capture_buffer = export_frame.map(|_| capture::frame_buffer(&vulkan_init.queue, vulkan_init.dimensions));
+        if let Some(ref buffer) = capture_buffer {
+            command_buffer = command_buffer.copy_image_to_buffer(vulkan_init.images[image_num].clone(), buffer.clone()).unwrap();
+        }
+        let command_buffer = command_buffer.build().unwrap();
 
         let future = previous_frame.join(acquire_future)
             .then_execute(vulkan_init.queue.clone(), command_buffer).unwrap()
             .then_swapchain_present(vulkan_init.queue.clone(), vulkan_init.swapchain.clone(), image_num)
             .then_signal_fence_and_flush().unwrap();
+        if capture_buffer.is_some() {
+            future.wait(None).unwrap();
+        }
         previous_frame = Box::new(future) as Box<_>;
 
+        if let (Some(frame), Some(buffer), Some(directory)) = (export_frame, capture_buffer, options.render.as_ref()) {
+            // Releases the buffer now that the frame has finished
+            previous_frame.cleanup_finished();
+            let path = capture::frame_path(directory, frame);
+            capture::save_frame(&buffer.read().unwrap(), vulkan_init.dimensions, vulkan_init.swapchain.format(), &path)
+                .unwrap_or_else(|err| panic!("{}", err));
+
+            let frames = playing.frame_count(options.fps);
+            if frame + 1 >= frames {
+                println!("rendered {} frames to {}", frames, directory.display());
+                return;
+            }
+            export_frame = Some(frame + 1);
+        }
+
         if done { return; }
     }
 }
-
-mod vs {
-    #[derive(VulkanoShader)]
-    #[ty = "vertex"]
-    #[src = "
-#version 450
-layout(location = 0) in vec3 position;
-layout(location = 1) in vec3 normal;
-layout(location = 0) out vec3 v_normal;
-layout(set = 0, binding = 0) uniform Data {
-    mat4 world;
-    mat4 view;
-    mat4 proj;
-} uniforms;
-void main() {
-    mat4 worldview = uniforms.view * uniforms.world;
-    v_normal = transpose(inverse(mat3(worldview))) * normal;
-    gl_Position = uniforms.proj * worldview * vec4(position, 1.0);
-}
-"]
-    struct Dummy;
-}
-
-mod fs {
-    #[derive(VulkanoShader)]
-    #[ty = "fragment"]
-    #[src = "
-#version 450
-layout(location = 0) in vec3 v_normal;
-layout(location = 0) out vec4 f_color;
-const vec3 LIGHT = vec3(0.0, 0.0, 1.0);
-void main() {
-    float brightness = dot(normalize(v_normal), normalize(LIGHT));
-    vec3 dark_color = vec3(0.6, 0.0, 0.0);
-    vec3 regular_color = vec3(1.0, 0.0, 0.0);
-    f_color = vec4(mix(dark_color, regular_color, brightness), 1.0);
-}
-"]
-    struct Dummy;
-}
\ No newline at end of file
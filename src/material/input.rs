@@ -0,0 +1,174 @@
+use image;
+use image::{GrayImage, RgbaImage};
+
+use texture;
+
+use std::collections::HashMap;
+use std::path::{Path, PathBuf};
+
+#[derive(Debug, Copy, Clone, PartialEq, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum Channel {
+    R,
+    G,
+    B,
+    A
+}
+
+impl Channel {
+    pub fn index(&self) -> usize {
+        match *self {
+            Channel::R => 0,
+            Channel::G => 1,
+            Channel::B => 2,
+            Channel::A => 3
+        }
+    }
+}
+
+impl Default for Channel {
+    fn default() -> Channel {
+        Channel::R
+    }
+}
+
+// A scalar material input sourced from one channel of an image, e.g. roughness from the green
+// channel of a packed ORM texture, or from an inverted gloss map
+#[derive(Debug, Clone, PartialEq, Deserialize)]
+#[serde(from = "ScalarInputDef")]
+pub struct ScalarInput {
+    pub path: PathBuf,
+    pub channel: Channel,
+    pub invert: bool
+}
+
+// In a material file an input is either just a path or a table with channel and inversion
+#[derive(Deserialize)]
+#[serde(untagged)]
+enum ScalarInputDef {
+    Path(PathBuf),
+    Table {
+        path: PathBuf,
+        #[serde(default)]
+        channel: Channel,
+        #[serde(default)]
+        invert: bool
+    }
+}
+
+impl From<ScalarInputDef> for ScalarInput {
+    fn from(def: ScalarInputDef) -> ScalarInput {
+        match def {
+            ScalarInputDef::Path(path) => ScalarInput::new(path, Channel::R, false),
+            ScalarInputDef::Table { path, channel, invert } => ScalarInput::new(path, channel, invert)
+        }
+    }
+}
+
+impl ScalarInput {
+    pub fn new<P: AsRef<Path>>(path: P, channel: Channel, invert: bool) -> ScalarInput {
+        ScalarInput {
+            path: path.as_ref().to_path_buf(),
+            channel: channel,
+            invert: invert
+        }
+    }
+
+    pub fn extract(&self, image: &RgbaImage) -> GrayImage {
+        let channel = self.channel.index();
+        let invert = self.invert;
+        GrayImage::from_fn(image.width(), image.height(), |x, y| {
+            let value = image.get_pixel(x, y).data[channel];
+            image::Luma { data: [if invert { 255 - value } else { value }] }
+        })
+    }
+}
+
+// Decodes every source image once, so the three inputs of a packed ORM texture share one decode
+pub struct ImageCache {
+    images: HashMap<PathBuf, RgbaImage>
+}
+
+impl ImageCache {
+    pub fn new() -> ImageCache {
+        ImageCache {
+            images: HashMap::new()
+        }
+    }
+
+    pub fn get(&mut self, path: &Path) -> Result<&RgbaImage, String> {
+        if !self.images.contains_key(path) {
+            let image = texture::load_rgba(path)?;
+            self.images.insert(path.to_path_buf(), image);
+        }
+
+        Ok(&self.images[path])
+    }
+
+    pub fn scalar(&mut self, input: &ScalarInput) -> Result<GrayImage, String> {
+        self.get(&input.path).map(|image| input.extract(image))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    use material::MaterialMaps;
+    use toml;
+
+    // Two pixels with a distinct value in every channel
+    fn packed() -> RgbaImage {
+        RgbaImage::from_raw(2, 1, vec![10, 20, 30, 40, 200, 150, 100, 0]).unwrap()
+    }
+
+    fn values(image: &GrayImage) -> Vec<u8> {
+        image.pixels().map(|p| p.data[0]).collect()
+    }
+
+    #[test]
+    fn extract_selects_and_inverts_channels() {
+        let image = packed();
+        let channels = [Channel::R, Channel::G, Channel::B, Channel::A];
+        let extracted: Vec<_> = channels.iter().map(|&c| values(&ScalarInput::new("packed.png", c, false).extract(&image))).collect();
+        assert_eq!(extracted, vec![vec![10, 200], vec![20, 150], vec![30, 100], vec![40, 0]]);
+
+        let gloss = ScalarInput::new("packed.png", Channel::G, true).extract(&image);
+        assert_eq!((gloss.width(), gloss.height()), (2, 1));
+        assert_eq!(values(&gloss), vec![235, 105]);
+    }
+
+    #[test]
+    fn packed_orm_fills_occlusion_roughness_and_metallic() {
+        let mut maps = MaterialMaps::default();
+        maps.orm = Some(PathBuf::from("orm.png"));
+        // A separate roughness map takes precedence over the packed one
+        maps.roughness = Some(ScalarInput::new("gloss.png", Channel::R, true));
+        maps.expand_packed();
+
+        let image = packed();
+        assert_eq!(values(&maps.ao.as_ref().unwrap().extract(&image)), vec![10, 200]);
+        assert_eq!(values(&maps.metallic.as_ref().unwrap().extract(&image)), vec![30, 100]);
+        assert_eq!(maps.roughness, Some(ScalarInput::new("gloss.png", Channel::R, true)));
+
+        maps.roughness = None;
+        maps.expand_packed();
+        assert_eq!(maps.roughness, Some(ScalarInput::new("orm.png", Channel::G, false)));
+        assert_eq!(values(&maps.roughness.as_ref().unwrap().extract(&image)), vec![20, 150]);
+    }
+
+    #[test]
+    fn inputs_deserialize_from_a_path_or_a_table() {
+        let maps: MaterialMaps = toml::from_str(r#"
+            ao = "ao.png"
+            metallic = { path = "mask.png", channel = "b" }
+            roughness = { path = "gloss.png", invert = true }
+        "#).unwrap();
+
+        assert_eq!(maps.ao, Some(ScalarInput::new("ao.png", Channel::R, false)));
+        assert_eq!(maps.metallic, Some(ScalarInput::new("mask.png", Channel::B, false)));
+        assert_eq!(maps.roughness, Some(ScalarInput::new("gloss.png", Channel::R, true)));
+
+        assert!(toml::from_str::<MaterialMaps>(r#"ao = { path = "ao.png", channel = "x" }"#).is_err());
+    }
+}
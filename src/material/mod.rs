@@ -0,0 +1,478 @@
+pub mod scan;
+pub mod input;
+pub mod textures;
+
+use toml;
+
+use file_watch::FileWatcher;
+use megascans::MegascansAsset;
+
+use self::input::{Channel, ScalarInput};
+
+use std::f32;
+use std::fmt;
+use std::fs::File;
+use std::io::Read;
+use std::path::{Path, PathBuf};
+
+#[derive(Debug, Copy, Clone, PartialEq, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum NormalConvention {
+    // Green channel points up (+Y), as used by Blender, Maya and glTF
+    OpenGl,
+    // Green channel points down (-Y), as used by Unreal and 3ds Max
+    DirectX
+}
+
+impl Default for NormalConvention {
+    fn default() -> NormalConvention {
+        NormalConvention::OpenGl
+    }
+}
+
+#[derive(Debug, Clone, Default, Deserialize)]
+#[serde(default)]
+pub struct MaterialMaps {
+    pub albedo: Option<PathBuf>,
+    pub normal: Option<PathBuf>,
+    pub ao: Option<ScalarInput>,
+    pub metallic: Option<ScalarInput>,
+    pub roughness: Option<ScalarInput>,
+    // Packed occlusion (R), roughness (G) and metallic (B), fills the scalar inputs not given separately
+    pub orm: Option<PathBuf>
+}
+
+fn resolve_path(path: &mut PathBuf, base: &Path) {
+    if path.is_relative() {
+        let resolved = base.join(&*path);
+        *path = resolved;
+    }
+}
+
+impl MaterialMaps {
+    pub fn iter(&self) -> Vec<(&'static str, &Path)> {
+        let maps = [
+            ("albedo", self.albedo.as_ref()),
+            ("normal", self.normal.as_ref()),
+            ("orm", self.orm.as_ref()),
+            ("ao", self.ao.as_ref().map(|i| &i.path)),
+            ("metallic", self.metallic.as_ref().map(|i| &i.path)),
+            ("roughness", self.roughness.as_ref().map(|i| &i.path))
+        ];
+        maps.iter().filter_map(|&(name, path)| path.map(|p| (name, p.as_path()))).collect()
+    }
+
+    pub fn expand_packed(&mut self) {
+        if let Some(ref orm) = self.orm {
+            if self.ao.is_none() {
+                self.ao = Some(ScalarInput::new(orm, Channel::R, false));
+            }
+            if self.roughness.is_none() {
+                self.roughness = Some(ScalarInput::new(orm, Channel::G, false));
+            }
+            if self.metallic.is_none() {
+                self.metallic = Some(ScalarInput::new(orm, Channel::B, false));
+            }
+        }
+    }
+
+    fn resolve(&mut self, base: &Path) {
+        for map in vec![&mut self.albedo, &mut self.normal, &mut self.orm] {
+            if let Some(ref mut path) = *map {
+                resolve_path(path, base);
+            }
+        }
+        for input in vec![&mut self.ao, &mut self.metallic, &mut self.roughness] {
+            if let Some(ref mut input) = *input {
+                resolve_path(&mut input.path, base);
+            }
+        }
+        self.expand_packed();
+    }
+}
+
+#[derive(Debug, Copy, Clone, Deserialize)]
+#[serde(default)]
+pub struct MaterialFactors {
+    pub base_color: [f32; 4],
+    pub metallic: f32,
+    pub roughness: f32
+}
+
+impl Default for MaterialFactors {
+    fn default() -> MaterialFactors {
+        MaterialFactors {
+            base_color: [1.0, 1.0, 1.0, 1.0],
+            metallic: 0.0,
+            roughness: 1.0
+        }
+    }
+}
+
+#[derive(Debug, Copy, Clone, Deserialize)]
+#[serde(default)]
+pub struct UvTransform {
+    pub tiling: [f32; 2],
+    pub offset: [f32; 2]
+}
+
+impl Default for UvTransform {
+    fn default() -> UvTransform {
+        UvTransform {
+            tiling: [1.0, 1.0],
+            offset: [0.0, 0.0]
+        }
+    }
+}
+
+#[derive(Debug, Copy, Clone, Deserialize)]
+#[serde(default)]
+pub struct NormalSettings {
+    pub strength: f32,
+    pub convention: NormalConvention
+}
+
+impl Default for NormalSettings {
+    fn default() -> NormalSettings {
+        NormalSettings {
+            strength: 1.0,
+            convention: NormalConvention::OpenGl
+        }
+    }
+}
+
+#[derive(Debug, Clone, Default, Deserialize)]
+#[serde(default)]
+pub struct Material {
+    pub maps: MaterialMaps,
+    pub factors: MaterialFactors,
+    pub uv: UvTransform,
+    pub normal: NormalSettings
+}
+
+// Every table and key the material format understands, anything else is reported
+const INPUT_KEYS: &'static [&'static str] = &["path", "channel", "invert"];
+
+const SCHEMA: &'static [(&'static str, &'static [&'static str])] = &[
+    ("maps", &["albedo", "normal", "ao", "metallic", "roughness", "orm"]),
+    ("factors", &["base_color", "metallic", "roughness"]),
+    ("uv", &["tiling", "offset"]),
+    ("normal", &["strength", "convention"])
+];
+
+#[derive(Debug, Default)]
+pub struct MaterialReport {
+    pub unknown_keys: Vec<String>,
+    pub missing_textures: Vec<(String, PathBuf)>,
+    // Key and value as given, the material holds the clamped value
+    pub out_of_range: Vec<(String, f32)>
+}
+
+impl MaterialReport {
+    pub fn is_clean(&self) -> bool {
+        self.unknown_keys.is_empty() && self.missing_textures.is_empty() && self.out_of_range.is_empty()
+    }
+}
+
+impl fmt::Display for MaterialReport {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        for key in &self.unknown_keys {
+            writeln!(f, "unknown key '{}'", key)?;
+        }
+        for &(ref map, ref path) in &self.missing_textures {
+            writeln!(f, "missing texture for {}: {}", map, path.display())?;
+        }
+        for &(ref key, value) in &self.out_of_range {
+            writeln!(f, "{} = {} is out of range, clamped", key, value)?;
+        }
+        Ok(())
+    }
+}
+
+fn validate_keys(value: &toml::Value, report: &mut MaterialReport) {
+    let root = match value.as_table() {
+        Some(table) => table,
+        None => return
+    };
+
+    for (table_name, table) in root {
+        let known = match SCHEMA.iter().find(|&&(name, _)| name == table_name) {
+            Some(&(_, keys)) => keys,
+            None => {
+                report.unknown_keys.push(table_name.clone());
+                continue;
+            }
+        };
+
+        match table.as_table() {
+            Some(table) => for (key, value) in table {
+                if !known.contains(&key.as_str()) {
+                    report.unknown_keys.push(format!("{}.{}", table_name, key));
+                } else if let Some(input) = value.as_table() {
+                    for input_key in input.keys().filter(|k| !INPUT_KEYS.contains(&k.as_str())) {
+                        report.unknown_keys.push(format!("{}.{}.{}", table_name, key, input_key));
+                    }
+                }
+            },
+            None => report.unknown_keys.push(table_name.clone())
+        }
+    }
+}
+
+// Clamps a value into [min, max], reporting it under the given key when it was outside
+fn clamp_factor(key: &str, value: &mut f32, min: f32, max: f32, report: &mut MaterialReport) {
+    if !(*value >= min && *value <= max) {
+        report.out_of_range.push((key.to_string(), *value));
+        *value = if *value > max { max } else { min };
+    }
+}
+
+impl Material {
+    // Colors and scalar factors are fractions, normal strength scales the tangent space offset
+    fn clamp_factors(&mut self, report: &mut MaterialReport) {
+        for (i, channel) in self.factors.base_color.iter_mut().enumerate() {
+            clamp_factor(&format!("factors.base_color[{}]", i), channel, 0.0, 1.0, report);
+        }
+        clamp_factor("factors.metallic", &mut self.factors.metallic, 0.0, 1.0, report);
+        clamp_factor("factors.roughness", &mut self.factors.roughness, 0.0, 1.0, report);
+        clamp_factor("normal.strength", &mut self.normal.strength, 0.0, f32::INFINITY, report);
+    }
+
+    pub fn parse(source: &str, base: &Path) -> Result<(Material, MaterialReport), String> {
+        let value = source.parse::<toml::Value>().map_err(|e| e.to_string())?;
+
+        let mut report = MaterialReport::default();
+        validate_keys(&value, &mut report);
+
+        let mut material: Material = value.try_into().map_err(|e| e.to_string())?;
+        material.clamp_factors(&mut report);
+        material.maps.resolve(base);
+
+        for (name, path) in material.maps.iter() {
+            if !path.is_file() {
+                report.missing_textures.push((name.to_string(), path.to_path_buf()));
+            }
+        }
+
+        Ok((material, report))
+    }
+
+    pub fn from_file<P: AsRef<Path>>(path: P) -> Result<(Material, MaterialReport), String> {
+        let path = path.as_ref();
+        let mut source = String::new();
+        File::open(path)
+            .and_then(|mut f| f.read_to_string(&mut source))
+            .map_err(|e| format!("{}: {}", path.display(), e))?;
+
+        let base = path.parent().unwrap_or(Path::new("."));
+        Material::parse(&source, base).map_err(|e| format!("{}: {}", path.display(), e))
+    }
+}
+
+enum MaterialOrigin {
+    File,
+    Scan,
+    Megascans
+}
+
+pub struct MaterialSource {
+    path: PathBuf,
+    origin: MaterialOrigin,
+    watcher: FileWatcher
+}
+
+impl MaterialSource {
+    pub fn new<P: AsRef<Path>>(path: P) -> MaterialSource {
+        MaterialSource {
+            path: path.as_ref().to_path_buf(),
+            origin: MaterialOrigin::File,
+            watcher: FileWatcher::new()
+        }
+    }
+
+    // Builds the material from the texture naming conventions in a folder or for a base name
+    pub fn scan<P: AsRef<Path>>(base: P) -> MaterialSource {
+        MaterialSource {
+            path: base.as_ref().to_path_buf(),
+            origin: MaterialOrigin::Scan,
+            watcher: FileWatcher::new()
+        }
+    }
+
+    // Takes the maps listed in a Megascans JSON sidecar
+    pub fn megascans<P: AsRef<Path>>(path: P) -> MaterialSource {
+        MaterialSource {
+            path: path.as_ref().to_path_buf(),
+            origin: MaterialOrigin::Megascans,
+            watcher: FileWatcher::new()
+        }
+    }
+
+    pub fn load(&mut self) -> Result<Material, String> {
+        self.watcher.clear();
+        self.watcher.watch(&self.path);
+
+        let material = match self.origin {
+            MaterialOrigin::File => {
+                let (material, report) = Material::from_file(&self.path)?;
+                if !report.is_clean() {
+                    print!("material {} has problems:\n{}", self.path.display(), report);
+                }
+                material
+            },
+            MaterialOrigin::Scan => {
+                // Watching the folder picks up textures that are added or removed
+                let (folder, _) = scan::split_base(&self.path);
+                self.watcher.watch(&folder);
+
+                let (material, report) = scan::scan_material(&self.path);
+                print!("textures for {}:\n{}", self.path.display(), report);
+                material
+            },
+            MaterialOrigin::Megascans => MegascansAsset::from_file(&self.path)?.material
+        };
+
+        // Texture edits should trigger a reload just like edits of the material itself
+        for (_, path) in material.maps.iter() {
+            self.watcher.watch(path);
+        }
+
+        Ok(material)
+    }
+
+    pub fn reload(&mut self) -> Option<Material> {
+        if !self.watcher.changed() {
+            return None;
+        }
+
+        match self.load() {
+            Ok(material) => {
+                println!("reloaded material {}", self.path.display());
+                Some(material)
+            },
+            Err(err) => {
+                println!("failed to reload material, keeping the previous one:\n{}", err);
+                None
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    use std::env;
+    use std::fs;
+    use std::process;
+
+    // A folder of its own for every test, so tests running in parallel do not share files
+    fn test_folder(name: &str) -> PathBuf {
+        let folder = env::temp_dir().join(format!("vulkan-test-material-{}-{}", name, process::id()));
+        fs::create_dir_all(&folder).unwrap();
+        folder
+    }
+
+    #[test]
+    fn valid_file_parses_cleanly() {
+        let folder = test_folder("valid");
+        fs::write(folder.join("wood_albedo.png"), b"").unwrap();
+        fs::write(folder.join("wood_orm.png"), b"").unwrap();
+
+        let source = r#"
+            [maps]
+            albedo = "wood_albedo.png"
+            orm = "wood_orm.png"
+            roughness = { path = "wood_orm.png", channel = "a", invert = true }
+
+            [factors]
+            base_color = [0.5, 0.25, 1.0, 1.0]
+            metallic = 1.0
+
+            [uv]
+            tiling = [2.0, 4.0]
+
+            [normal]
+            convention = "directx"
+        "#;
+        let (material, report) = Material::parse(source, &folder).unwrap();
+        fs::remove_dir_all(&folder).unwrap();
+
+        assert!(report.is_clean(), "{}", report);
+        assert_eq!(material.maps.albedo, Some(folder.join("wood_albedo.png")));
+        // Given inputs win over the packed texture, the others are filled from it
+        assert_eq!(material.maps.roughness, Some(ScalarInput::new(folder.join("wood_orm.png"), Channel::A, true)));
+        assert_eq!(material.maps.metallic, Some(ScalarInput::new(folder.join("wood_orm.png"), Channel::B, false)));
+        assert_eq!(material.factors.base_color, [0.5, 0.25, 1.0, 1.0]);
+        assert_eq!((material.factors.metallic, material.factors.roughness), (1.0, 1.0));
+        assert_eq!((material.uv.tiling, material.uv.offset), ([2.0, 4.0], [0.0, 0.0]));
+        assert!(material.normal.convention == NormalConvention::DirectX);
+    }
+
+    #[test]
+    fn unknown_keys_are_reported() {
+        let source = r#"
+            shininess = 3.0
+
+            [factors]
+            roughnes = 0.5
+
+            [maps]
+            roughness = { path = "gloss.png", invrt = true }
+        "#;
+        let (material, report) = Material::parse(source, Path::new("/nonexistent")).unwrap();
+
+        let mut unknown = report.unknown_keys.clone();
+        unknown.sort();
+        assert_eq!(unknown, vec!["factors.roughnes", "maps.roughness.invrt", "shininess"]);
+        assert_eq!(material.factors.roughness, 1.0);
+    }
+
+    #[test]
+    fn out_of_range_factors_are_clamped_and_reported() {
+        let source = r#"
+            [factors]
+            base_color = [1.5, 0.5, -0.25, 1.0]
+            roughness = 2.0
+
+            [normal]
+            strength = -1.0
+        "#;
+        let (material, report) = Material::parse(source, Path::new(".")).unwrap();
+
+        assert_eq!(report.out_of_range, vec![
+            ("factors.base_color[0]".to_string(), 1.5),
+            ("factors.base_color[2]".to_string(), -0.25),
+            ("factors.roughness".to_string(), 2.0),
+            ("normal.strength".to_string(), -1.0)
+        ]);
+        assert_eq!(material.factors.base_color, [1.0, 0.5, 0.0, 1.0]);
+        assert_eq!((material.factors.roughness, material.normal.strength), (1.0, 0.0));
+
+        // A value of the wrong type is an error, not a report
+        assert!(Material::parse("[factors]\nmetallic = \"high\"", Path::new(".")).is_err());
+    }
+
+    #[test]
+    fn missing_textures_are_reported_with_their_resolved_path() {
+        let folder = test_folder("missing");
+        fs::write(folder.join("rock_normal.png"), b"").unwrap();
+
+        let source = r#"
+            [maps]
+            normal = "rock_normal.png"
+            albedo = "rock_albedo.png"
+            ao = { path = "textures/rock_ao.png", channel = "g" }
+        "#;
+        let (material, report) = Material::parse(source, &folder).unwrap();
+        fs::remove_dir_all(&folder).unwrap();
+
+        assert_eq!(report.missing_textures, vec![
+            ("albedo".to_string(), folder.join("rock_albedo.png")),
+            ("ao".to_string(), folder.join("textures/rock_ao.png"))
+        ]);
+        // Missing maps are kept, so the material picks them up once they appear
+        assert_eq!(material.maps.albedo, Some(folder.join("rock_albedo.png")));
+        assert!(report.unknown_keys.is_empty() && report.out_of_range.is_empty());
+    }
+}
@@ -0,0 +1,250 @@
+use material::{Material, MaterialMaps, NormalConvention};
+use material::input::{Channel, ScalarInput};
+
+use std::fmt;
+use std::fs;
+use std::path::{Path, PathBuf};
+
+pub const IMAGE_EXTENSIONS: &'static [&'static str] = &["jpg", "jpeg", "png", "tga", "tif", "tiff", "bmp"];
+
+// Suffixes are compared with separators removed, so "Base_Color" and "BaseColor" both match "basecolor".
+// Earlier entries win when several files match the same slot.
+const ALBEDO: &'static [&'static str] = &["albedo", "basecolor", "diffuse", "diff", "color", "col"];
+const NORMAL: &'static [&'static str] = &["normalgl", "normal", "normaldx", "nrm", "nor", "norm"];
+const NORMAL_DIRECTX: usize = 2;
+const AO: &'static [&'static str] = &["ao", "ambientocclusion", "occlusion", "occ"];
+const METALLIC: &'static [&'static str] = &["metallic", "metalness", "metal", "met"];
+const ROUGHNESS: &'static [&'static str] = &["roughness", "rough", "rgh"];
+const GLOSS: &'static [&'static str] = &["glossiness", "gloss"];
+// Occlusion, roughness and metallic packed into R, G and B
+const ORM: &'static [&'static str] = &["orm", "arm"];
+
+const SLOTS: [(&'static str, &'static [&'static str]); 7] = [
+    ("albedo", ALBEDO),
+    ("normal", NORMAL),
+    ("ao", AO),
+    ("metallic", METALLIC),
+    ("roughness", ROUGHNESS),
+    ("gloss", GLOSS),
+    ("orm", ORM)
+];
+
+// Resolution and variant tokens that vendors append after the map name, e.g. "_Albedo_4K" or "_Normal_LOD0"
+fn is_noise_token(token: &str) -> bool {
+    let digits = token.trim_end_matches('k');
+    (!digits.is_empty() && digits.chars().all(|c| c.is_digit(10))) || token.starts_with("lod")
+}
+
+fn suffix_tokens(stem: &str) -> Vec<String> {
+    let mut tokens: Vec<String> = stem.to_ascii_lowercase()
+        .split(|c| c == '_' || c == '-' || c == ' ' || c == '.')
+        .filter(|t| !t.is_empty())
+        .map(|t| t.to_string())
+        .collect();
+
+    while tokens.last().map_or(false, |t| is_noise_token(t)) {
+        tokens.pop();
+    }
+
+    tokens
+}
+
+// Returns the priority of the best pattern matching the last one or two tokens of the file name
+fn match_suffix(tokens: &[String], patterns: &[&str]) -> Option<usize> {
+    let n = tokens.len();
+    let mut candidates = Vec::new();
+    if n >= 1 {
+        candidates.push(tokens[n - 1].clone());
+    }
+    if n >= 2 {
+        candidates.push(format!("{}{}", tokens[n - 2], tokens[n - 1]));
+    }
+
+    patterns.iter().position(|p| candidates.iter().any(|c| c == p))
+}
+
+pub fn has_extension(path: &Path, extensions: &[&str]) -> bool {
+    path.extension()
+        .and_then(|e| e.to_str())
+        .map_or(false, |e| extensions.contains(&e.to_ascii_lowercase().as_str()))
+}
+
+// Splits a folder or a base name like "assets/Aset_wood_stump_M_okfch" into the folder and file prefix
+pub fn split_base(base: &Path) -> (PathBuf, String) {
+    if base.is_dir() {
+        (base.to_path_buf(), String::new())
+    } else {
+        let folder = base.parent().map_or(PathBuf::from("."), |p| p.to_path_buf());
+        let prefix = base.file_name().map_or(String::new(), |n| n.to_string_lossy().into_owned());
+        (folder, prefix)
+    }
+}
+
+pub fn find_files(base: &Path, extensions: &[&str]) -> Vec<PathBuf> {
+    let (folder, prefix) = split_base(base);
+    let prefix = prefix.to_ascii_lowercase();
+
+    let mut files: Vec<PathBuf> = match fs::read_dir(&folder) {
+        Ok(entries) => entries.filter_map(|e| e.ok()).map(|e| e.path()).collect(),
+        Err(_) => return Vec::new()
+    };
+
+    files.retain(|p| {
+        p.is_file() && has_extension(p, extensions) &&
+            p.file_name().map_or(false, |n| n.to_string_lossy().to_ascii_lowercase().starts_with(&prefix))
+    });
+    files.sort();
+    files
+}
+
+#[derive(Debug, Default)]
+pub struct ScanReport {
+    pub found: Vec<(&'static str, PathBuf)>,
+    pub defaulted: Vec<&'static str>,
+    pub ignored: Vec<PathBuf>
+}
+
+impl fmt::Display for ScanReport {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        for &(slot, ref path) in &self.found {
+            writeln!(f, "  {:<10} {}", slot, path.display())?;
+        }
+        for slot in &self.defaulted {
+            writeln!(f, "  {:<10} (defaulted to the material factor)", slot)?;
+        }
+        for path in &self.ignored {
+            writeln!(f, "  ignored    {}", path.display())?;
+        }
+        Ok(())
+    }
+}
+
+// Fills every map slot of a material from the images in a folder or sharing a base name,
+// slots without a matching image keep the default factors
+pub fn scan_material(base: &Path) -> (Material, ScanReport) {
+    let mut best: [Option<(usize, PathBuf)>; 7] = [None, None, None, None, None, None, None];
+    let mut report = ScanReport::default();
+
+    for file in find_files(base, IMAGE_EXTENSIONS) {
+        let tokens = match file.file_stem() {
+            Some(stem) => suffix_tokens(&stem.to_string_lossy()),
+            None => continue
+        };
+
+        let mut matched = false;
+        for (slot, &(_, patterns)) in SLOTS.iter().enumerate() {
+            if let Some(priority) = match_suffix(&tokens, patterns) {
+                matched = true;
+                if best[slot].as_ref().map_or(true, |&(p, _)| priority < p) {
+                    best[slot] = Some((priority, file.clone()));
+                }
+            }
+        }
+
+        if !matched {
+            report.ignored.push(file);
+        }
+    }
+
+    let path = |slot: usize| best[slot].as_ref().map(|&(_, ref path)| path.clone());
+    let scalar = |slot: usize, invert: bool| path(slot).map(|p| ScalarInput::new(p, Channel::R, invert));
+
+    let mut maps = MaterialMaps {
+        albedo: path(0),
+        normal: path(1),
+        ao: scalar(2, false),
+        metallic: scalar(3, false),
+        // Gloss is only used when there is no roughness map, inverted it is the same thing
+        roughness: scalar(4, false).or_else(|| scalar(5, true)),
+        orm: path(6)
+    };
+    maps.expand_packed();
+
+    let found = [
+        ("albedo", maps.albedo.clone()),
+        ("normal", maps.normal.clone()),
+        ("ao", maps.ao.as_ref().map(|i| i.path.clone())),
+        ("metallic", maps.metallic.as_ref().map(|i| i.path.clone())),
+        ("roughness", maps.roughness.as_ref().map(|i| i.path.clone()))
+    ];
+    for &(name, ref path) in found.iter() {
+        match *path {
+            Some(ref p) => report.found.push((name, p.clone())),
+            None => report.defaulted.push(name)
+        }
+    }
+
+    let mut material = Material::default();
+    if best[1].as_ref().map_or(false, |&(priority, _)| priority == NORMAL_DIRECTX) {
+        material.normal.convention = NormalConvention::DirectX;
+    }
+    material.maps = maps;
+
+    (material, report)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    use std::env;
+    use std::process;
+
+    fn slots_of(name: &str) -> Vec<&'static str> {
+        let stem = Path::new(name).file_stem().unwrap().to_string_lossy().into_owned();
+        let tokens = suffix_tokens(&stem);
+        SLOTS.iter().filter(|&&(_, patterns)| match_suffix(&tokens, patterns).is_some()).map(|&(slot, _)| slot).collect()
+    }
+
+    #[test]
+    fn file_names_map_to_slots() {
+        let table: &[(&str, &[&str])] = &[
+            ("Aset_wood_stump_M_okfch_4K_Albedo.jpg", &["albedo"]),
+            ("wood_BaseColor.png", &["albedo"]),
+            ("Wood_Base_Color_2K.png", &["albedo"]),
+            ("wood_diff_1k.jpg", &["albedo"]),
+            ("rock_Normal_LOD0.jpg", &["normal"]),
+            ("rock_Normal_DX.png", &["normal"]),
+            ("rock-nrm.tga", &["normal"]),
+            ("rock_AO.png", &["ao"]),
+            ("rock_Ambient_Occlusion.png", &["ao"]),
+            ("rock_Metalness.tif", &["metallic"]),
+            ("rock_Roughness_8K.jpg", &["roughness"]),
+            ("rock_Gloss.jpg", &["gloss"]),
+            ("rock_ORM.png", &["orm"]),
+            ("rock_arm_4k.png", &["orm"]),
+            ("rock_Preview.png", &[]),
+            ("rock_4K.png", &[])
+        ];
+        for &(name, slots) in table {
+            assert_eq!(slots_of(name), slots, "{}", name);
+        }
+    }
+
+    #[test]
+    fn scan_fills_slots_from_a_folder() {
+        let folder = env::temp_dir().join(format!("vulkan-test-material-scan-{}", process::id()));
+        fs::create_dir_all(&folder).unwrap();
+        for name in &["rock_Albedo_4K.jpg", "rock_Normal_DX.png", "rock_Gloss.jpg", "rock_ORM.png", "rock_Preview.png"] {
+            fs::write(folder.join(name), b"").unwrap();
+        }
+
+        let (material, report) = scan_material(&folder.join("rock"));
+        let maps = &material.maps;
+        assert_eq!(maps.albedo, Some(folder.join("rock_Albedo_4K.jpg")));
+        assert_eq!(maps.normal, Some(folder.join("rock_Normal_DX.png")));
+        assert!(material.normal.convention == NormalConvention::DirectX);
+
+        // Gloss fills roughness before the packed map does, ORM fills the rest
+        let roughness = maps.roughness.as_ref().unwrap();
+        assert!(roughness.path == folder.join("rock_Gloss.jpg") && roughness.invert);
+        let ao = maps.ao.as_ref().unwrap();
+        assert!(ao.path == folder.join("rock_ORM.png") && ao.channel == Channel::R);
+        let metallic = maps.metallic.as_ref().unwrap();
+        assert!(metallic.path == folder.join("rock_ORM.png") && metallic.channel == Channel::B);
+
+        assert_eq!(report.ignored, vec![folder.join("rock_Preview.png")]);
+        assert!(report.defaulted.is_empty());
+        fs::remove_dir_all(&folder).unwrap();
+    }
+}
@@ -0,0 +1,231 @@
+use material::Material;
+use material::input::{Channel, ImageCache, ScalarInput};
+use texture;
+use texture::{bc, cache, TextureData, TextureFormat};
+use texture::mipmap;
+
+use std::path::{Path, PathBuf};
+
+// The mip chains of every material slot, ready for upload. Slots without a map get a 1x1
+// texture that leaves the material factor unchanged.
+pub struct MaterialTextureData {
+    pub albedo: TextureData,
+    pub normal: TextureData,
+    pub ao: TextureData,
+    pub metallic: TextureData,
+    pub roughness: TextureData
+}
+
+fn constant(format: TextureFormat, pixel: &[u8]) -> TextureData {
+    TextureData {
+        format: format,
+        width: 1,
+        height: 1,
+        faces: 1,
+        levels: vec![pixel.to_vec()]
+    }
+}
+
+// KTX2 and DDS files already carry their mips and possibly a compressed format, they are used as they are.
+// `srgb` tells color from data maps for containers that do not say.
+fn container(path: &Path, srgb: bool) -> Result<Option<TextureData>, String> {
+    if texture::is_container(path) {
+        texture::load_container(path, srgb).map(Some)
+    } else {
+        Ok(None)
+    }
+}
+
+fn scalar_container(input: &ScalarInput) -> Result<Option<TextureData>, String> {
+    if input.channel == Channel::R && !input.invert {
+        container(&input.path, false)
+    } else {
+        Ok(None)
+    }
+}
+
+fn is_scalar_container(input: &ScalarInput) -> bool {
+    input.channel == Channel::R && !input.invert && texture::is_container(&input.path)
+}
+
+// The files a slot is generated from and how they are read, together they form the cache key.
+// Roughness depends on the normal map as well, since its mips are widened by the normal variance.
+fn slot_sources<'a>(material: &'a Material, slot: &str) -> Option<(Vec<&'a Path>, String)> {
+    let maps = &material.maps;
+    let scalar = |input: &'a ScalarInput| (vec![input.path.as_path()], format!("{}:{:?}:{}", slot, input.channel, input.invert));
+
+    match slot {
+        "albedo" => maps.albedo.as_ref().filter(|p| !texture::is_container(p)).map(|p| (vec![p.as_path()], "albedo".to_string())),
+        "normal" => maps.normal.as_ref().filter(|p| !texture::is_container(p)).map(|p| (vec![p.as_path()], "normal".to_string())),
+        "ao" => maps.ao.as_ref().filter(|i| !is_scalar_container(i)).map(scalar),
+        "metallic" => maps.metallic.as_ref().filter(|i| !is_scalar_container(i)).map(scalar),
+        "roughness" => maps.roughness.as_ref().filter(|i| !is_scalar_container(i)).map(|input| {
+            let (mut sources, mut descriptor) = scalar(input);
+            if let Some(normal) = maps.normal.as_ref().filter(|p| !texture::is_container(p)) {
+                sources.push(normal.as_path());
+                descriptor.push_str(":normal");
+            }
+            (sources, descriptor)
+        }),
+        _ => None
+    }
+}
+
+pub const SLOTS: [&'static str; 5] = ["albedo", "normal", "ao", "metallic", "roughness"];
+
+fn cached(material: &Material, slot: &str) -> Option<TextureData> {
+    let (sources, descriptor) = slot_sources(material, slot)?;
+    let key = cache::key(&sources, &descriptor).ok()?;
+    cache::lookup(sources[0], slot, &key)
+}
+
+// With `fallback` a map that cannot be read is reported and replaced by the texture that leaves the
+// factor unchanged, otherwise its error is returned
+fn or_factor<T>(slot: &str, result: Result<T, String>, factor: T, fallback: bool) -> Result<T, String> {
+    match result {
+        Err(ref err) if fallback => {
+            println!("{} map: {}\nfalling back to the material factor", slot, err);
+            Ok(factor)
+        },
+        result => result
+    }
+}
+
+fn build_textures(material: &Material, use_cache: bool, fallback: bool, progress: &Fn(&'static str)) -> Result<MaterialTextureData, String> {
+    let mut images = ImageCache::new();
+    let maps = &material.maps;
+    let lookup = |slot: &str| if use_cache { cached(material, slot) } else { None };
+    let white = || constant(TextureFormat::R8G8B8A8Srgb, &[255, 255, 255, 255]);
+    let flat = || constant(TextureFormat::R8G8B8A8Unorm, &[128, 128, 255, 255]);
+    let one = || constant(TextureFormat::R8Unorm, &[255]);
+
+    progress("albedo");
+    let albedo = match maps.albedo {
+        Some(ref path) => {
+            let result = container(path, true).and_then(|found| match found.or_else(|| lookup("albedo")) {
+                Some(texture) => Ok(texture),
+                None => images.get(path).map(|image| mipmap::color_mips(image, true))
+            });
+            or_factor("albedo", result, white(), fallback)?
+        },
+        None => white()
+    };
+
+    progress("normal");
+    // A baked normal map cannot feed the roughness adjustment, its mips are already filtered
+    let (normal, mut normal_lengths) = match maps.normal {
+        Some(ref path) => {
+            let result = container(path, false).and_then(|found| match found.or_else(|| lookup("normal")) {
+                Some(texture) => Ok((texture, None)),
+                None => images.get(path).map(|image| {
+                    let mips = mipmap::normal_mips(image);
+                    (mips.data, Some(mips.lengths))
+                })
+            });
+            or_factor("normal", result, (flat(), None), fallback)?
+        },
+        None => (flat(), None)
+    };
+
+    progress("ao");
+    let ao = match maps.ao {
+        Some(ref input) => {
+            let result = scalar_container(input).and_then(|found| match found.or_else(|| lookup("ao")) {
+                Some(texture) => Ok(texture),
+                None => images.scalar(input).map(|image| mipmap::scalar_mips(&image))
+            });
+            or_factor("ao", result, one(), fallback)?
+        },
+        None => one()
+    };
+
+    progress("metallic");
+    let metallic = match maps.metallic {
+        Some(ref input) => {
+            let result = scalar_container(input).and_then(|found| match found.or_else(|| lookup("metallic")) {
+                Some(texture) => Ok(texture),
+                None => images.scalar(input).map(|image| mipmap::scalar_mips(&image))
+            });
+            or_factor("metallic", result, one(), fallback)?
+        },
+        None => one()
+    };
+
+    progress("roughness");
+    let roughness = match maps.roughness {
+        Some(ref input) => {
+            let result = scalar_container(input).and_then(|found| match found.or_else(|| lookup("roughness")) {
+                Some(texture) => Ok(texture),
+                None => {
+                    // The normal map may have come from the cache while roughness did not
+                    if normal_lengths.is_none() {
+                        if let Some(path) = maps.normal.as_ref().filter(|p| !texture::is_container(p)) {
+                            normal_lengths = Some(mipmap::normal_mips(images.get(path)?).lengths);
+                        }
+                    }
+                    let image = images.scalar(input)?;
+                    Ok(mipmap::roughness_mips(&image, normal_lengths.as_ref().map(|l| l.as_slice())))
+                }
+            });
+            or_factor("roughness", result, one(), fallback)?
+        },
+        None => one()
+    };
+
+    Ok(MaterialTextureData {
+        albedo: albedo,
+        normal: normal,
+        ao: ao,
+        metallic: metallic,
+        roughness: roughness
+    })
+}
+
+// Uses cooked textures where the cache has an up to date entry, everything else is generated.
+// Maps that cannot be read are reported and leave their factor unchanged.
+pub fn prepare_textures(material: &Material) -> Result<MaterialTextureData, String> {
+    build_textures(material, true, true, &|_| ())
+}
+
+// Same as prepare_textures, reporting each slot before it is prepared
+pub fn prepare_textures_with_progress(material: &Material, progress: &Fn(&'static str)) -> Result<MaterialTextureData, String> {
+    build_textures(material, true, true, progress)
+}
+
+fn cooked_format(slot: &str) -> TextureFormat {
+    match slot {
+        "albedo" => TextureFormat::Bc7Srgb,
+        "normal" => TextureFormat::Bc5Unorm,
+        _ => TextureFormat::Bc4Unorm
+    }
+}
+
+// Generates, downsizes and block compresses every slot that comes from a plain image and
+// writes it to the texture cache. Slots from containers or without a map are left alone.
+pub fn cook_textures(material: &Material, max_size: u32) -> Result<Vec<(&'static str, PathBuf)>, String> {
+    let data = build_textures(material, false, false, &|_| ())?;
+    let textures = [&data.albedo, &data.normal, &data.ao, &data.metallic, &data.roughness];
+
+    let mut written = Vec::new();
+    for (&slot, &generated) in SLOTS.iter().zip(textures.iter()) {
+        let (sources, descriptor) = match slot_sources(material, slot) {
+            Some(sources) => sources,
+            None => continue
+        };
+        let key = cache::key(&sources, &descriptor)?;
+
+        let mut texture = TextureData {
+            format: generated.format,
+            width: generated.width,
+            height: generated.height,
+            faces: generated.faces,
+            levels: generated.levels.clone()
+        };
+        texture.limit_size(max_size);
+
+        let compressed = bc::compress(&texture, cooked_format(slot));
+        written.push((slot, cache::store(sources[0], slot, &key, &compressed)?));
+    }
+
+    Ok(written)
+}
@@ -0,0 +1,268 @@
+use serde_json;
+use serde_json::Value;
+
+use material::Material;
+use material::input::{Channel, ScalarInput};
+use bounds::Aabb;
+
+use std::fs::File;
+use std::path::{Path, PathBuf};
+
+// Reads the JSON sidecar of a Megascans / Quixel Bridge export. Only the parts the viewer
+// needs are picked out of it: the mesh LODs, the texture maps and the physical size.
+pub struct MegascansAsset {
+    pub name: String,
+    pub meshes: Vec<(u32, PathBuf)>,
+    pub material: Material,
+    // Width, height and length in meters, as far as the metadata lists them
+    pub physical_size: [Option<f32>; 3]
+}
+
+// Parses lengths like "0.6 m", "60 cm" or "600mm" into meters
+fn parse_length(value: &str) -> Option<f32> {
+    let value = value.trim();
+    let split = value.find(|c: char| !(c.is_digit(10) || c == '.' || c == '-')).unwrap_or(value.len());
+    let number = value[..split].parse::<f32>().ok()?;
+
+    match value[split..].trim() {
+        "m" | "" => Some(number),
+        "cm" => Some(number / 100.0),
+        "mm" => Some(number / 1000.0),
+        _ => None
+    }
+}
+
+fn is_obj(uri: &str) -> bool {
+    uri.to_lowercase().ends_with(".obj")
+}
+
+// Exports list the LODs either under "meshes" with a list of uris or under "models" with a single uri
+fn collect_meshes(json: &Value, base: &Path) -> Vec<(u32, PathBuf)> {
+    let mut meshes = Vec::new();
+
+    if let Some(entries) = json["meshes"].as_array() {
+        for (i, entry) in entries.iter().enumerate() {
+            let lod = entry["lod"].as_u64().map_or(i as u32, |l| l as u32);
+            if let Some(uris) = entry["uris"].as_array() {
+                for uri in uris.iter().filter_map(|u| u["uri"].as_str()).filter(|u| is_obj(u)) {
+                    meshes.push((lod, base.join(uri)));
+                }
+            }
+        }
+    }
+
+    if let Some(entries) = json["models"].as_array() {
+        for (i, entry) in entries.iter().enumerate() {
+            let lod = entry["lod"].as_u64().map_or(i as u32, |l| l as u32);
+            if let Some(uri) = entry["uri"].as_str().filter(|u| is_obj(u)) {
+                meshes.push((lod, base.join(uri)));
+            }
+        }
+    }
+
+    meshes.sort_by_key(|&(lod, _)| lod);
+    meshes.dedup_by(|a, b| a.1 == b.1);
+    meshes
+}
+
+fn collect_maps(json: &Value, base: &Path, material: &mut Material) {
+    let entries = match json["maps"].as_array() {
+        Some(entries) => entries,
+        None => return
+    };
+
+    let mut gloss = None;
+    for entry in entries {
+        let uri = match entry["uri"].as_str() {
+            Some(uri) => base.join(uri),
+            None => continue
+        };
+
+        // Several resolutions of the same map can be listed, the first one wins
+        let maps = &mut material.maps;
+        match entry["type"].as_str().unwrap_or("") {
+            "albedo" | "diffuse" if maps.albedo.is_none() => maps.albedo = Some(uri),
+            "normal" if maps.normal.is_none() => maps.normal = Some(uri),
+            "ao" if maps.ao.is_none() => maps.ao = Some(ScalarInput::new(uri, Channel::R, false)),
+            "metalness" | "metallic" if maps.metallic.is_none() => maps.metallic = Some(ScalarInput::new(uri, Channel::R, false)),
+            "roughness" if maps.roughness.is_none() => maps.roughness = Some(ScalarInput::new(uri, Channel::R, false)),
+            // Only used when no roughness map is listed at all
+            "gloss" => gloss = gloss.or(Some(uri)),
+            _ => ()
+        }
+    }
+
+    if material.maps.roughness.is_none() {
+        material.maps.roughness = gloss.map(|uri| ScalarInput::new(uri, Channel::R, true));
+    }
+}
+
+fn collect_physical_size(json: &Value) -> [Option<f32>; 3] {
+    let mut size = [None, None, None];
+
+    if let Some(entries) = json["meta"].as_array() {
+        for entry in entries {
+            let index = match entry["key"].as_str().unwrap_or("") {
+                "width" => 0,
+                "height" => 1,
+                "length" | "depth" => 2,
+                _ => continue
+            };
+            size[index] = entry["value"].as_str().and_then(parse_length);
+        }
+    }
+
+    size
+}
+
+impl MegascansAsset {
+    pub fn from_file<P: AsRef<Path>>(path: P) -> Result<MegascansAsset, String> {
+        let path = path.as_ref();
+        let json: Value = File::open(path)
+            .map_err(|e| e.to_string())
+            .and_then(|f| serde_json::from_reader(f).map_err(|e| e.to_string()))
+            .map_err(|e| format!("{}: {}", path.display(), e))?;
+
+        let base = path.parent().unwrap_or(Path::new("."));
+
+        let mut material = Material::default();
+        collect_maps(&json, base, &mut material);
+
+        Ok(MegascansAsset {
+            name: json["name"].as_str().unwrap_or("").to_string(),
+            meshes: collect_meshes(&json, base),
+            material: material,
+            physical_size: collect_physical_size(&json)
+        })
+    }
+
+    // Picks the requested LOD, or the closest coarser one, or failing that the closest finer one
+    pub fn mesh(&self, lod: u32) -> Option<&Path> {
+        self.meshes.iter().find(|&&(l, ref p)| l >= lod && p.is_file())
+            .or_else(|| self.meshes.iter().rev().find(|&&(_, ref p)| p.is_file()))
+            .map(|&(_, ref p)| p.as_path())
+    }
+
+    // The factor that brings a model with the given bounds to real-world size in meters,
+    // based on the largest dimension listed in the metadata
+    pub fn physical_scale(&self, bounds: &Aabb) -> Option<f32> {
+        let size = bounds.size();
+        let extent = [size.x, size.y, size.z];
+
+        let (axis, size) = self.physical_size.iter().enumerate()
+            .filter_map(|(i, s)| s.map(|s| (i, s)))
+            .fold(None, |best: Option<(usize, f32)>, (i, s)| match best {
+                Some((_, b)) if b >= s => best,
+                _ => Some((i, s))
+            })?;
+
+        if extent[axis] > 0.0 {
+            Some(size / extent[axis])
+        } else {
+            None
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    use cgmath::Point3;
+
+    use std::env;
+    use std::fs;
+    use std::process;
+
+    const SIDECAR: &str = r#"{
+        "name": "Mossy Rock",
+        "meshes": [
+            { "lod": 0, "uris": [{ "uri": "rock_LOD0.obj" }, { "uri": "rock_LOD0.fbx" }] },
+            { "lod": 2, "uris": [{ "uri": "rock_LOD2.obj" }] },
+            { "lod": 4, "uris": [{ "uri": "rock_LOD4.obj" }] }
+        ],
+        "maps": [
+            { "type": "albedo", "uri": "rock_Albedo_4K.jpg" },
+            { "type": "albedo", "uri": "rock_Albedo_2K.jpg" },
+            { "type": "normal", "uri": "rock_Normal_4K.jpg" },
+            { "type": "gloss", "uri": "rock_Gloss_4K.jpg" },
+            { "type": "displacement", "uri": "rock_Displacement_4K.exr" }
+        ],
+        "meta": [
+            { "key": "width", "value": "60 cm" },
+            { "key": "height", "value": "0.25 m" },
+            { "key": "length", "value": "450mm" },
+            { "key": "scanArea", "value": "1x1 m" }
+        ]
+    }"#;
+
+    #[test]
+    fn lengths_parse_into_meters() {
+        assert_eq!(parse_length("0.6 m"), Some(0.6));
+        assert_eq!(parse_length("60 cm"), Some(0.6));
+        assert_eq!(parse_length("600mm"), Some(0.6));
+        assert_eq!(parse_length("2"), Some(2.0));
+        assert_eq!(parse_length("2 ft"), None);
+        assert_eq!(parse_length("m"), None);
+    }
+
+    #[test]
+    fn sidecar_lists_meshes_maps_and_size() {
+        let folder = env::temp_dir().join(format!("vulkan-test-megascans-{}", process::id()));
+        fs::create_dir_all(&folder).unwrap();
+        let path = folder.join("rock.json");
+        fs::write(&path, SIDECAR).unwrap();
+        // LOD 2 is listed but was not exported
+        fs::write(folder.join("rock_LOD0.obj"), b"").unwrap();
+        fs::write(folder.join("rock_LOD4.obj"), b"").unwrap();
+
+        let asset = MegascansAsset::from_file(&path).unwrap();
+        let lods: Vec<_> = [0, 1, 3, 4, 5].iter().map(|&lod| asset.mesh(lod).map(Path::to_path_buf)).collect();
+        fs::remove_dir_all(&folder).unwrap();
+
+        assert_eq!(asset.name, "Mossy Rock");
+        assert_eq!(asset.meshes, vec![
+            (0, folder.join("rock_LOD0.obj")),
+            (2, folder.join("rock_LOD2.obj")),
+            (4, folder.join("rock_LOD4.obj"))
+        ]);
+
+        // Missing files are skipped for the next coarser LOD, past the coarsest the finest left is taken
+        assert_eq!(lods, vec![
+            Some(folder.join("rock_LOD0.obj")),
+            Some(folder.join("rock_LOD4.obj")),
+            Some(folder.join("rock_LOD4.obj")),
+            Some(folder.join("rock_LOD4.obj")),
+            Some(folder.join("rock_LOD4.obj"))
+        ]);
+
+        let maps = &asset.material.maps;
+        assert_eq!(maps.albedo, Some(folder.join("rock_Albedo_4K.jpg")));
+        assert_eq!(maps.normal, Some(folder.join("rock_Normal_4K.jpg")));
+        assert_eq!(maps.roughness, Some(ScalarInput::new(folder.join("rock_Gloss_4K.jpg"), Channel::R, true)));
+        assert!(maps.ao.is_none() && maps.metallic.is_none());
+
+        assert_eq!(asset.physical_size, [Some(0.6), Some(0.25), Some(0.45)]);
+    }
+
+    #[test]
+    fn physical_scale_uses_the_largest_listed_dimension() {
+        let mut asset = MegascansAsset {
+            name: String::new(),
+            meshes: Vec::new(),
+            material: Material::default(),
+            physical_size: [Some(0.6), Some(0.25), None]
+        };
+        let bounds = Aabb::new(Point3::new(-1.0, 0.0, -2.0), Point3::new(2.0, 1.0, 2.0));
+
+        // The width is the largest size given, even though the model is longest along z
+        assert_eq!(asset.physical_scale(&bounds), Some(0.2));
+
+        asset.physical_size = [None, None, None];
+        assert_eq!(asset.physical_scale(&bounds), None);
+
+        asset.physical_size = [Some(0.6), None, None];
+        let flat = Aabb::new(Point3::new(0.0, 0.0, 0.0), Point3::new(0.0, 1.0, 1.0));
+        assert_eq!(asset.physical_scale(&flat), None);
+    }
+}
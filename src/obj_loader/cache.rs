@@ -0,0 +1,438 @@
+use memmap::Mmap;
+use cgmath::Point3;
+
+use bounds::Aabb;
+use obj_loader::{Model, Normal, Submesh, TexCoord, Vertex};
+use texture::hash::Fnv64;
+
+use std::fs;
+use std::fs::File;
+use std::io::Write;
+use std::path::{Path, PathBuf};
+use std::time::UNIX_EPOCH;
+
+// Binary mesh cache stored next to the source as <name>.obj.meshcache. All sections are little
+// endian and 16 byte aligned, so the file can be mapped and read in place:
+//
+//   header          magic, version, source stamp, counts, bounds as min and max, section offsets
+//   positions       3 x f32 per vertex
+//   normals         3 x f32 per vertex, absent if the model has none
+//   texcoords       2 x f32 per vertex, absent if the model has none
+//   indices         u32
+//   submeshes       first index, index count, name and material as (offset, length) into the strings,
+//                   then the submesh bounds as min and max
+//   strings         UTF-8, a material length of u32::MAX means no material
+
+const MAGIC: &'static [u8; 8] = b"VTMESH\0\0";
+// Bump whenever the layout or the processing of parsed models changes
+const VERSION: u32 = 3;
+const EXTENSION: &'static str = "meshcache";
+
+const HEADER_SIZE: usize = 128;
+const SUBMESH_SIZE: usize = 48;
+const NO_MATERIAL: u32 = 0xffffffff;
+
+// Identifies the source a cache was written from. A matching size and mtime is trusted right
+// away, otherwise the content hash decides, so touching or copying a file keeps its cache.
+#[derive(Debug, Copy, Clone, PartialEq)]
+pub struct SourceStamp {
+    pub size: u64,
+    pub modified: u64,
+    pub hash: u64
+}
+
+impl SourceStamp {
+    fn metadata(path: &Path) -> Result<(u64, u64), String> {
+        let metadata = fs::metadata(path).map_err(|e| format!("{}: {}", path.display(), e))?;
+        let modified = metadata.modified().ok()
+            .and_then(|t| t.duration_since(UNIX_EPOCH).ok())
+            .map_or(0, |d| d.as_secs() * 1_000_000_000 + d.subsec_nanos() as u64);
+        Ok((metadata.len(), modified))
+    }
+
+    fn hash(path: &Path) -> Result<u64, String> {
+        let mut hash = Fnv64::new();
+        hash.write_file(path).map_err(|e| format!("{}: {}", path.display(), e))?;
+        Ok(hash.finish())
+    }
+
+    pub fn of(path: &Path) -> Result<SourceStamp, String> {
+        let (size, modified) = SourceStamp::metadata(path)?;
+        Ok(SourceStamp {
+            size: size,
+            modified: modified,
+            hash: SourceStamp::hash(path)?
+        })
+    }
+}
+
+pub fn cache_path(source: &Path) -> PathBuf {
+    let mut name = source.file_name().map_or("mesh".into(), |n| n.to_os_string());
+    name.push(".");
+    name.push(EXTENSION);
+    source.with_file_name(name)
+}
+
+fn align(offset: usize) -> usize {
+    (offset + 15) / 16 * 16
+}
+
+fn push_u32(out: &mut Vec<u8>, value: u32) {
+    out.extend_from_slice(&[value as u8, (value >> 8) as u8, (value >> 16) as u8, (value >> 24) as u8]);
+}
+
+fn push_u64(out: &mut Vec<u8>, value: u64) {
+    push_u32(out, value as u32);
+    push_u32(out, (value >> 32) as u32);
+}
+
+fn push_f32(out: &mut Vec<u8>, value: f32) {
+    push_u32(out, value.to_bits());
+}
+
+fn read_u32(bytes: &[u8], offset: usize) -> u32 {
+    (bytes[offset] as u32) | (bytes[offset + 1] as u32) << 8 |
+        (bytes[offset + 2] as u32) << 16 | (bytes[offset + 3] as u32) << 24
+}
+
+fn read_u64(bytes: &[u8], offset: usize) -> u64 {
+    read_u32(bytes, offset) as u64 | (read_u32(bytes, offset + 4) as u64) << 32
+}
+
+fn read_f32(bytes: &[u8], offset: usize) -> f32 {
+    f32::from_bits(read_u32(bytes, offset))
+}
+
+fn push_bounds(out: &mut Vec<u8>, bounds: &Aabb) {
+    for &value in &[bounds.min.x, bounds.min.y, bounds.min.z, bounds.max.x, bounds.max.y, bounds.max.z] {
+        push_f32(out, value);
+    }
+}
+
+fn read_bounds(bytes: &[u8], offset: usize) -> Aabb {
+    let value = |i: usize| read_f32(bytes, offset + 4 * i);
+    Aabb::new(Point3::new(value(0), value(1), value(2)), Point3::new(value(3), value(4), value(5)))
+}
+
+pub fn encode(model: &Model, stamp: &SourceStamp) -> Vec<u8> {
+    let mut strings = Vec::new();
+    let mut submeshes = Vec::with_capacity(model.submeshes.len() * SUBMESH_SIZE);
+    for submesh in &model.submeshes {
+        push_u32(&mut submeshes, submesh.first_index);
+        push_u32(&mut submeshes, submesh.index_count);
+        push_u32(&mut submeshes, strings.len() as u32);
+        push_u32(&mut submeshes, submesh.name.len() as u32);
+        strings.extend_from_slice(submesh.name.as_bytes());
+        match submesh.material {
+            Some(ref material) => {
+                push_u32(&mut submeshes, strings.len() as u32);
+                push_u32(&mut submeshes, material.len() as u32);
+                strings.extend_from_slice(material.as_bytes());
+            },
+            None => {
+                push_u32(&mut submeshes, 0);
+                push_u32(&mut submeshes, NO_MATERIAL);
+            }
+        }
+        push_bounds(&mut submeshes, &submesh.bounds);
+    }
+
+    let mut sections: Vec<Vec<u8>> = Vec::with_capacity(6);
+    sections.push(model.vertices.iter().fold(Vec::with_capacity(model.vertices.len() * 12), |mut out, v| {
+        push_f32(&mut out, v.position.0);
+        push_f32(&mut out, v.position.1);
+        push_f32(&mut out, v.position.2);
+        out
+    }));
+    sections.push(model.normals.iter().fold(Vec::with_capacity(model.normals.len() * 12), |mut out, n| {
+        push_f32(&mut out, n.normal.0);
+        push_f32(&mut out, n.normal.1);
+        push_f32(&mut out, n.normal.2);
+        out
+    }));
+    sections.push(model.texcoords.iter().fold(Vec::with_capacity(model.texcoords.len() * 8), |mut out, t| {
+        push_f32(&mut out, t.texcoord.0);
+        push_f32(&mut out, t.texcoord.1);
+        out
+    }));
+    sections.push(model.indices.iter().fold(Vec::with_capacity(model.indices.len() * 4), |mut out, &i| {
+        push_u32(&mut out, i);
+        out
+    }));
+    sections.push(submeshes);
+    sections.push(strings);
+
+    let mut offsets = Vec::with_capacity(sections.len());
+    let mut end = HEADER_SIZE;
+    for section in &sections {
+        offsets.push(align(end));
+        end = align(end) + section.len();
+    }
+
+    let mut out = Vec::with_capacity(end);
+    out.extend_from_slice(MAGIC);
+    push_u32(&mut out, VERSION);
+    push_u32(&mut out, 0);
+    push_u64(&mut out, stamp.size);
+    push_u64(&mut out, stamp.modified);
+    push_u64(&mut out, stamp.hash);
+
+    push_u32(&mut out, model.vertices.len() as u32);
+    push_u32(&mut out, model.normals.len() as u32);
+    push_u32(&mut out, model.texcoords.len() as u32);
+    push_u32(&mut out, model.indices.len() as u32);
+    push_u32(&mut out, model.submeshes.len() as u32);
+    push_u32(&mut out, sections[5].len() as u32);
+
+    push_bounds(&mut out, &model.bounds);
+    for &offset in &offsets {
+        push_u32(&mut out, offset as u32);
+    }
+
+    for (section, &offset) in sections.iter().zip(offsets.iter()) {
+        out.resize(offset, 0);
+        out.extend_from_slice(section);
+    }
+
+    out
+}
+
+pub fn read_stamp(bytes: &[u8]) -> Result<SourceStamp, String> {
+    if bytes.len() < HEADER_SIZE || &bytes[..8] != MAGIC {
+        return Err("not a mesh cache".to_string());
+    }
+    let version = read_u32(bytes, 8);
+    if version != VERSION {
+        return Err(format!("mesh cache version {} is outdated, expected {}", version, VERSION));
+    }
+
+    Ok(SourceStamp {
+        size: read_u64(bytes, 16),
+        modified: read_u64(bytes, 24),
+        hash: read_u64(bytes, 32)
+    })
+}
+
+pub fn decode(bytes: &[u8]) -> Result<Model, String> {
+    read_stamp(bytes)?;
+
+    let vertex_count = read_u32(bytes, 40) as usize;
+    let normal_count = read_u32(bytes, 44) as usize;
+    let texcoord_count = read_u32(bytes, 48) as usize;
+    let index_count = read_u32(bytes, 52) as usize;
+    let submesh_count = read_u32(bytes, 56) as usize;
+    let string_bytes = read_u32(bytes, 60) as usize;
+
+    let bounds = read_bounds(bytes, 64);
+
+    let sizes = [vertex_count * 12, normal_count * 12, texcoord_count * 8, index_count * 4, submesh_count * SUBMESH_SIZE, string_bytes];
+    let mut offsets = [0usize; 6];
+    for (i, &size) in sizes.iter().enumerate() {
+        offsets[i] = read_u32(bytes, 88 + 4 * i) as usize;
+        if offsets[i] + size > bytes.len() {
+            return Err("mesh cache is truncated".to_string());
+        }
+    }
+
+    let vertices = (0..vertex_count).map(|i| {
+        let o = offsets[0] + 12 * i;
+        Vertex { position: (read_f32(bytes, o), read_f32(bytes, o + 4), read_f32(bytes, o + 8)) }
+    }).collect();
+    let normals = (0..normal_count).map(|i| {
+        let o = offsets[1] + 12 * i;
+        Normal { normal: (read_f32(bytes, o), read_f32(bytes, o + 4), read_f32(bytes, o + 8)) }
+    }).collect();
+    let texcoords = (0..texcoord_count).map(|i| {
+        let o = offsets[2] + 8 * i;
+        TexCoord { texcoord: (read_f32(bytes, o), read_f32(bytes, o + 4)) }
+    }).collect();
+    let indices: Vec<u32> = (0..index_count).map(|i| read_u32(bytes, offsets[3] + 4 * i)).collect();
+
+    if indices.iter().any(|&i| i as usize >= vertex_count) {
+        return Err("mesh cache has out of range indices".to_string());
+    }
+
+    let strings = &bytes[offsets[5]..offsets[5] + string_bytes];
+    let string = |offset: u32, length: u32| -> Result<String, String> {
+        let (offset, length) = (offset as usize, length as usize);
+        strings.get(offset..offset + length)
+            .map(|s| String::from_utf8_lossy(s).into_owned())
+            .ok_or_else(|| "mesh cache has a broken string table".to_string())
+    };
+
+    let mut submeshes = Vec::with_capacity(submesh_count);
+    for i in 0..submesh_count {
+        let o = offsets[4] + SUBMESH_SIZE * i;
+        let (first_index, submesh_index_count) = (read_u32(bytes, o), read_u32(bytes, o + 4));
+        match first_index.checked_add(submesh_index_count) {
+            Some(end) if end as usize <= index_count => (),
+            _ => return Err("mesh cache has out of range submeshes".to_string())
+        }
+
+        let material_length = read_u32(bytes, o + 20);
+        submeshes.push(Submesh {
+            first_index: first_index,
+            index_count: submesh_index_count,
+            name: string(read_u32(bytes, o + 8), read_u32(bytes, o + 12))?,
+            material: if material_length == NO_MATERIAL { None } else { Some(string(read_u32(bytes, o + 16), material_length)?) },
+            bounds: read_bounds(bytes, o + 24)
+        });
+    }
+
+    Ok(Model {
+        vertices: vertices,
+        indices: indices,
+        normals: normals,
+        texcoords: texcoords,
+        bounds: bounds,
+        submeshes: submeshes
+    })
+}
+
+// Returns the cached model if the cache exists and still matches its source
+pub fn load(source: &Path) -> Option<Model> {
+    let path = cache_path(source);
+    let file = File::open(&path).ok()?;
+    let map = unsafe { Mmap::map(&file) }.ok()?;
+
+    let stamp = read_stamp(&map).ok()?;
+    let (size, modified) = SourceStamp::metadata(source).ok()?;
+    if stamp.size != size || stamp.modified != modified {
+        if stamp.size != size || SourceStamp::hash(source).ok()? != stamp.hash {
+            return None;
+        }
+    }
+
+    match decode(&map) {
+        Ok(model) => Some(model),
+        Err(err) => {
+            println!("{}: {}", path.display(), err);
+            None
+        }
+    }
+}
+
+pub fn store(source: &Path, model: &Model) -> Result<PathBuf, String> {
+    let path = cache_path(source);
+    let bytes = encode(model, &SourceStamp::of(source)?);
+
+    File::create(&path)
+        .and_then(|mut f| f.write_all(&bytes))
+        .map_err(|e| format!("{}: {}", path.display(), e))?;
+
+    Ok(path)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use obj_loader::parse_obj;
+
+    use std::env;
+    use std::fs;
+
+    const CUBE_FACES: &'static str = "
+o first
+v 0 0 0
+v 1 0 0
+v 1 1 0
+v 0 1 0
+vt 0 0
+vt 1 0
+vt 1 1
+vt 0 1
+vn 0 0 1
+f 1/1/1 2/2/1 3/3/1
+f 1/1/1 3/3/1 4/4/1
+o second
+v 0 0 1
+v 1 0 1
+v 1 1 1
+vt 0 0
+vt 1 0
+vt 1 1
+vn 0 0 -1
+f 5/5/2 7/7/2 6/6/2
+";
+
+    // The second object has neither texcoords nor normals
+    const PARTIAL_ATTRIBUTES: &'static str = "
+o textured
+v 0 0 0
+v 1 0 0
+v 1 1 0
+vt 0 0
+vt 1 0
+vt 1 1
+vn 0 1 0
+f 1/1/1 2/2/1 3/3/1
+o bare
+v 0 0 1
+v 1 0 1
+v 1 1 1
+f 4 6 5
+";
+
+    fn write_source(name: &str, contents: &str) -> PathBuf {
+        let folder = env::temp_dir().join("vulkan-test-mesh-cache");
+        fs::create_dir_all(&folder).unwrap();
+        let path = folder.join(name);
+        fs::write(&path, contents).unwrap();
+        path
+    }
+
+    #[test]
+    fn cached_model_equals_parsed_model() {
+        let source = write_source("round_trip.obj", CUBE_FACES);
+        let parsed = parse_obj(&source);
+        assert_eq!(parsed.submeshes.len(), 2);
+
+        store(&source, &parsed).unwrap();
+        let cached = load(&source).expect("cache should be valid for an unchanged source");
+
+        assert_eq!(cached, parsed);
+    }
+
+    #[test]
+    fn missing_attributes_are_filled_per_object() {
+        let source = write_source("partial_attributes.obj", PARTIAL_ATTRIBUTES);
+        let parsed = parse_obj(&source);
+        assert_eq!(parsed.vertices.len(), 6);
+        assert_eq!(parsed.normals.len(), parsed.vertices.len());
+        assert_eq!(parsed.texcoords.len(), parsed.vertices.len());
+
+        // The textured object keeps its attributes, the bare one gets the defaults
+        let vertices = parsed.interleaved();
+        assert_eq!((vertices[1].normal, vertices[1].texcoord), ((0.0, 1.0, 0.0), (1.0, 0.0)));
+        assert!(vertices[3..].iter().all(|v| v.normal == (0.0, 0.0, 1.0) && v.texcoord == (0.0, 0.0)));
+
+        store(&source, &parsed).unwrap();
+        assert_eq!(load(&source).expect("cache should be valid for an unchanged source"), parsed);
+    }
+
+    #[test]
+    fn edited_source_invalidates_cache() {
+        let source = write_source("invalidate.obj", CUBE_FACES);
+        let path = store(&source, &parse_obj(&source)).unwrap();
+        assert!(load(&source).is_some());
+
+        // Submeshes reaching past the indices, once by their end and once by overflowing
+        let bytes = fs::read(&path).unwrap();
+        let (index_count, submeshes) = (read_u32(&bytes, 52), read_u32(&bytes, 104) as usize);
+        for &(first_index, count) in &[(0, index_count + 1), (3, u32::max_value() - 1)] {
+            let mut corrupt = bytes.clone();
+            let mut range = Vec::new();
+            push_u32(&mut range, first_index);
+            push_u32(&mut range, count);
+            corrupt[submeshes..submeshes + 8].copy_from_slice(&range);
+            fs::write(&path, &corrupt).unwrap();
+            assert!(load(&source).is_none());
+        }
+        fs::write(&path, &bytes).unwrap();
+        assert!(load(&source).is_some());
+
+        fs::write(&source, CUBE_FACES.replace("v 1 1 1", "v 2 2 2.5")).unwrap();
+
+        assert!(load(&source).is_none());
+    }
+}
@@ -1,90 +1,308 @@
+pub mod cache;
+pub mod optimize;
+pub mod simplify;
+pub mod validate;
+
+use bounds::{Aabb, Ray, Sphere};
+
 use tobj;
 use find_folder::Search;
+use cgmath::Point3;
 
-#[derive(Copy, Clone)]
+use std::collections::HashMap;
+use std::hash::Hash;
+use std::f32::consts::PI;
+use std::path::Path;
+
+#[derive(Copy, Clone, Debug, PartialEq)]
 pub struct Vertex {
     position: (f32, f32, f32)
 }
 
 impl_vertex!(Vertex, position);
 
-#[derive(Copy, Clone)]
+impl Vertex {
+    pub fn point(&self) -> Point3<f32> {
+        Point3::new(self.position.0, self.position.1, self.position.2)
+    }
+}
+
+#[derive(Copy, Clone, Debug, PartialEq)]
 pub struct Normal {
     normal: (f32, f32, f32)
 }
 
 impl_vertex!(Normal, normal);
 
-type Coordinate = (f32, f32);
-#[derive(Copy, Clone, Debug)]
-pub struct Bounds {
-    pub x: Coordinate,
-    pub y: Coordinate,
-    pub z: Coordinate
+#[derive(Copy, Clone, Debug, PartialEq)]
+pub struct TexCoord {
+    texcoord: (f32, f32)
 }
 
+impl_vertex!(TexCoord, texcoord);
+
+// All attributes interleaved in one buffer, as consumed by the model pipeline
+#[derive(Copy, Clone)]
+pub struct ModelVertex {
+    pub position: (f32, f32, f32),
+    pub normal: (f32, f32, f32),
+    pub texcoord: (f32, f32)
+}
+
+impl_vertex!(ModelVertex, position, normal, texcoord);
+
+// Used for vertices whose object has no normals or texcoords
+const DEFAULT_NORMAL: (f32, f32, f32) = (0.0, 0.0, 1.0);
+const DEFAULT_TEXCOORD: (f32, f32) = (0.0, 0.0);
+
+// A range of the index buffer that came from one object of the source file, with the material it uses
+#[derive(Clone, Debug, PartialEq)]
+pub struct Submesh {
+    pub name: String,
+    pub material: Option<String>,
+    pub first_index: u32,
+    pub index_count: u32,
+    pub bounds: Aabb
+}
+
+#[derive(Clone, Debug, PartialEq)]
 pub struct Model {
     pub vertices: Vec<Vertex>,
     pub indices: Vec<u32>,
     pub normals: Vec<Normal>,
-    pub bounds: Bounds,
+    pub texcoords: Vec<TexCoord>,
+    pub bounds: Aabb,
+    pub submeshes: Vec<Submesh>
 }
 
-pub fn load_model(name: &str) -> Model {
-    let mut path = Search::ParentsThenKids(3, 3).for_folder("assets").unwrap();
-    path.push(name);
+pub fn compute_bounds(vertices: &[Vertex]) -> Aabb {
+    Aabb::from_points(vertices.iter().map(|v| v.point()))
+}
+
+impl Model {
+    pub fn interleaved(&self) -> Vec<ModelVertex> {
+        (0..self.vertices.len()).map(|i| ModelVertex {
+            position: self.vertices[i].position,
+            normal: self.normals.get(i).map_or(DEFAULT_NORMAL, |n| n.normal),
+            texcoord: self.texcoords.get(i).map_or(DEFAULT_TEXCOORD, |t| t.texcoord)
+        }).collect()
+    }
 
-    let asset = tobj::load_obj(&path);
-    assert!(asset.is_ok());
+    // Uniformly scales the model around the origin, e.g. to convert it to real-world units
+    pub fn scale(&mut self, factor: f32) {
+        for v in self.vertices.iter_mut() {
+            v.position = (v.position.0 * factor, v.position.1 * factor, v.position.2 * factor);
+        }
+        self.update_bounds();
+    }
 
-    let (models, _) = asset.unwrap();
+    // Tight sphere around all vertices, bounds only hold the box
+    pub fn bounding_sphere(&self) -> Sphere {
+        let points: Vec<Point3<f32>> = self.vertices.iter().map(|v| v.point()).collect();
+        Sphere::from_points(&points)
+    }
 
-    let mesh = &models[0].mesh;
+    // Distance along the ray to the nearest triangle, submeshes whose bounds are missed or lie
+    // behind a closer hit are skipped
+    pub fn raycast(&self, ray: &Ray) -> Option<f32> {
+        let mut nearest: Option<f32> = None;
+        for submesh in self.submeshes.iter() {
+            match submesh.bounds.intersect_ray(ray) {
+                Some((near, _)) if nearest.map_or(true, |t| near < t) => (),
+                _ => continue
+            }
 
-    println!("model.name = \'{}\'", models[0].name);
+            let start = submesh.first_index as usize;
+            let end = start + submesh.index_count as usize;
+            for corners in self.indices[start..end].chunks(3) {
+                if corners.len() < 3 {
+                    continue;
+                }
+                let p: Vec<Point3<f32>> = corners.iter().filter_map(|&i| self.vertices.get(i as usize)).map(|v| v.point()).collect();
+                if p.len() < 3 {
+                    continue;
+                }
+                if let Some(t) = ray.intersect_triangle(p[0], p[1], p[2]) {
+                    if nearest.map_or(true, |n| t < n) {
+                        nearest = Some(t);
+                    }
+                }
+            }
+        }
+        nearest
+    }
 
-    let indices = mesh.indices.clone();
-    let mut vertices: Vec<Vertex> = Vec::with_capacity(mesh.positions.len() / 3);
-    for i in 0..mesh.positions.len() / 3 {
-        vertices.push(Vertex{ position: (mesh.positions[3 * i], mesh.positions[3 * i + 1], mesh.positions[3 * i + 2]) });
+    // Recomputes the bounds of the model and of every submesh after vertices or indices changed
+    pub fn update_bounds(&mut self) {
+        self.bounds = compute_bounds(&self.vertices);
+        for submesh in self.submeshes.iter_mut() {
+            let range = submesh.first_index as usize..(submesh.first_index + submesh.index_count) as usize;
+            let vertices = &self.vertices;
+            submesh.bounds = Aabb::from_points(self.indices[range].iter()
+                .filter_map(|&i| vertices.get(i as usize)).map(|v| v.point()));
+        }
     }
 
-    let mut normals: Vec<Normal> = Vec::with_capacity(mesh.normals.len() / 3);
-    for i in 0..mesh.normals.len() / 3 {
-        normals.push(Normal{ normal: (mesh.normals[3 * i], mesh.normals[3 * i + 1], mesh.normals[3 * i + 2]) });
+    // Merges vertices whose position, normal and texcoord are bit-identical, returns how many were removed
+    pub fn deduplicate(&mut self) -> usize {
+        self.merge_vertices(|v| [
+            v.position.0.to_bits(), v.position.1.to_bits(), v.position.2.to_bits(),
+            v.normal.0.to_bits(), v.normal.1.to_bits(), v.normal.2.to_bits(),
+            v.texcoord.0.to_bits(), v.texcoord.1.to_bits()
+        ])
     }
 
-    let mut x: Coordinate = (vertices[0].position.0, vertices[0].position.0);
-    let mut y: Coordinate = (vertices[0].position.1, vertices[0].position.1);
-    let mut z: Coordinate = (vertices[0].position.2, vertices[0].position.2);
+    // Merges all vertices with the same key into the first one of them, returns how many were removed
+    pub fn merge_vertices<K: Hash + Eq, F: Fn(&ModelVertex) -> K>(&mut self, key: F) -> usize {
+        let count = self.vertices.len();
+        let mut unique: HashMap<K, u32> = HashMap::with_capacity(count);
+        let mut remap = Vec::with_capacity(count);
+        let mut kept = Vec::with_capacity(count);
+
+        for (i, v) in self.interleaved().iter().enumerate() {
+            let next = kept.len() as u32;
+            let index = *unique.entry(key(v)).or_insert(next);
+            if index == next {
+                kept.push(i);
+            }
+            remap.push(index);
+        }
+
+        for index in self.indices.iter_mut() {
+            *index = remap[*index as usize];
+        }
+        self.reorder_vertices(&kept);
+
+        count - self.vertices.len()
+    }
 
-    let vtx = vertices.clone();
-    for v in vtx {
-        if v.position.0 < x.0 {
-            x.0 = v.position.0;
+    // Keeps only the given vertices in the given order, indices have to be remapped by the caller
+    pub fn reorder_vertices(&mut self, order: &[usize]) {
+        self.vertices = order.iter().map(|&i| self.vertices[i]).collect();
+        if !self.normals.is_empty() {
+            self.normals = order.iter().map(|&i| self.normals[i]).collect();
         }
-        if v.position.0 > x.1 {
-            x.1 = v.position.0;
+        if !self.texcoords.is_empty() {
+            self.texcoords = order.iter().map(|&i| self.texcoords[i]).collect();
         }
+    }
+}
+
+// A UV sphere, shown as a placeholder while the real model is still loading
+pub fn uv_sphere(radius: f32, rings: u32, segments: u32) -> Model {
+    let mut vertices = Vec::with_capacity(((rings + 1) * (segments + 1)) as usize);
+    let mut normals = Vec::with_capacity(vertices.capacity());
+    let mut texcoords = Vec::with_capacity(vertices.capacity());
+
+    for ring in 0..rings + 1 {
+        let v = ring as f32 / rings as f32;
+        let theta = v * PI;
+        for segment in 0..segments + 1 {
+            let u = segment as f32 / segments as f32;
+            let phi = u * 2.0 * PI;
+            let normal = (theta.sin() * phi.cos(), theta.cos(), theta.sin() * phi.sin());
 
-        if v.position.1 < y.0 {
-            y.0 = v.position.1;
+            vertices.push(Vertex { position: (normal.0 * radius, normal.1 * radius, normal.2 * radius) });
+            normals.push(Normal { normal: normal });
+            texcoords.push(TexCoord { texcoord: (u, v) });
         }
-        if v.position.1 > y.1 {
-            y.1 = v.position.1;
+    }
+
+    let mut indices = Vec::with_capacity((rings * segments * 6) as usize);
+    for ring in 0..rings {
+        for segment in 0..segments {
+            let a = ring * (segments + 1) + segment;
+            let b = a + segments + 1;
+            indices.extend_from_slice(&[a, a + 1, b, b, a + 1, b + 1]);
         }
+    }
+
+    let bounds = compute_bounds(&vertices);
 
-        if v.position.2 < z.0 {
-            z.0 = v.position.2;
+    let submeshes = vec![Submesh { name: "sphere".to_string(), material: None, first_index: 0, index_count: indices.len() as u32, bounds: bounds }];
+
+    Model { indices: indices, normals: normals, texcoords: texcoords, vertices: vertices, bounds: bounds, submeshes: submeshes }
+}
+
+pub fn load_model(name: &str) -> Model {
+    let mut path = Search::ParentsThenKids(3, 3).for_folder("assets").unwrap();
+    path.push(name);
+
+    load_model_from_path(&path)
+}
+
+// Uses the binary cache next to the source when it is up to date, otherwise parses the OBJ and refreshes the cache
+pub fn load_model_from_path(path: &Path) -> Model {
+    if let Some(model) = cache::load(path) {
+        println!("loaded {} from the mesh cache", path.display());
+        return model;
+    }
+
+    let model = parse_obj(path);
+    match cache::store(path, &model) {
+        Ok(cache_path) => println!("wrote mesh cache {}", cache_path.display()),
+        Err(err) => println!("could not write the mesh cache: {}", err)
+    }
+    model
+}
+
+// Reads every object of the file into one model, each object becomes a submesh
+pub fn parse_obj(path: &Path) -> Model {
+    let asset = tobj::load_obj(path);
+    assert!(asset.is_ok(), "failed to load {}", path.display());
+
+    let (models, materials) = asset.unwrap();
+
+    // Attributes only some objects have are filled with the defaults interleaved() would use
+    let has_normals = models.iter().any(|m| !m.mesh.normals.is_empty());
+    let has_texcoords = models.iter().any(|m| !m.mesh.texcoords.is_empty());
+
+    let mut vertices: Vec<Vertex> = Vec::new();
+    let mut normals: Vec<Normal> = Vec::new();
+    let mut texcoords: Vec<TexCoord> = Vec::new();
+    let mut indices: Vec<u32> = Vec::new();
+    let mut submeshes = Vec::with_capacity(models.len());
+
+    for model in &models {
+        let mesh = &model.mesh;
+        println!("model.name = \'{}\'", model.name);
+
+        let base = vertices.len() as u32;
+        submeshes.push(Submesh {
+            name: model.name.clone(),
+            material: mesh.material_id.and_then(|id| materials.get(id)).map(|m| m.name.clone()),
+            first_index: indices.len() as u32,
+            index_count: mesh.indices.len() as u32,
+            bounds: Aabb::empty()
+        });
+        indices.extend(mesh.indices.iter().map(|i| i + base));
+
+        let count = mesh.positions.len() / 3;
+        for i in 0..count {
+            vertices.push(Vertex{ position: (mesh.positions[3 * i], mesh.positions[3 * i + 1], mesh.positions[3 * i + 2]) });
         }
-        if v.position.2 > z.1 {
-            z.1 = v.position.2;
+
+        // Per object, so the attribute arrays stay aligned with the vertices
+        if has_normals {
+            for i in 0..count {
+                let normal = mesh.normals.get(3 * i..3 * i + 3).map_or(DEFAULT_NORMAL, |n| (n[0], n[1], n[2]));
+                normals.push(Normal{ normal: normal });
+            }
+        }
+
+        if has_texcoords {
+            for i in 0..count {
+                let texcoord = mesh.texcoords.get(2 * i..2 * i + 2).map_or(DEFAULT_TEXCOORD, |t| (t[0], t[1]));
+                texcoords.push(TexCoord{ texcoord: texcoord });
+            }
         }
     }
 
-    let bounds = Bounds{ x: x, y: y, z: z };
+    let mut model = Model {indices: indices, normals: normals, texcoords: texcoords, vertices: vertices, bounds: Aabb::empty(), submeshes: submeshes};
+    model.update_bounds();
+    let merged = model.deduplicate();
 
-    println!("#Vertices {}, #Indices {}, #Normals {}", vertices.len(), indices.len(), normals.len());
+    println!("#Vertices {}, #Indices {}, #Normals {}, {} duplicates merged", model.vertices.len(), model.indices.len(), model.normals.len(), merged);
 
-    Model {indices: indices, normals: normals, vertices: vertices, bounds}
-}
\ No newline at end of file
+    model
+}
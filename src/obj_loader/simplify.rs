@@ -0,0 +1,581 @@
+use obj_loader::{Model, Submesh};
+use obj_loader::optimize::optimize_vertex_fetch;
+
+use std::collections::HashMap;
+
+// Quadric error metric simplification (Garland and Heckbert, "Surface Simplification Using Quadric
+// Error Metrics") with half-edge collapses, so every remaining vertex keeps its exact attributes.
+//
+// Vertices are grouped by position. A position with one set of attributes and no open, seam or
+// material boundary edges collapses freely. Positions on such feature edges only slide along them,
+// moving every attribute set (wedge) of the position to the matching one at the target, which keeps
+// UV seams and hard normals intact. Corners where feature edges meet and non-manifold parts are locked.
+
+// Scales the planes that keep feature edges in place relative to the surface planes
+const FEATURE_WEIGHT: f64 = 10.0;
+
+// A symmetric 4x4 matrix as the 10 unique coefficients, plus the accumulated weight so the error
+// can be reported as a mean squared distance
+#[derive(Copy, Clone)]
+struct Quadric {
+    a: [f64; 10],
+    weight: f64
+}
+
+impl Quadric {
+    fn zero() -> Quadric {
+        Quadric { a: [0.0; 10], weight: 0.0 }
+    }
+
+    // Squared distance to the plane n.p + d = 0, n normalized
+    fn plane(n: [f64; 3], d: f64, weight: f64) -> Quadric {
+        let mut a = [
+            n[0] * n[0], n[0] * n[1], n[0] * n[2], n[0] * d,
+            n[1] * n[1], n[1] * n[2], n[1] * d,
+            n[2] * n[2], n[2] * d,
+            d * d
+        ];
+        for v in a.iter_mut() {
+            *v *= weight;
+        }
+
+        Quadric {
+            a: a,
+            weight: weight
+        }
+    }
+
+    fn add(&mut self, other: &Quadric) {
+        for i in 0..10 {
+            self.a[i] += other.a[i];
+        }
+        self.weight += other.weight;
+    }
+
+    fn error(&self, p: [f64; 3]) -> f64 {
+        let a = &self.a;
+        let (x, y, z) = (p[0], p[1], p[2]);
+        let e = a[0] * x * x + 2.0 * a[1] * x * y + 2.0 * a[2] * x * z + 2.0 * a[3] * x +
+            a[4] * y * y + 2.0 * a[5] * y * z + 2.0 * a[6] * y +
+            a[7] * z * z + 2.0 * a[8] * z +
+            a[9];
+        if self.weight > 0.0 { e.max(0.0) / self.weight } else { 0.0 }
+    }
+}
+
+fn sub(a: [f64; 3], b: [f64; 3]) -> [f64; 3] {
+    [a[0] - b[0], a[1] - b[1], a[2] - b[2]]
+}
+
+fn cross(a: [f64; 3], b: [f64; 3]) -> [f64; 3] {
+    [a[1] * b[2] - a[2] * b[1], a[2] * b[0] - a[0] * b[2], a[0] * b[1] - a[1] * b[0]]
+}
+
+fn dot(a: [f64; 3], b: [f64; 3]) -> f64 {
+    a[0] * b[0] + a[1] * b[1] + a[2] * b[2]
+}
+
+fn normalize(a: [f64; 3]) -> Option<[f64; 3]> {
+    let length = dot(a, a).sqrt();
+    if length > 0.0 { Some([a[0] / length, a[1] / length, a[2] / length]) } else { None }
+}
+
+#[derive(Copy, Clone, PartialEq)]
+enum Kind {
+    Interior,
+    Feature,
+    Locked
+}
+
+// Connectivity of the current triangles on position level
+struct Topology {
+    // For every edge between two positions, lower id first: the triangles using it with the
+    // wedges they use at the lower and the higher position
+    edges: HashMap<(u32, u32), Vec<(usize, u32, u32)>>,
+    triangles: Vec<Vec<usize>>,
+    kinds: Vec<Kind>
+}
+
+fn is_feature(edge: &[(usize, u32, u32)], submeshes: &[u32]) -> bool {
+    match edge.len() {
+        1 => true,
+        2 => {
+            let (t0, a0, b0) = edge[0];
+            let (t1, a1, b1) = edge[1];
+            a0 != a1 || b0 != b1 || submeshes[t0] != submeshes[t1]
+        },
+        _ => true
+    }
+}
+
+impl Topology {
+    fn build(indices: &[u32], wedge_position: &[u32], submeshes: &[u32], position_count: usize) -> Topology {
+        let mut edges: HashMap<(u32, u32), Vec<(usize, u32, u32)>> = HashMap::new();
+        let mut triangles = vec![Vec::new(); position_count];
+        let mut wedges: Vec<Vec<u32>> = vec![Vec::new(); position_count];
+
+        for (t, corners) in indices.chunks(3).enumerate() {
+            for i in 0..3 {
+                let (wa, wb) = (corners[i], corners[(i + 1) % 3]);
+                let (pa, pb) = (wedge_position[wa as usize], wedge_position[wb as usize]);
+                let entry = if pa < pb { ((pa, pb), (t, wa, wb)) } else { ((pb, pa), (t, wb, wa)) };
+                edges.entry(entry.0).or_insert_with(Vec::new).push(entry.1);
+
+                triangles[pa as usize].push(t);
+                if !wedges[pa as usize].contains(&wa) {
+                    wedges[pa as usize].push(wa);
+                }
+            }
+        }
+
+        let mut features = vec![0usize; position_count];
+        let mut locked = vec![false; position_count];
+        for (&(a, b), edge) in &edges {
+            if edge.len() > 2 {
+                locked[a as usize] = true;
+                locked[b as usize] = true;
+            } else if is_feature(edge, submeshes) {
+                features[a as usize] += 1;
+                features[b as usize] += 1;
+            }
+        }
+
+        let kinds = (0..position_count).map(|p| {
+            if locked[p] {
+                Kind::Locked
+            } else if features[p] == 0 && wedges[p].len() == 1 {
+                Kind::Interior
+            } else if features[p] == 2 && wedges[p].len() <= 2 {
+                Kind::Feature
+            } else {
+                Kind::Locked
+            }
+        }).collect();
+
+        Topology {
+            edges: edges,
+            triangles: triangles,
+            kinds: kinds
+        }
+    }
+}
+
+struct Collapse {
+    cost: f64,
+    from: u32,
+    to: u32,
+    edge: (u32, u32),
+    wedges: Vec<(u32, u32)>
+}
+
+pub struct Lod {
+    pub model: Model,
+    // Largest collapse error, as a distance in model units. In a LOD chain it also includes the
+    // errors of the levels this one was simplified from.
+    pub error: f32
+}
+
+impl Lod {
+    pub fn triangle_count(&self) -> usize {
+        self.model.indices.len() / 3
+    }
+}
+
+// Simplifies until the model has at most target_triangles triangles or the next collapse would
+// move the surface further than max_error
+pub fn simplify(model: &Model, target_triangles: usize, max_error: f32) -> Lod {
+    // Group wedges by position, a position shared by several wedges sits on an attribute seam
+    let mut position_ids: HashMap<[u32; 3], u32> = HashMap::new();
+    let mut positions: Vec<[f64; 3]> = Vec::new();
+    let wedge_position: Vec<u32> = model.vertices.iter().map(|v| {
+        let p = v.position;
+        let next = positions.len() as u32;
+        let id = *position_ids.entry([p.0.to_bits(), p.1.to_bits(), p.2.to_bits()]).or_insert(next);
+        if id == next {
+            positions.push([p.0 as f64, p.1 as f64, p.2 as f64]);
+        }
+        id
+    }).collect();
+
+    let mut indices = model.indices.clone();
+    let mut submeshes: Vec<u32> = vec![0; indices.len() / 3];
+    for (i, submesh) in model.submeshes.iter().enumerate() {
+        let first = submesh.first_index as usize / 3;
+        for t in first..first + submesh.index_count as usize / 3 {
+            submeshes[t] = i as u32;
+        }
+    }
+
+    let corner = |indices: &[u32], t: usize, i: usize| wedge_position[indices[3 * t + i] as usize];
+
+    let mut quadrics = vec![Quadric::zero(); positions.len()];
+    {
+        let topology = Topology::build(&indices, &wedge_position, &submeshes, positions.len());
+        for t in 0..indices.len() / 3 {
+            let p = [corner(&indices, t, 0), corner(&indices, t, 1), corner(&indices, t, 2)];
+            let (a, b, c) = (positions[p[0] as usize], positions[p[1] as usize], positions[p[2] as usize]);
+            let normal = cross(sub(b, a), sub(c, a));
+            let area = dot(normal, normal).sqrt() * 0.5;
+            let normal = match normalize(normal) {
+                Some(normal) => normal,
+                None => continue
+            };
+
+            let surface = Quadric::plane(normal, -dot(normal, a), area);
+            for &p in &p {
+                quadrics[p as usize].add(&surface);
+            }
+
+            // A plane through each feature edge, perpendicular to the triangle, keeps it from drifting
+            for i in 0..3 {
+                let (pa, pb) = (p[i], p[(i + 1) % 3]);
+                let key = if pa < pb { (pa, pb) } else { (pb, pa) };
+                if !is_feature(&topology.edges[&key], &submeshes) {
+                    continue;
+                }
+                let edge = sub(positions[pb as usize], positions[pa as usize]);
+                if let Some(side) = normalize(cross(edge, normal)) {
+                    let constraint = Quadric::plane(side, -dot(side, positions[pa as usize]), dot(edge, edge) * FEATURE_WEIGHT);
+                    quadrics[pa as usize].add(&constraint);
+                    quadrics[pb as usize].add(&constraint);
+                }
+            }
+        }
+    }
+
+    let max_cost = (max_error as f64) * (max_error as f64);
+    let mut error = 0.0f64;
+
+    loop {
+        // Drop the triangles collapsed in the previous pass
+        let mut live = Vec::with_capacity(indices.len());
+        let mut live_submeshes = Vec::with_capacity(submeshes.len());
+        for t in 0..indices.len() / 3 {
+            let (a, b, c) = (corner(&indices, t, 0), corner(&indices, t, 1), corner(&indices, t, 2));
+            if a != b && b != c && a != c {
+                live.extend_from_slice(&indices[3 * t..3 * t + 3]);
+                live_submeshes.push(submeshes[t]);
+            }
+        }
+        indices = live;
+        submeshes = live_submeshes;
+
+        let mut triangle_count = indices.len() / 3;
+        if triangle_count <= target_triangles {
+            break;
+        }
+
+        let topology = Topology::build(&indices, &wedge_position, &submeshes, positions.len());
+
+        let mut candidates = Vec::new();
+        for (&(a, b), edge) in &topology.edges {
+            let feature = is_feature(edge, &submeshes);
+            let mut best: Option<Collapse> = None;
+
+            for &(from, to) in &[(a, b), (b, a)] {
+                let allowed = match topology.kinds[from as usize] {
+                    Kind::Interior => !feature,
+                    Kind::Feature => feature && edge.len() <= 2,
+                    Kind::Locked => false
+                };
+                if !allowed {
+                    continue;
+                }
+
+                // Every wedge of the collapsing position has to move to one wedge of the target
+                let mut wedges: Vec<(u32, u32)> = Vec::new();
+                let mut consistent = true;
+                for &(_, wa, wb) in edge {
+                    let (w_from, w_to) = if from == a { (wa, wb) } else { (wb, wa) };
+                    match wedges.iter().find(|&&(f, _)| f == w_from) {
+                        Some(&(_, t)) if t != w_to => consistent = false,
+                        Some(_) => (),
+                        None => wedges.push((w_from, w_to))
+                    }
+                }
+                let wedge_count = topology.triangles[from as usize].iter()
+                    .flat_map(|&t| indices[3 * t..3 * t + 3].iter())
+                    .filter(|&&w| wedge_position[w as usize] == from)
+                    .fold(Vec::new(), |mut seen, &w| {
+                        if !seen.contains(&w) {
+                            seen.push(w);
+                        }
+                        seen
+                    }).len();
+                if !consistent || wedges.len() != wedge_count {
+                    continue;
+                }
+
+                let mut quadric = quadrics[from as usize];
+                quadric.add(&quadrics[to as usize]);
+                let cost = quadric.error(positions[to as usize]);
+
+                if best.as_ref().map_or(true, |b| cost < b.cost) {
+                    best = Some(Collapse { cost: cost, from: from, to: to, edge: (a, b), wedges: wedges });
+                }
+            }
+
+            if let Some(collapse) = best {
+                candidates.push(collapse);
+            }
+        }
+
+        candidates.sort_by(|a, b| a.cost.partial_cmp(&b.cost).unwrap_or(::std::cmp::Ordering::Equal));
+
+        // Collapses within one pass must not touch each others neighborhood, the geometry they
+        // are checked against is only updated in the next pass
+        let mut touched = vec![false; positions.len()];
+        let mut remap: Vec<u32> = (0..model.vertices.len() as u32).collect();
+        let mut collapsed = 0;
+
+        for collapse in &candidates {
+            if triangle_count <= target_triangles || collapse.cost > max_cost {
+                break;
+            }
+            let (from, to) = (collapse.from as usize, collapse.to as usize);
+            if touched[from] || touched[to] {
+                continue;
+            }
+
+            // Reject collapses that would flip a triangle around the collapsing position
+            let flips = topology.triangles[from].iter().any(|&t| {
+                let p = [corner(&indices, t, 0), corner(&indices, t, 1), corner(&indices, t, 2)];
+                if p.contains(&collapse.to) {
+                    return false;
+                }
+                let before: Vec<[f64; 3]> = p.iter().map(|&q| positions[q as usize]).collect();
+                let after: Vec<[f64; 3]> = p.iter().map(|&q| positions[if q as usize == from { to } else { q as usize }]).collect();
+                let n0 = cross(sub(before[1], before[0]), sub(before[2], before[0]));
+                let n1 = cross(sub(after[1], after[0]), sub(after[2], after[0]));
+                dot(n0, n1) <= 0.0
+            });
+            if flips {
+                continue;
+            }
+
+            for &(w_from, w_to) in &collapse.wedges {
+                remap[w_from as usize] = w_to;
+            }
+            let from_quadric = quadrics[from];
+            quadrics[to].add(&from_quadric);
+
+            triangle_count -= topology.edges[&collapse.edge].len();
+            error = error.max(collapse.cost);
+            collapsed += 1;
+
+            for &t in &topology.triangles[from] {
+                for i in 0..3 {
+                    touched[corner(&indices, t, i) as usize] = true;
+                }
+            }
+        }
+
+        if collapsed == 0 {
+            break;
+        }
+
+        for index in indices.iter_mut() {
+            *index = remap[*index as usize];
+        }
+    }
+
+    // Triangles back in submesh order, then only the wedges still in use
+    let mut order: Vec<usize> = (0..indices.len() / 3).collect();
+    order.sort_by_key(|&t| submeshes[t]);
+
+    let mut simplified = Model {
+        vertices: model.vertices.clone(),
+        indices: order.iter().flat_map(|&t| indices[3 * t..3 * t + 3].iter().cloned()).collect(),
+        normals: model.normals.clone(),
+        texcoords: model.texcoords.clone(),
+        bounds: model.bounds,
+        submeshes: Vec::with_capacity(model.submeshes.len())
+    };
+
+    let mut first_index = 0;
+    for (i, submesh) in model.submeshes.iter().enumerate() {
+        let index_count = 3 * submeshes.iter().filter(|&&s| s == i as u32).count() as u32;
+        simplified.submeshes.push(Submesh {
+            name: submesh.name.clone(),
+            material: submesh.material.clone(),
+            first_index: first_index,
+            index_count: index_count,
+            bounds: submesh.bounds
+        });
+        first_index += index_count;
+    }
+
+    optimize_vertex_fetch(&mut simplified);
+    if !simplified.vertices.is_empty() {
+        simplified.update_bounds();
+    }
+
+    Lod {
+        model: simplified,
+        error: error.sqrt() as f32
+    }
+}
+
+pub struct LodSettings {
+    // Number of simplified levels generated below the full resolution one
+    pub levels: u32,
+    // Triangle count of each level relative to the previous one
+    pub ratio: f32,
+    // Largest allowed error relative to the bounding box diagonal
+    pub max_error: f32
+}
+
+impl LodSettings {
+    pub fn default() -> LodSettings {
+        LodSettings {
+            levels: 4,
+            ratio: 0.5,
+            max_error: 0.05
+        }
+    }
+
+    pub fn levels(self, levels: u32) -> LodSettings {
+        LodSettings {
+            levels: levels,
+            .. self
+        }
+    }
+
+    pub fn ratio(self, ratio: f32) -> LodSettings {
+        LodSettings {
+            ratio: ratio,
+            .. self
+        }
+    }
+
+    pub fn max_error(self, max_error: f32) -> LodSettings {
+        LodSettings {
+            max_error: max_error,
+            .. self
+        }
+    }
+}
+
+// The full resolution model followed by progressively simpler levels, each simplified from the
+// one before. Stops early once a level cannot be reduced any further. Each level measures its error
+// against the one before, so the errors add up to bound the distance to the full model.
+pub fn lod_chain(model: &Model, settings: &LodSettings) -> Vec<Lod> {
+    let max_error = settings.max_error * model.bounds.diagonal();
+
+    let mut chain = vec![Lod { model: model.clone(), error: 0.0 }];
+    for _ in 0..settings.levels {
+        let next = {
+            let previous = chain.last().unwrap();
+            let target = (previous.triangle_count() as f32 * settings.ratio) as usize;
+            let mut next = simplify(&previous.model, target, max_error);
+            next.error = previous.error + next.error;
+            next
+        };
+
+        if next.triangle_count() >= chain.last().unwrap().triangle_count() {
+            break;
+        }
+        chain.push(next);
+    }
+
+    chain
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use bounds::Aabb;
+    use obj_loader::{compute_bounds, Normal, TexCoord, Vertex};
+
+    // A size x size grid of quads in the xy plane with heights from the given function, and a UV
+    // seam down the middle column where the texcoords jump by 0.5
+    fn grid<F: Fn(u32, u32) -> f32>(size: u32, height: F) -> Model {
+        let seam = size / 2;
+        let mut vertices = Vec::new();
+        let mut texcoords = Vec::new();
+        let mut ids = HashMap::new();
+        for y in 0..size + 1 {
+            for x in 0..size + 1 {
+                for &right in &[false, true] {
+                    if x == seam || right == (x > seam) {
+                        ids.insert((x, y, right), vertices.len() as u32);
+                        vertices.push(Vertex { position: (x as f32, y as f32, height(x, y)) });
+                        texcoords.push(TexCoord { texcoord: (x as f32 / size as f32 + if right { 0.5 } else { 0.0 }, y as f32 / size as f32) });
+                    }
+                }
+            }
+        }
+
+        let mut indices = Vec::new();
+        for y in 0..size {
+            for x in 0..size {
+                let right = x >= seam;
+                let id = |dx: u32, dy: u32| ids[&(x + dx, y + dy, right)];
+                indices.extend_from_slice(&[id(0, 0), id(1, 0), id(0, 1), id(0, 1), id(1, 0), id(1, 1)]);
+            }
+        }
+
+        let normals = vec![Normal { normal: (0.0, 0.0, 1.0) }; vertices.len()];
+        let bounds = compute_bounds(&vertices);
+        let submeshes = vec![Submesh { name: "grid".to_string(), material: None, first_index: 0, index_count: indices.len() as u32, bounds: bounds }];
+        Model { vertices: vertices, indices: indices, normals: normals, texcoords: texcoords, bounds: Aabb::empty(), submeshes: submeshes }
+    }
+
+    #[test]
+    fn flat_grid_reaches_the_target_and_keeps_its_outline_and_seam() {
+        let model = grid(8, |_, _| 0.0);
+        let lod = simplify(&model, 32, 1.0);
+        let simplified = &lod.model;
+        assert!(lod.triangle_count() <= 32 && lod.triangle_count() > 0, "{} triangles", lod.triangle_count());
+
+        // Boundary vertices only slide along the border, so the grid is still fully covered
+        let mut area = 0.0;
+        for corners in simplified.indices.chunks(3) {
+            let p: Vec<(f32, f32, f32)> = corners.iter().map(|&i| simplified.vertices[i as usize].position).collect();
+            area += ((p[1].0 - p[0].0) * (p[2].1 - p[0].1) - (p[2].0 - p[0].0) * (p[1].1 - p[0].1)) * 0.5;
+
+            // No triangle crosses the seam, and every corner keeps the texcoord of its side
+            let right = p.iter().any(|v| v.0 > 4.0);
+            assert!(p.iter().all(|v| if right { v.0 >= 4.0 } else { v.0 <= 4.0 }));
+            for &i in corners {
+                let (x, y, _) = simplified.vertices[i as usize].position;
+                let u = x / 8.0 + if right { 0.5 } else { 0.0 };
+                assert_eq!(simplified.texcoords[i as usize].texcoord, (u, y / 8.0));
+            }
+        }
+        assert!((area - 64.0).abs() < 1e-4, "area {}", area);
+
+        // Corners of the grid and the ends of the seam are locked
+        for &corner in &[(0.0, 0.0), (8.0, 0.0), (0.0, 8.0), (8.0, 8.0), (4.0, 0.0), (4.0, 8.0)] {
+            assert!(simplified.vertices.iter().any(|v| (v.position.0, v.position.1) == corner), "{:?}", corner);
+        }
+        assert_eq!(simplified.submeshes[0].index_count as usize, simplified.indices.len());
+    }
+
+    #[test]
+    fn error_stays_below_max_error() {
+        let model = grid(12, |x, y| ((x * 7 + y * 13) as f32).sin() * 0.05);
+        let max_error = 0.02;
+        let lod = simplify(&model, 0, max_error);
+
+        assert!(lod.triangle_count() < model.indices.len() / 3);
+        assert!(lod.error > 0.0 && lod.error <= max_error, "error {}", lod.error);
+    }
+
+    #[test]
+    fn chain_errors_add_up_across_levels() {
+        let mut model = grid(16, |x, y| ((x * 7 + y * 13) as f32).sin() * 0.05);
+        model.bounds = compute_bounds(&model.vertices);
+        let settings = LodSettings::default();
+        let chain = lod_chain(&model, &settings);
+        assert!(chain.len() >= 3, "{} levels", chain.len());
+
+        let max_error = settings.max_error * model.bounds.diagonal();
+        for pair in chain.windows(2) {
+            let target = (pair[0].triangle_count() as f32 * settings.ratio) as usize;
+            let step = simplify(&pair[0].model, target, max_error).error;
+            assert!(step > 0.0);
+            assert!((pair[1].error - (pair[0].error + step)).abs() < 1e-6, "{} after {} and {}", pair[1].error, pair[0].error, step);
+        }
+    }
+}
@@ -0,0 +1,423 @@
+use obj_loader::{Model, Normal};
+
+use std::collections::HashMap;
+use std::fmt;
+
+// Checks a model for the kinds of broken data that otherwise only show up as rendering garbage,
+// and repairs the ones that have an unambiguous fix
+
+const MAX_SAMPLES: usize = 8;
+// Triangles thinner than this fraction of the bounding box diagonal count as degenerate, below
+// that their normal is mostly float noise
+const DEGENERATE_HEIGHT: f32 = 1e-6;
+
+#[derive(Debug, Default)]
+pub struct Problem {
+    pub count: usize,
+    // The first few offending indices, what they index is given by the problem
+    pub samples: Vec<usize>
+}
+
+impl Problem {
+    fn add(&mut self, index: usize) {
+        self.count += 1;
+        if self.samples.len() < MAX_SAMPLES {
+            self.samples.push(index);
+        }
+    }
+}
+
+#[derive(Debug, Default)]
+pub struct ValidationReport {
+    // Vertex indices
+    pub nan_positions: Problem,
+    // Index buffer positions
+    pub out_of_range_indices: Problem,
+    // Triangle indices, repeated vertices or no area
+    pub degenerate_triangles: Problem,
+    // Vertex indices
+    pub zero_length_normals: Problem,
+    // Triangle indices, one triangle per edge used by more than two triangles
+    pub non_manifold_edges: Problem,
+    // Triangle indices, triangles facing away from their vertex normals
+    pub flipped_winding: Problem,
+    // Triangle indices, triangles wound against a neighbor across a shared edge
+    pub inconsistent_winding: Problem
+}
+
+impl ValidationReport {
+    fn problems(&self) -> Vec<(&'static str, &'static str, &Problem)> {
+        vec![
+            ("NaN positions", "vertices", &self.nan_positions),
+            ("out of range indices", "index buffer positions", &self.out_of_range_indices),
+            ("degenerate triangles", "triangles", &self.degenerate_triangles),
+            ("zero length normals", "vertices", &self.zero_length_normals),
+            ("non-manifold edges", "triangles", &self.non_manifold_edges),
+            ("flipped winding", "triangles", &self.flipped_winding),
+            ("inconsistent winding", "triangles", &self.inconsistent_winding)
+        ]
+    }
+
+    pub fn is_clean(&self) -> bool {
+        self.problems().iter().all(|&(_, _, p)| p.count == 0)
+    }
+}
+
+impl fmt::Display for ValidationReport {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        if self.is_clean() {
+            return writeln!(f, "no problems found");
+        }
+
+        for (name, kind, problem) in self.problems() {
+            if problem.count == 0 {
+                continue;
+            }
+            let samples: Vec<String> = problem.samples.iter().map(|s| s.to_string()).collect();
+            let more = if problem.count > problem.samples.len() { ", ..." } else { "" };
+            writeln!(f, "{} {} ({} {}{})", problem.count, name, kind, samples.join(", "), more)?;
+        }
+        Ok(())
+    }
+}
+
+type Vec3 = (f32, f32, f32);
+
+fn sub(a: Vec3, b: Vec3) -> Vec3 {
+    (a.0 - b.0, a.1 - b.1, a.2 - b.2)
+}
+
+fn cross(a: Vec3, b: Vec3) -> Vec3 {
+    (a.1 * b.2 - a.2 * b.1, a.2 * b.0 - a.0 * b.2, a.0 * b.1 - a.1 * b.0)
+}
+
+fn dot(a: Vec3, b: Vec3) -> f32 {
+    a.0 * b.0 + a.1 * b.1 + a.2 * b.2
+}
+
+fn is_nan(p: Vec3) -> bool {
+    p.0.is_nan() || p.1.is_nan() || p.2.is_nan()
+}
+
+fn face_normal(model: &Model, corners: &[u32]) -> Vec3 {
+    let (a, b, c) = (model.vertices[corners[0] as usize].position,
+                     model.vertices[corners[1] as usize].position,
+                     model.vertices[corners[2] as usize].position);
+    cross(sub(b, a), sub(c, a))
+}
+
+fn is_degenerate(model: &Model, corners: &[u32], min_height: f32) -> bool {
+    if corners[0] == corners[1] || corners[1] == corners[2] || corners[0] == corners[2] {
+        return true;
+    }
+    let p: Vec<Vec3> = corners.iter().map(|&i| model.vertices[i as usize].position).collect();
+    let longest = (0..3).map(|i| {
+        let e = sub(p[(i + 1) % 3], p[i]);
+        dot(e, e)
+    }).fold(0.0, f32::max).sqrt();
+    let n = face_normal(model, corners);
+    // Twice the area over the longest edge is twice the smallest height
+    !(dot(n, n).sqrt() > 2.0 * min_height * longest)
+}
+
+pub fn validate(model: &Model) -> ValidationReport {
+    let mut report = ValidationReport::default();
+    let vertex_count = model.vertices.len();
+
+    for (i, v) in model.vertices.iter().enumerate() {
+        if is_nan(v.position) {
+            report.nan_positions.add(i);
+        }
+    }
+
+    for (i, n) in model.normals.iter().enumerate() {
+        if !(dot(n.normal, n.normal) > 1e-12) {
+            report.zero_length_normals.add(i);
+        }
+    }
+
+    for (i, &index) in model.indices.iter().enumerate() {
+        if index as usize >= vertex_count {
+            report.out_of_range_indices.add(i);
+        }
+    }
+
+    // Only triangles with valid indices can be checked any further
+    let min_height = DEGENERATE_HEIGHT * model.bounds.diagonal();
+    let valid = |corners: &[u32]| corners.iter().all(|&i| (i as usize) < vertex_count);
+
+    // Edges between positions, so seams with split attributes still count as connected
+    let mut position_ids: HashMap<[u32; 3], u32> = HashMap::new();
+    let position_of: Vec<u32> = model.vertices.iter().map(|v| {
+        let next = position_ids.len() as u32;
+        *position_ids.entry([v.position.0.to_bits(), v.position.1.to_bits(), v.position.2.to_bits()]).or_insert(next)
+    }).collect();
+    let mut edges: HashMap<(u32, u32), Vec<(usize, bool)>> = HashMap::new();
+
+    for (t, corners) in model.indices.chunks(3).enumerate() {
+        if corners.len() < 3 || !valid(corners) {
+            continue;
+        }
+        if is_degenerate(model, corners, min_height) {
+            report.degenerate_triangles.add(t);
+            continue;
+        }
+
+        if model.normals.len() == vertex_count {
+            let n = face_normal(model, corners);
+            let shading = corners.iter().fold((0.0, 0.0, 0.0), |s, &i| {
+                let v = model.normals[i as usize].normal;
+                (s.0 + v.0, s.1 + v.1, s.2 + v.2)
+            });
+            if dot(n, shading) < 0.0 {
+                report.flipped_winding.add(t);
+            }
+        }
+
+        for i in 0..3 {
+            let (a, b) = (position_of[corners[i] as usize], position_of[corners[(i + 1) % 3] as usize]);
+            let (key, forward) = if a < b { ((a, b), true) } else { ((b, a), false) };
+            edges.entry(key).or_insert_with(Vec::new).push((t, forward));
+        }
+    }
+
+    let mut edges: Vec<(&(u32, u32), &Vec<(usize, bool)>)> = edges.iter().collect();
+    edges.sort_by_key(|&(key, _)| *key);
+    for (_, users) in edges {
+        if users.len() > 2 {
+            report.non_manifold_edges.add(users[0].0);
+        } else if users.len() == 2 && users[0].1 == users[1].1 {
+            // Two consistently wound neighbors traverse their shared edge in opposite directions
+            report.inconsistent_winding.add(users[1].0);
+        }
+    }
+
+    report
+}
+
+pub struct RepairOptions {
+    pub clamp_indices: bool,
+    pub drop_degenerates: bool,
+    pub renormalize_normals: bool
+}
+
+impl RepairOptions {
+    pub fn all() -> RepairOptions {
+        RepairOptions {
+            clamp_indices: true,
+            drop_degenerates: true,
+            renormalize_normals: true
+        }
+    }
+}
+
+#[derive(Debug, Default)]
+pub struct RepairReport {
+    pub clamped_indices: usize,
+    pub dropped_triangles: usize,
+    pub renormalized_normals: usize,
+    // Zero length normals replaced by the normal of the surrounding faces
+    pub rebuilt_normals: usize
+}
+
+impl fmt::Display for RepairReport {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        writeln!(f, "clamped {} indices", self.clamped_indices)?;
+        writeln!(f, "dropped {} degenerate triangles", self.dropped_triangles)?;
+        write!(f, "renormalized {} normals, rebuilt {} zero length normals", self.renormalized_normals, self.rebuilt_normals)
+    }
+}
+
+// Applies the fixes that cannot make a valid model worse. NaN positions, non-manifold edges and
+// winding problems need a decision about the intended surface and are only reported.
+pub fn repair(model: &mut Model, options: &RepairOptions) -> RepairReport {
+    let mut report = RepairReport::default();
+    let vertex_count = model.vertices.len();
+
+    if options.clamp_indices && vertex_count > 0 {
+        for index in model.indices.iter_mut() {
+            if *index as usize >= vertex_count {
+                *index = vertex_count as u32 - 1;
+                report.clamped_indices += 1;
+            }
+        }
+    }
+
+    if options.drop_degenerates {
+        let min_height = DEGENERATE_HEIGHT * model.bounds.diagonal();
+        let mut indices = Vec::with_capacity(model.indices.len());
+        let mut ranges = Vec::with_capacity(model.submeshes.len());
+
+        for submesh in model.submeshes.iter() {
+            let start = submesh.first_index as usize;
+            let end = start + submesh.index_count as usize;
+            let first_index = indices.len();
+
+            for corners in model.indices[start..end].chunks(3) {
+                let valid = corners.len() == 3 && corners.iter().all(|&i| (i as usize) < vertex_count);
+                if valid && !is_degenerate(model, corners, min_height) {
+                    indices.extend_from_slice(corners);
+                } else {
+                    report.dropped_triangles += 1;
+                }
+            }
+
+            ranges.push((first_index as u32, (indices.len() - first_index) as u32));
+        }
+
+        for (submesh, (first_index, index_count)) in model.submeshes.iter_mut().zip(ranges) {
+            submesh.first_index = first_index;
+            submesh.index_count = index_count;
+        }
+        model.indices = indices;
+        model.update_bounds();
+    }
+
+    if options.renormalize_normals && model.normals.len() == vertex_count {
+        // Face normals accumulated per vertex, the fallback for normals without a direction
+        let mut face_normals = vec![(0.0, 0.0, 0.0); vertex_count];
+        for corners in model.indices.chunks(3) {
+            if corners.len() < 3 || !corners.iter().all(|&i| (i as usize) < vertex_count) {
+                continue;
+            }
+            let n = face_normal(model, corners);
+            for &i in corners {
+                let s = face_normals[i as usize];
+                face_normals[i as usize] = (s.0 + n.0, s.1 + n.1, s.2 + n.2);
+            }
+        }
+
+        for (i, normal) in model.normals.iter_mut().enumerate() {
+            let n = normal.normal;
+            let length = dot(n, n).sqrt();
+            if length > 1e-6 {
+                if (length - 1.0).abs() > 1e-4 {
+                    *normal = Normal { normal: (n.0 / length, n.1 / length, n.2 / length) };
+                    report.renormalized_normals += 1;
+                }
+            } else {
+                let f = face_normals[i];
+                let length = dot(f, f).sqrt();
+                *normal = Normal { normal: if length > 0.0 { (f.0 / length, f.1 / length, f.2 / length) } else { (0.0, 0.0, 1.0) } };
+                report.rebuilt_normals += 1;
+            }
+        }
+    }
+
+    report
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use bounds::Aabb;
+    use obj_loader::{compute_bounds, Submesh, Vertex};
+
+    use std::f32;
+
+    // A unit quad of two triangles facing +z, every test breaks it in one way
+    fn quad() -> Model {
+        let vertices = vec![
+            Vertex { position: (0.0, 0.0, 0.0) },
+            Vertex { position: (1.0, 0.0, 0.0) },
+            Vertex { position: (1.0, 1.0, 0.0) },
+            Vertex { position: (0.0, 1.0, 0.0) }
+        ];
+        let indices = vec![0, 1, 2, 0, 2, 3];
+        let bounds = compute_bounds(&vertices);
+        Model {
+            normals: vec![Normal { normal: (0.0, 0.0, 1.0) }; vertices.len()],
+            texcoords: Vec::new(),
+            submeshes: vec![Submesh { name: "quad".to_string(), material: None, first_index: 0, index_count: indices.len() as u32, bounds: bounds }],
+            vertices: vertices,
+            indices: indices,
+            bounds: bounds
+        }
+    }
+
+    fn add_triangle(model: &mut Model, corners: &[u32]) {
+        model.indices.extend_from_slice(corners);
+        model.submeshes[0].index_count += 3;
+    }
+
+    #[test]
+    fn clean_quad_has_no_problems() {
+        assert!(validate(&quad()).is_clean());
+    }
+
+    #[test]
+    fn nan_position() {
+        let mut model = quad();
+        model.vertices[3].position = (f32::NAN, 1.0, 0.0);
+        let report = validate(&model);
+        assert_eq!((report.nan_positions.count, report.nan_positions.samples.clone()), (1, vec![3]));
+    }
+
+    #[test]
+    fn out_of_range_index() {
+        let mut model = quad();
+        model.indices[5] = 7;
+        let report = validate(&model);
+        assert_eq!((report.out_of_range_indices.count, report.out_of_range_indices.samples.clone()), (1, vec![5]));
+        // The broken triangle is not looked at any further
+        assert_eq!(report.degenerate_triangles.count, 0);
+    }
+
+    #[test]
+    fn repeated_vertex_triangle() {
+        let mut model = quad();
+        add_triangle(&mut model, &[0, 1, 1]);
+        let report = validate(&model);
+        assert_eq!((report.degenerate_triangles.count, report.degenerate_triangles.samples.clone()), (1, vec![2]));
+    }
+
+    #[test]
+    fn zero_length_normal() {
+        let mut model = quad();
+        model.normals[2] = Normal { normal: (0.0, 0.0, 0.0) };
+        let report = validate(&model);
+        assert_eq!((report.zero_length_normals.count, report.zero_length_normals.samples.clone()), (1, vec![2]));
+        assert_eq!(report.flipped_winding.count, 0);
+    }
+
+    #[test]
+    fn three_triangles_on_one_edge() {
+        let mut model = quad();
+        model.vertices.push(Vertex { position: (0.5, 0.5, 1.0) });
+        model.normals.push(Normal { normal: (0.0, 0.0, 1.0) });
+        model.update_bounds();
+        add_triangle(&mut model, &[0, 2, 4]);
+        let report = validate(&model);
+        assert_eq!((report.non_manifold_edges.count, report.non_manifold_edges.samples.clone()), (1, vec![0]));
+    }
+
+    #[test]
+    fn flipped_neighbor() {
+        let mut model = quad();
+        model.indices[4..6].copy_from_slice(&[3, 2]);
+        let report = validate(&model);
+        assert_eq!((report.inconsistent_winding.count, report.inconsistent_winding.samples.clone()), (1, vec![1]));
+        assert_eq!((report.flipped_winding.count, report.flipped_winding.samples.clone()), (1, vec![1]));
+    }
+
+    #[test]
+    fn repair_keeps_submesh_ranges_after_dropping_triangles() {
+        let mut model = quad();
+        // First submesh: the quad with a degenerate triangle in between
+        model.indices = vec![0, 1, 2, 0, 1, 1, 0, 2, 3, 2, 2, 3, 1, 2, 9];
+        model.submeshes[0].index_count = 9;
+        model.submeshes.push(Submesh { name: "second".to_string(), material: None, first_index: 9, index_count: 6, bounds: Aabb::empty() });
+        model.normals[0] = Normal { normal: (0.0, 0.0, 2.0) };
+        model.normals[1] = Normal { normal: (0.0, 0.0, 0.0) };
+
+        let report = repair(&mut model, &RepairOptions::all());
+        assert_eq!((report.clamped_indices, report.dropped_triangles), (1, 2));
+        assert_eq!((report.renormalized_normals, report.rebuilt_normals), (1, 1));
+        assert_eq!((model.normals[0].normal, model.normals[1].normal), ((0.0, 0.0, 1.0), (0.0, 0.0, 1.0)));
+
+        assert_eq!(model.indices, vec![0, 1, 2, 0, 2, 3, 1, 2, 3]);
+        let ranges: Vec<(u32, u32)> = model.submeshes.iter().map(|s| (s.first_index, s.index_count)).collect();
+        assert_eq!(ranges, vec![(0, 6), (6, 3)]);
+        assert_eq!(model.submeshes[1].bounds, compute_bounds(&model.vertices[1..4]));
+    }
+}
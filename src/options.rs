@@ -0,0 +1,120 @@
+use camera_movement::physical::{parse_shutter, PhysicalCamera};
+
+use std::env;
+use std::path::PathBuf;
+
+pub struct Options {
+    pub asset: Option<PathBuf>,
+    pub lod: u32,
+    // Upper bound for anisotropic filtering of material textures, clamped to the device limit
+    pub anisotropy: f32,
+    // Run the mesh optimization stage after loading and print its cache statistics
+    pub optimize: bool,
+    // Print a report of broken mesh data after loading
+    pub validate: bool,
+    // Drop degenerate triangles, clamp indices and renormalize normals after loading
+    pub repair: bool,
+    // Number of simplified levels of detail generated below the loaded mesh
+    pub simplify: u32,
+    // Initial camera view, either a bookmark slot or a view in the text format of CameraView
+    pub view: Option<String>,
+    // Keyframe file, defaults to the .path file next to the model
+    pub camera_path: Option<PathBuf>,
+    // Play a turntable of this many seconds instead of the keyframes
+    pub turntable: Option<f32>,
+    // Render the camera path into this folder as an image sequence and exit
+    pub render: Option<PathBuf>,
+    // Frame rate of the rendered image sequence
+    pub fps: f32,
+    // Set once any camera setting is given, the focal length then replaces the field of view
+    pub physical: Option<PhysicalCamera>,
+    // Focus distance in meters, otherwise the camera focuses on the orbit target of the framed model
+    pub focus: Option<f32>,
+    // Blur by the depth of field of the physical camera
+    pub dof: bool
+}
+
+impl Options {
+    pub fn default() -> Options {
+        Options {
+            asset: None,
+            lod: 0,
+            anisotropy: 16.0,
+            optimize: false,
+            validate: false,
+            repair: false,
+            simplify: 0,
+            view: None,
+            camera_path: None,
+            turntable: None,
+            render: None,
+            fps: 30.0,
+            physical: None,
+            focus: None,
+            dof: false
+        }
+    }
+
+    pub fn from_args() -> Options {
+        let mut options = Options::default();
+        let mut args = env::args().skip(1);
+
+        while let Some(arg) = args.next() {
+            match arg.as_str() {
+                "--lod" => options.lod = args.next().and_then(|v| v.parse().ok())
+                    .expect("--lod expects a level of detail, e.g. --lod 1"),
+                "--anisotropy" => options.anisotropy = args.next().and_then(|v| v.parse().ok())
+                    .expect("--anisotropy expects a number, e.g. --anisotropy 8"),
+                "--optimize" => options.optimize = true,
+                "--validate" => options.validate = true,
+                "--repair" => options.repair = true,
+                "--simplify" => options.simplify = args.next().and_then(|v| v.parse().ok())
+                    .expect("--simplify expects the number of generated levels, e.g. --simplify 4"),
+                "--view" => options.view = Some(args.next()
+                    .expect("--view expects a bookmark slot or a view, e.g. --view 1")),
+                "--camera-path" => options.camera_path = Some(args.next().map(PathBuf::from)
+                    .expect("--camera-path expects a keyframe file, e.g. --camera-path review.path")),
+                "--turntable" => options.turntable = Some(args.next().and_then(|v| v.parse().ok())
+                    .expect("--turntable expects the duration in seconds, e.g. --turntable 8")),
+                "--render" => options.render = Some(args.next().map(PathBuf::from)
+                    .expect("--render expects an output folder, e.g. --render frames")),
+                "--fps" => options.fps = args.next().and_then(|v| v.parse().ok())
+                    .expect("--fps expects a frame rate, e.g. --fps 60"),
+                "--focal-length" => options.physical_camera().focal_length = args.next().and_then(|v| v.parse().ok())
+                    .expect("--focal-length expects millimeters, e.g. --focal-length 85"),
+                "--f-stop" => options.physical_camera().aperture = args.next().and_then(|v| v.parse().ok())
+                    .expect("--f-stop expects an f-number, e.g. --f-stop 2.8"),
+                "--shutter" => options.physical_camera().shutter = args.next().and_then(|v| parse_shutter(&v))
+                    .expect("--shutter expects seconds, e.g. --shutter 1/125"),
+                "--iso" => options.physical_camera().iso = args.next().and_then(|v| v.parse().ok())
+                    .expect("--iso expects a sensitivity, e.g. --iso 400"),
+                "--sensor" => {
+                    let size: Vec<f32> = args.next().map(|v| v.split('x').filter_map(|s| s.parse().ok()).collect())
+                        .unwrap_or_default();
+                    if size.len() != 2 {
+                        panic!("--sensor expects the sensor size in millimeters, e.g. --sensor 36x24");
+                    }
+                    let camera = options.physical_camera();
+                    camera.sensor_width = size[0];
+                    camera.sensor_height = size[1];
+                },
+                "--focus" => {
+                    options.physical_camera();
+                    options.focus = Some(args.next().and_then(|v| v.parse().ok())
+                        .expect("--focus expects a distance in meters, e.g. --focus 1.5"));
+                },
+                "--dof" => {
+                    options.physical_camera();
+                    options.dof = true;
+                },
+                _ => options.asset = Some(PathBuf::from(arg))
+            }
+        }
+
+        options
+    }
+
+    fn physical_camera(&mut self) -> &mut PhysicalCamera {
+        self.physical.get_or_insert(PhysicalCamera::default())
+    }
+}
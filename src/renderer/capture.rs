@@ -0,0 +1,43 @@
+use vulkano::buffer::{BufferUsage, CpuAccessibleBuffer};
+use vulkano::device::Queue;
+use vulkano::format::Format;
+
+use image;
+
+use std::path::{Path, PathBuf};
+use std::sync::Arc;
+
+// Reads rendered frames back for exporting image sequences. The swapchain image is copied into a
+// host visible buffer after drawing, so the export shows exactly what the window shows.
+
+// Host visible buffer for one frame with four bytes per pixel
+pub fn frame_buffer(queue: &Arc<Queue>, dimensions: [u32; 2]) -> Arc<CpuAccessibleBuffer<[u8]>> {
+    let size = (dimensions[0] * dimensions[1] * 4) as usize;
+    CpuAccessibleBuffer::from_iter(queue.device().clone(), BufferUsage::transfer_destination(),
+                                   Some(queue.family()), (0..size).map(|_| 0u8))
+        .expect("failed to create buffer")
+}
+
+// Numbered so the frames sort in playback order
+pub fn frame_path(directory: &Path, frame: u32) -> PathBuf {
+    directory.join(format!("frame_{:05}.png", frame))
+}
+
+pub fn save_frame(pixels: &[u8], dimensions: [u32; 2], format: Format, path: &Path) -> Result<(), String> {
+    let mut rgba = pixels.to_vec();
+    match format {
+        Format::B8G8R8A8Unorm | Format::B8G8R8A8Srgb => for pixel in rgba.chunks_mut(4) {
+            pixel.swap(0, 2);
+        },
+        Format::R8G8B8A8Unorm | Format::R8G8B8A8Srgb => (),
+        _ => return Err(format!("frames in {:?} cannot be saved", format))
+    }
+    // The window is opaque whatever ends up in alpha
+    for pixel in rgba.chunks_mut(4) {
+        pixel[3] = 255;
+    }
+
+    let frame = image::RgbaImage::from_raw(dimensions[0], dimensions[1], rgba)
+        .ok_or_else(|| format!("frame data does not match {}x{}", dimensions[0], dimensions[1]))?;
+    frame.save(path).map_err(|e| format!("could not write {}: {}", path.display(), e))
+}
@@ -0,0 +1,297 @@
+use vulkano::buffer::{BufferUsage, CpuAccessibleBuffer};
+use vulkano::buffer::cpu_pool::CpuBufferPool;
+use vulkano::command_buffer::{AutoCommandBufferBuilder, DynamicState};
+use vulkano::descriptor::descriptor::{DescriptorDesc, DescriptorDescTy, DescriptorBufferDesc, DescriptorImageDesc,
+                                      DescriptorImageDescArray, DescriptorImageDescDimensions, ShaderStages};
+use vulkano::descriptor::descriptor_set::PersistentDescriptorSet;
+use vulkano::descriptor::pipeline_layout::{PipelineLayoutDesc, PipelineLayoutDescPcRange};
+use vulkano::device::Device;
+use vulkano::format::{ClearValue, D32Sfloat, Format};
+use vulkano::framebuffer::{Framebuffer, FramebufferAbstract, RenderPassAbstract, Subpass};
+use vulkano::image::attachment::AttachmentImage;
+use vulkano::pipeline::{GraphicsPipeline, GraphicsPipelineAbstract};
+use vulkano::pipeline::shader::{GraphicsShaderType, ShaderModule};
+use vulkano::pipeline::vertex::SingleBufferDefinition;
+use vulkano::pipeline::viewport::Viewport;
+use vulkano::sampler::{Filter, MipmapMode, Sampler, SamplerAddressMode};
+
+use renderer::model_pipeline::{Interface, InterfaceEntry};
+use renderer::shader::ShaderProgram;
+use renderer::vulkan_init::VulkanInit;
+
+use std::ffi::CStr;
+use std::sync::Arc;
+
+// Depth of field as a post effect. The scene is drawn into an offscreen color and depth image with
+// the usual render pass, then shaders/dof.frag blurs it into the swapchain image by the thin lens
+// circle of confusion of every pixel.
+
+// Larger blurs are clamped, the gather covers a disk of this diameter in pixels
+const MAX_COC: f32 = 32.0;
+
+#[repr(C)]
+#[derive(Copy, Clone)]
+pub struct DofData {
+    pub inverse_proj: [[f32; 4]; 4],
+    pub texel_size: [f32; 2],
+    pub focus_distance: f32,
+    pub coc_scale: f32,
+    pub max_coc: f32
+}
+
+impl DofData {
+    pub fn new(inverse_proj: [[f32; 4]; 4], dimensions: [u32; 2], focus_distance: f32, coc_scale: f32) -> DofData {
+        DofData {
+            inverse_proj: inverse_proj,
+            texel_size: [1.0 / dimensions[0] as f32, 1.0 / dimensions[1] as f32],
+            focus_distance: focus_distance,
+            coc_scale: coc_scale,
+            max_coc: MAX_COC
+        }
+    }
+}
+
+#[derive(Copy, Clone)]
+pub struct PostVertex {
+    position: [f32; 2]
+}
+
+impl_vertex!(PostVertex, position);
+
+const VERTEX_INPUT: &'static [InterfaceEntry] = &[
+    (0, Format::R32G32Sfloat, "position")
+];
+
+const VARYINGS: &'static [InterfaceEntry] = &[
+    (0, Format::R32G32Sfloat, "v_texcoord")
+];
+
+const FRAGMENT_OUTPUT: &'static [InterfaceEntry] = &[
+    (0, Format::R32G32B32A32Sfloat, "f_color")
+];
+
+// The uniform buffer followed by the scene color and depth
+#[derive(Debug, Copy, Clone)]
+pub struct Layout(ShaderStages);
+
+unsafe impl PipelineLayoutDesc for Layout {
+    fn num_sets(&self) -> usize {
+        1
+    }
+
+    fn num_bindings_in_set(&self, set: usize) -> Option<usize> {
+        match set {
+            0 => Some(3),
+            _ => None
+        }
+    }
+
+    fn descriptor(&self, set: usize, binding: usize) -> Option<DescriptorDesc> {
+        match (set, binding) {
+            (0, 0) => Some(DescriptorDesc {
+                ty: DescriptorDescTy::Buffer(DescriptorBufferDesc {
+                    dynamic: Some(false),
+                    storage: false
+                }),
+                array_count: 1,
+                stages: self.0,
+                readonly: true
+            }),
+            (0, 1) | (0, 2) => Some(DescriptorDesc {
+                ty: DescriptorDescTy::CombinedImageSampler(DescriptorImageDesc {
+                    sampled: true,
+                    dimensions: DescriptorImageDescDimensions::TwoDimensional,
+                    format: None,
+                    multisampled: false,
+                    array_layers: DescriptorImageDescArray::NonArrayed
+                }),
+                array_count: 1,
+                stages: self.0,
+                readonly: true
+            }),
+            _ => None
+        }
+    }
+
+    fn num_push_constants_ranges(&self) -> usize {
+        0
+    }
+
+    fn push_constants_range(&self, _num: usize) -> Option<PipelineLayoutDescPcRange> {
+        None
+    }
+}
+
+fn build(device: Arc<Device>,
+         vs: &Arc<ShaderModule>,
+         fs: &Arc<ShaderModule>,
+         render_pass: Arc<RenderPassAbstract + Send + Sync>) -> Result<Arc<GraphicsPipelineAbstract + Send + Sync>, String> {
+    let main = unsafe { CStr::from_ptr(b"main\0".as_ptr() as *const _) };
+    let stages = ShaderStages { vertex: true, fragment: true, ..ShaderStages::none() };
+
+    let vertex_entry = unsafe {
+        vs.graphics_entry_point(main, Interface(VERTEX_INPUT), Interface(VARYINGS), Layout(stages), GraphicsShaderType::Vertex)
+    };
+    let fragment_entry = unsafe {
+        fs.graphics_entry_point(main, Interface(VARYINGS), Interface(FRAGMENT_OUTPUT), Layout(stages), GraphicsShaderType::Fragment)
+    };
+
+    let pipeline = GraphicsPipeline::start()
+        .vertex_input(SingleBufferDefinition::<PostVertex>::new())
+        .vertex_shader(vertex_entry, ())
+        .triangle_list()
+        .viewports_dynamic_scissors_irrelevant(1)
+        .fragment_shader(fragment_entry, ())
+        .render_pass(Subpass::from(render_pass, 0).unwrap())
+        .build(device)
+        .map_err(|e| format!("failed to create the depth of field pipeline: {:?}", e))?;
+
+    Ok(Arc::new(pipeline))
+}
+
+pub struct DepthOfField {
+    render_pass: Arc<RenderPassAbstract + Send + Sync>,
+    shader_program: ShaderProgram,
+    pipeline: Arc<GraphicsPipelineAbstract + Send + Sync>,
+    triangle: Arc<CpuAccessibleBuffer<[PostVertex]>>,
+    sampler: Arc<Sampler>,
+    uniform_buffer: CpuBufferPool<DofData>,
+    // The scene is drawn into these instead of the swapchain image
+    color: Arc<AttachmentImage>,
+    depth: Arc<AttachmentImage<D32Sfloat>>,
+    scene_framebuffer: Arc<FramebufferAbstract + Send + Sync>,
+    // One per swapchain image
+    framebuffers: Vec<Arc<FramebufferAbstract + Send + Sync>>
+}
+
+impl DepthOfField {
+    // The scene pass needs a color attachment in the swapchain format and a stored D32 depth attachment
+    pub fn new(vulkan_init: &VulkanInit, scene_pass: &Arc<RenderPassAbstract + Send + Sync>) -> Result<DepthOfField, String> {
+        let device = vulkan_init.device.clone();
+
+        let render_pass: Arc<RenderPassAbstract + Send + Sync> = Arc::new(
+            single_pass_renderpass!(device.clone(),
+                attachments: {
+                    color: {
+                        load: DontCare,
+                        store: Store,
+                        format: vulkan_init.swapchain.format(),
+                        samples: 1,
+                    }
+                },
+                pass: {
+                    color: [color],
+                    depth_stencil: {}
+                }
+            ).map_err(|e| format!("failed to create the depth of field render pass: {:?}", e))?
+        );
+
+        let mut shader_program = ShaderProgram::new("fullscreen.vert", "dof.frag");
+        let (vs, fs) = shader_program.load(&device)
+            .map_err(|err| format!("failed to compile the depth of field shaders:\n{}", err))?;
+        let pipeline = build(device.clone(), &vs, &fs, render_pass.clone())?;
+
+        let vertices = vec![
+            PostVertex { position: [-1.0, -1.0] },
+            PostVertex { position: [3.0, -1.0] },
+            PostVertex { position: [-1.0, 3.0] }
+        ];
+        let triangle = CpuAccessibleBuffer::from_iter(device.clone(), BufferUsage::vertex_buffer(),
+                                                      Some(vulkan_init.queue.family()), vertices.into_iter())
+            .expect("failed to create buffer");
+
+        // Depth cannot be filtered on every device, and the blur picks its own sample positions anyway
+        let sampler = Sampler::new(device.clone(), Filter::Nearest, Filter::Nearest, MipmapMode::Nearest,
+                                   SamplerAddressMode::ClampToEdge, SamplerAddressMode::ClampToEdge, SamplerAddressMode::ClampToEdge,
+                                   0.0, 1.0, 0.0, 0.0).expect("failed to create sampler");
+
+        let uniform_buffer = CpuBufferPool::new(device.clone(), BufferUsage::all(), Some(vulkan_init.queue.family()));
+
+        let (color, depth, scene_framebuffer, framebuffers) = DepthOfField::targets(vulkan_init, scene_pass, &render_pass);
+
+        Ok(DepthOfField {
+            render_pass: render_pass,
+            shader_program: shader_program,
+            pipeline: pipeline,
+            triangle: triangle,
+            sampler: sampler,
+            uniform_buffer: uniform_buffer,
+            color: color,
+            depth: depth,
+            scene_framebuffer: scene_framebuffer,
+            framebuffers: framebuffers
+        })
+    }
+
+    fn targets(vulkan_init: &VulkanInit,
+               scene_pass: &Arc<RenderPassAbstract + Send + Sync>,
+               render_pass: &Arc<RenderPassAbstract + Send + Sync>)
+               -> (Arc<AttachmentImage>, Arc<AttachmentImage<D32Sfloat>>, Arc<FramebufferAbstract + Send + Sync>, Vec<Arc<FramebufferAbstract + Send + Sync>>) {
+        let device = vulkan_init.device.clone();
+        let color = AttachmentImage::sampled(device.clone(), vulkan_init.dimensions, vulkan_init.swapchain.format()).unwrap();
+        let depth = AttachmentImage::sampled(device.clone(), vulkan_init.dimensions, D32Sfloat).unwrap();
+
+        let scene_framebuffer = Arc::new(Framebuffer::start(scene_pass.clone())
+            .add(color.clone()).unwrap()
+            .add(depth.clone()).unwrap()
+            .build().unwrap()) as Arc<FramebufferAbstract + Send + Sync>;
+
+        let framebuffers = vulkan_init.images.iter().map(|image| {
+            Arc::new(Framebuffer::start(render_pass.clone())
+                .add(image.clone()).unwrap()
+                .build().unwrap()) as Arc<FramebufferAbstract + Send + Sync>
+        }).collect();
+
+        (color, depth, scene_framebuffer, framebuffers)
+    }
+
+    // Recreates the offscreen images and framebuffers once the swapchain has been recreated
+    pub fn resize(&mut self, vulkan_init: &VulkanInit, scene_pass: &Arc<RenderPassAbstract + Send + Sync>) {
+        let (color, depth, scene_framebuffer, framebuffers) = DepthOfField::targets(vulkan_init, scene_pass, &self.render_pass);
+        self.color = color;
+        self.depth = depth;
+        self.scene_framebuffer = scene_framebuffer;
+        self.framebuffers = framebuffers;
+    }
+
+    // Rebuilds the pipeline when the shaders changed on disk
+    pub fn reload(&mut self, device: &Arc<Device>) {
+        if let Some((vs, fs)) = self.shader_program.reload(device) {
+            match build(device.clone(), &vs, &fs, self.render_pass.clone()) {
+                Ok(pipeline) => self.pipeline = pipeline,
+                Err(err) => println!("{}", err)
+            }
+        }
+    }
+
+    // Where the scene is drawn while depth of field is on
+    pub fn scene_framebuffer(&self) -> Arc<FramebufferAbstract + Send + Sync> {
+        self.scene_framebuffer.clone()
+    }
+
+    // Blurs the scene into the swapchain image, after the scene render pass has ended
+    pub fn draw(&self, command_buffer: AutoCommandBufferBuilder, image_num: usize, dimensions: [u32; 2], data: DofData) -> AutoCommandBufferBuilder {
+        let set = Arc::new(PersistentDescriptorSet::start(self.pipeline.clone(), 0)
+            .add_buffer(self.uniform_buffer.next(data)).unwrap()
+            .add_sampled_image(self.color.clone(), self.sampler.clone()).unwrap()
+            .add_sampled_image(self.depth.clone(), self.sampler.clone()).unwrap()
+            .build().unwrap()
+        );
+
+        command_buffer
+            .begin_render_pass(self.framebuffers[image_num].clone(), false, vec![ClearValue::None]).unwrap()
+            .draw(
+                self.pipeline.clone(),
+                DynamicState {
+                    line_width: None,
+                    viewports: Some(vec![Viewport {
+                        origin: [0.0, 0.0],
+                        dimensions: [dimensions[0] as f32, dimensions[1] as f32],
+                        depth_range: 0.0..1.0,
+                    }]),
+                    scissors: None,
+                },
+                vec![self.triangle.clone()], set, ()).unwrap()
+            .end_render_pass().unwrap()
+    }
+}
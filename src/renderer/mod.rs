@@ -1,2 +1,7 @@
 pub mod vulkan_init;
-pub mod renderer;
\ No newline at end of file
+pub mod capture;
+pub mod renderer;
+pub mod shader;
+pub mod model_pipeline;
+pub mod depth_of_field;
+pub mod texture;
\ No newline at end of file
@@ -0,0 +1,186 @@
+use vulkano::descriptor::descriptor::{DescriptorDesc, DescriptorDescTy, DescriptorBufferDesc, DescriptorImageDesc,
+                                      DescriptorImageDescArray, DescriptorImageDescDimensions, ShaderStages};
+use vulkano::descriptor::pipeline_layout::{PipelineLayoutDesc, PipelineLayoutDescPcRange};
+use vulkano::device::Device;
+use vulkano::format::Format;
+use vulkano::framebuffer::{RenderPassAbstract, Subpass};
+use vulkano::pipeline::{GraphicsPipeline, GraphicsPipelineAbstract};
+use vulkano::pipeline::depth_stencil::{Compare, DepthStencil};
+use vulkano::pipeline::shader::{GraphicsShaderType, ShaderInterfaceDef, ShaderInterfaceDefEntry, ShaderModule};
+use vulkano::pipeline::vertex::SingleBufferDefinition;
+
+use obj_loader::ModelVertex;
+use material::{Material, NormalConvention};
+
+use std::borrow::Cow;
+use std::ffi::CStr;
+use std::sync::Arc;
+
+// The interface of shaders/model.vert and shaders/model.frag. Shaders are compiled at runtime,
+// so the layout vulkano_shader_derive used to generate has to be spelled out here.
+
+#[repr(C)]
+#[derive(Copy, Clone)]
+pub struct Data {
+    pub world: [[f32; 4]; 4],
+    pub view: [[f32; 4]; 4],
+    pub proj: [[f32; 4]; 4],
+    pub exposure: f32
+}
+
+#[repr(C)]
+#[derive(Copy, Clone)]
+pub struct MaterialData {
+    pub base_color: [f32; 4],
+    pub uv_tiling_offset: [f32; 4],
+    pub metallic: f32,
+    pub roughness: f32,
+    pub normal_strength: f32,
+    pub normal_green_sign: f32
+}
+
+impl<'a> From<&'a Material> for MaterialData {
+    fn from(material: &'a Material) -> MaterialData {
+        MaterialData {
+            base_color: material.factors.base_color,
+            uv_tiling_offset: [material.uv.tiling[0], material.uv.tiling[1], material.uv.offset[0], material.uv.offset[1]],
+            metallic: material.factors.metallic,
+            roughness: material.factors.roughness,
+            normal_strength: material.normal.strength,
+            normal_green_sign: match material.normal.convention {
+                NormalConvention::OpenGl => 1.0,
+                NormalConvention::DirectX => -1.0
+            }
+        }
+    }
+}
+
+pub type InterfaceEntry = (u32, Format, &'static str);
+
+const VERTEX_INPUT: &'static [InterfaceEntry] = &[
+    (0, Format::R32G32B32Sfloat, "position"),
+    (1, Format::R32G32B32Sfloat, "normal"),
+    (2, Format::R32G32Sfloat, "texcoord")
+];
+
+const VARYINGS: &'static [InterfaceEntry] = &[
+    (0, Format::R32G32B32Sfloat, "v_normal"),
+    (1, Format::R32G32Sfloat, "v_texcoord")
+];
+
+// Albedo, normal, ao, metallic and roughness maps follow the two uniform buffers
+const TEXTURE_BINDINGS: usize = 5;
+
+const FRAGMENT_OUTPUT: &'static [InterfaceEntry] = &[
+    (0, Format::R32G32B32A32Sfloat, "f_color")
+];
+
+#[derive(Debug, Copy, Clone)]
+pub struct Interface(pub &'static [InterfaceEntry]);
+
+pub struct InterfaceIter(&'static [InterfaceEntry]);
+
+impl Iterator for InterfaceIter {
+    type Item = ShaderInterfaceDefEntry;
+
+    fn next(&mut self) -> Option<ShaderInterfaceDefEntry> {
+        self.0.split_first().map(|(&(location, format, name), rest)| {
+            self.0 = rest;
+            ShaderInterfaceDefEntry {
+                location: location..location + 1,
+                format: format,
+                name: Some(Cow::Borrowed(name))
+            }
+        })
+    }
+}
+
+impl ExactSizeIterator for InterfaceIter {}
+
+unsafe impl ShaderInterfaceDef for Interface {
+    type Iter = InterfaceIter;
+
+    fn elements(&self) -> InterfaceIter {
+        InterfaceIter(self.0)
+    }
+}
+
+#[derive(Debug, Copy, Clone)]
+pub struct Layout(ShaderStages);
+
+unsafe impl PipelineLayoutDesc for Layout {
+    fn num_sets(&self) -> usize {
+        1
+    }
+
+    fn num_bindings_in_set(&self, set: usize) -> Option<usize> {
+        match set {
+            0 => Some(2 + TEXTURE_BINDINGS),
+            _ => None
+        }
+    }
+
+    fn descriptor(&self, set: usize, binding: usize) -> Option<DescriptorDesc> {
+        match (set, binding) {
+            (0, 0) | (0, 1) => Some(DescriptorDesc {
+                ty: DescriptorDescTy::Buffer(DescriptorBufferDesc {
+                    dynamic: Some(false),
+                    storage: false
+                }),
+                array_count: 1,
+                stages: self.0,
+                readonly: true
+            }),
+            (0, b) if b < 2 + TEXTURE_BINDINGS => Some(DescriptorDesc {
+                ty: DescriptorDescTy::CombinedImageSampler(DescriptorImageDesc {
+                    sampled: true,
+                    dimensions: DescriptorImageDescDimensions::TwoDimensional,
+                    format: None,
+                    multisampled: false,
+                    array_layers: DescriptorImageDescArray::NonArrayed
+                }),
+                array_count: 1,
+                stages: self.0,
+                readonly: true
+            }),
+            _ => None
+        }
+    }
+
+    fn num_push_constants_ranges(&self) -> usize {
+        0
+    }
+
+    fn push_constants_range(&self, _num: usize) -> Option<PipelineLayoutDescPcRange> {
+        None
+    }
+}
+
+pub fn build(device: Arc<Device>,
+             vs: &Arc<ShaderModule>,
+             fs: &Arc<ShaderModule>,
+             render_pass: Arc<RenderPassAbstract + Send + Sync>) -> Result<Arc<GraphicsPipelineAbstract + Send + Sync>, String> {
+    let main = unsafe { CStr::from_ptr(b"main\0".as_ptr() as *const _) };
+    let stages = ShaderStages { vertex: true, fragment: true, ..ShaderStages::none() };
+
+    let vertex_entry = unsafe {
+        vs.graphics_entry_point(main, Interface(VERTEX_INPUT), Interface(VARYINGS), Layout(stages), GraphicsShaderType::Vertex)
+    };
+    let fragment_entry = unsafe {
+        fs.graphics_entry_point(main, Interface(VARYINGS), Interface(FRAGMENT_OUTPUT), Layout(stages), GraphicsShaderType::Fragment)
+    };
+
+    let pipeline = GraphicsPipeline::start()
+        .vertex_input(SingleBufferDefinition::<ModelVertex>::new())
+        .vertex_shader(vertex_entry, ())
+        .triangle_list()
+        .viewports_dynamic_scissors_irrelevant(1)
+        .fragment_shader(fragment_entry, ())
+        // Reverse-Z, nearer fragments have the greater depth
+        .depth_stencil(DepthStencil { depth_compare: Compare::Greater, ..DepthStencil::simple_depth_test() })
+        .render_pass(Subpass::from(render_pass, 0).unwrap())
+        .build(device)
+        .map_err(|e| format!("failed to create the model pipeline: {:?}", e))?;
+
+    Ok(Arc::new(pipeline))
+}
@@ -1,22 +1,76 @@
+use vulkano::buffer::{BufferUsage, ImmutableBuffer};
+use vulkano::device::Queue;
 use vulkano::image::{AttachmentImage, ImmutableImage};
 use vulkano::format;
+use vulkano::sync::GpuFuture;
 
-use obj_loader::Model;
+use obj_loader::{Model, ModelVertex};
+use material::textures::MaterialTextureData;
+use renderer::texture;
+
+use std::sync::Arc;
+
+pub struct MaterialTextures {
+    pub albedo_map: Arc<ImmutableImage<format::Format>>,
+    pub normal_map: Arc<ImmutableImage<format::Format>>,
+    pub ao_map: Arc<ImmutableImage<format::Format>>,
+    pub metallic_map: Arc<ImmutableImage<format::Format>>,
+    pub roughness_map: Arc<ImmutableImage<format::Format>>
+}
+
+impl MaterialTextures {
+    pub fn upload(queue: &Arc<Queue>, data: &MaterialTextureData) -> (MaterialTextures, Box<GpuFuture>) {
+        let (albedo_map, albedo_future) = texture::upload(queue, &data.albedo);
+        let (normal_map, normal_future) = texture::upload(queue, &data.normal);
+        let (ao_map, ao_future) = texture::upload(queue, &data.ao);
+        let (metallic_map, metallic_future) = texture::upload(queue, &data.metallic);
+        let (roughness_map, roughness_future) = texture::upload(queue, &data.roughness);
+
+        let future = albedo_future.join(normal_future).join(ao_future).join(metallic_future).join(roughness_future);
+
+        (MaterialTextures {
+            albedo_map: albedo_map,
+            normal_map: normal_map,
+            ao_map: ao_map,
+            metallic_map: metallic_map,
+            roughness_map: roughness_map
+        }, Box::new(future))
+    }
+}
+
+pub struct MeshBuffers {
+    pub vertices: Arc<ImmutableBuffer<[ModelVertex]>>,
+    pub indices: Arc<ImmutableBuffer<[u32]>>
+}
+
+impl MeshBuffers {
+    // Device local buffers, filled through staging buffers on the given queue and usable from every queue family
+    pub fn upload(queue: &Arc<Queue>, model: &Model) -> (MeshBuffers, Box<GpuFuture>) {
+        let device = queue.device();
+
+        let (vertices, vertex_future) = ImmutableBuffer::from_iter(model.interleaved().into_iter(), BufferUsage::vertex_buffer(),
+                                                                   device.active_queue_families(), queue.clone())
+            .expect("failed to create vertex buffer");
+        let (indices, index_future) = ImmutableBuffer::from_iter(model.indices.iter().cloned(), BufferUsage::index_buffer(),
+                                                                 device.active_queue_families(), queue.clone())
+            .expect("failed to create index buffer");
+
+        (MeshBuffers {
+            vertices: vertices,
+            indices: indices
+        }, Box::new(vertex_future.join(index_future)))
+    }
+}
 
 struct Textures {
     environment_cube: ImmutableImage<format::R16G16B16A16Sfloat>,
     lut_brdf: AttachmentImage,
     irradience_cube: AttachmentImage,
     prefiltered_cube: AttachmentImage,
-    albedo_map: ImmutableImage<format::R8G8B8A8Unorm>,
-    normal_map: ImmutableImage<format::R8G8B8A8Unorm>,
-    ao_map: ImmutableImage<format::R8Unorm>,
-    metallic_map: ImmutableImage<format::R8Unorm>,
-    roughness_map: ImmutableImage<format::R8Unorm>
+    material: MaterialTextures
 }
 
 struct Mesh {
     object: Model,
     skybox: Model
 }
-
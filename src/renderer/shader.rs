@@ -0,0 +1,143 @@
+use shaderc;
+use shaderc::{Compiler, CompileOptions, IncludeType, ResolvedInclude, ShaderKind};
+
+use vulkano::device::Device;
+use vulkano::pipeline::shader::ShaderModule;
+
+use find_folder::Search;
+
+use file_watch::FileWatcher;
+
+use std::cell::RefCell;
+use std::fs::File;
+use std::io::Read;
+use std::path::{Path, PathBuf};
+use std::sync::Arc;
+
+pub fn shader_folder() -> PathBuf {
+    Search::ParentsThenKids(3, 3).for_folder("shaders").expect("could not find the shaders folder")
+}
+
+fn read_source(path: &Path) -> Result<String, String> {
+    let mut source = String::new();
+    File::open(path)
+        .and_then(|mut f| f.read_to_string(&mut source))
+        .map_err(|e| format!("{}: {}", path.display(), e))?;
+    Ok(source)
+}
+
+// Quoted includes are looked up next to the including file, angled includes in the shader folder
+fn resolve_include(root: &Path, requested: &str, ty: IncludeType, requesting: &str) -> PathBuf {
+    match ty {
+        IncludeType::Relative => Path::new(requesting).parent().unwrap_or(root).join(requested),
+        IncludeType::Standard => root.join(requested)
+    }
+}
+
+pub struct ShaderSource {
+    pub path: PathBuf,
+    pub kind: ShaderKind
+}
+
+impl ShaderSource {
+    pub fn new<P: AsRef<Path>>(path: P, kind: ShaderKind) -> ShaderSource {
+        ShaderSource {
+            path: path.as_ref().to_path_buf(),
+            kind: kind
+        }
+    }
+
+    // Returns the SPIR-V words together with every file that took part in the compilation,
+    // the latter is returned even on failure so a fix in an include triggers a reload
+    pub fn compile(&self, compiler: &mut Compiler) -> (Result<Vec<u32>, String>, Vec<PathBuf>) {
+        let root = shader_folder();
+        let dependencies = RefCell::new(vec![self.path.clone()]);
+
+        let result = {
+            let mut options = CompileOptions::new().expect("failed to create shader compile options");
+            options.set_include_callback(|requested, ty, requesting, _depth| {
+                let path = resolve_include(&root, requested, ty, requesting);
+                dependencies.borrow_mut().push(path.clone());
+                read_source(&path).map(|content| ResolvedInclude {
+                    resolved_name: path.to_string_lossy().into_owned(),
+                    content: content
+                })
+            });
+
+            read_source(&self.path).and_then(|source| {
+                compiler.compile_into_spirv(&source, self.kind, &self.path.to_string_lossy(), "main", Some(&options))
+                    .map(|artifact| artifact.as_binary().to_vec())
+                    .map_err(|e| e.to_string())
+            })
+        };
+
+        (result, dependencies.into_inner())
+    }
+}
+
+fn create_module(device: &Arc<Device>, spirv: &[u32]) -> Result<Arc<ShaderModule>, String> {
+    let mut bytes = Vec::with_capacity(spirv.len() * 4);
+    for word in spirv {
+        bytes.push((word & 0xff) as u8);
+        bytes.push(((word >> 8) & 0xff) as u8);
+        bytes.push(((word >> 16) & 0xff) as u8);
+        bytes.push(((word >> 24) & 0xff) as u8);
+    }
+
+    unsafe {
+        ShaderModule::new(device.clone(), &bytes).map_err(|e| format!("failed to create shader module: {:?}", e))
+    }
+}
+
+pub struct ShaderProgram {
+    vertex: ShaderSource,
+    fragment: ShaderSource,
+    compiler: Compiler,
+    watcher: FileWatcher
+}
+
+impl ShaderProgram {
+    pub fn new(vertex: &str, fragment: &str) -> ShaderProgram {
+        let root = shader_folder();
+        ShaderProgram {
+            vertex: ShaderSource::new(root.join(vertex), ShaderKind::Vertex),
+            fragment: ShaderSource::new(root.join(fragment), ShaderKind::Fragment),
+            compiler: Compiler::new().expect("failed to create shader compiler"),
+            watcher: FileWatcher::new()
+        }
+    }
+
+    pub fn load(&mut self, device: &Arc<Device>) -> Result<(Arc<ShaderModule>, Arc<ShaderModule>), String> {
+        let (vertex, mut dependencies) = self.vertex.compile(&mut self.compiler);
+        let (fragment, fragment_dependencies) = self.fragment.compile(&mut self.compiler);
+        dependencies.extend(fragment_dependencies);
+
+        self.watcher.clear();
+        for path in dependencies {
+            self.watcher.watch(path);
+        }
+
+        let vertex = vertex?;
+        let fragment = fragment?;
+        Ok((create_module(device, &vertex)?, create_module(device, &fragment)?))
+    }
+
+    // Only returns modules if a watched file changed and everything compiled,
+    // otherwise the caller should keep using its last good pipeline
+    pub fn reload(&mut self, device: &Arc<Device>) -> Option<(Arc<ShaderModule>, Arc<ShaderModule>)> {
+        if !self.watcher.changed() {
+            return None;
+        }
+
+        match self.load(device) {
+            Ok(modules) => {
+                println!("reloaded shaders {} and {}", self.vertex.path.display(), self.fragment.path.display());
+                Some(modules)
+            },
+            Err(err) => {
+                println!("shader compilation failed, keeping the previous pipeline:\n{}", err);
+                None
+            }
+        }
+    }
+}
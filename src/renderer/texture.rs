@@ -0,0 +1,101 @@
+use vulkano::buffer::{BufferUsage, CpuAccessibleBuffer};
+use vulkano::command_buffer::{AutoCommandBufferBuilder, CommandBuffer};
+use vulkano::device::{Device, Queue};
+use vulkano::format::Format;
+use vulkano::image::{Dimensions, ImageLayout, ImageUsage, ImmutableImage, MipmapsCount};
+use vulkano::sampler::{Filter, MipmapMode, Sampler, SamplerAddressMode};
+use vulkano::sync::GpuFuture;
+
+use texture::{TextureData, TextureFormat};
+use texture::bc;
+
+use std::sync::Arc;
+
+pub fn vulkan_format(format: TextureFormat) -> Format {
+    match format {
+        TextureFormat::R8Unorm => Format::R8Unorm,
+        TextureFormat::R8G8B8A8Unorm => Format::R8G8B8A8Unorm,
+        TextureFormat::R8G8B8A8Srgb => Format::R8G8B8A8Srgb,
+        TextureFormat::Bc1RgbaUnorm => Format::BC1_RGBAUnormBlock,
+        TextureFormat::Bc1RgbaSrgb => Format::BC1_RGBASrgbBlock,
+        TextureFormat::Bc3Unorm => Format::BC3UnormBlock,
+        TextureFormat::Bc3Srgb => Format::BC3SrgbBlock,
+        TextureFormat::Bc4Unorm => Format::BC4UnormBlock,
+        TextureFormat::Bc5Unorm => Format::BC5UnormBlock,
+        TextureFormat::Bc7Unorm => Format::BC7UnormBlock,
+        TextureFormat::Bc7Srgb => Format::BC7SrgbBlock
+    }
+}
+
+pub fn supports_bc(device: &Arc<Device>) -> bool {
+    device.enabled_features().texture_compression_bc
+}
+
+// Uploads every mip level and face of the texture in a single command buffer. Block compressed
+// textures are decoded on the CPU first if the device cannot sample them. The image is shared
+// by all queue families of the device, so it can be filled on a transfer queue and sampled on
+// the graphics queue.
+pub fn upload(queue: &Arc<Queue>, data: &TextureData) -> (Arc<ImmutableImage<Format>>, Box<GpuFuture>) {
+    let device = queue.device().clone();
+
+    let decoded;
+    let data = if data.format.is_compressed() && !supports_bc(&device) {
+        decoded = bc::decompress(data).expect("failed to decode block compressed texture");
+        &decoded
+    } else {
+        data
+    };
+
+    let dimensions = if data.faces == 6 {
+        Dimensions::Cubemap { size: data.width }
+    } else {
+        Dimensions::Dim2d { width: data.width, height: data.height }
+    };
+
+    let usage = ImageUsage {
+        transfer_destination: true,
+        sampled: true,
+        ..ImageUsage::none()
+    };
+
+    let (image, init) = ImmutableImage::uninitialized(
+        device.clone(),
+        dimensions,
+        vulkan_format(data.format),
+        MipmapsCount::Specific(data.levels.len() as u32),
+        usage,
+        ImageLayout::ShaderReadOnlyOptimal,
+        device.active_queue_families()
+    ).expect("failed to create texture");
+
+    let init = Arc::new(init);
+    let mut command_buffer = AutoCommandBufferBuilder::new(device.clone(), queue.family()).unwrap();
+    for (level, pixels) in data.levels.iter().enumerate() {
+        let (width, height) = data.level_size(level);
+        let source = CpuAccessibleBuffer::from_iter(device.clone(), BufferUsage::transfer_source(),
+                                                    Some(queue.family()), pixels.iter().cloned())
+            .expect("failed to create buffer");
+
+        command_buffer = command_buffer
+            .copy_buffer_to_image_dimensions(source, init.clone(), [0, 0, 0], [width, height, 1], 0, data.faces, level as u32)
+            .unwrap();
+    }
+
+    let future = command_buffer.build().unwrap()
+        .execute(queue.clone()).unwrap();
+
+    (image, Box::new(future))
+}
+
+// Trilinear filtering with as much anisotropy as requested and the device allows
+pub fn material_sampler(device: &Arc<Device>, max_anisotropy: f32) -> Arc<Sampler> {
+    let anisotropy = if device.enabled_features().sampler_anisotropy {
+        max_anisotropy.max(1.0).min(device.physical_device().limits().max_sampler_anisotropy())
+    } else {
+        1.0
+    };
+
+    Sampler::new(device.clone(), Filter::Linear, Filter::Linear, MipmapMode::Linear,
+                 SamplerAddressMode::Repeat, SamplerAddressMode::Repeat, SamplerAddressMode::Repeat,
+                 0.0, anisotropy, 0.0, 1000.0).expect("failed to create sampler")
+}
@@ -16,6 +16,8 @@ pub struct VulkanInit {
     pub dimensions: [u32; 2],
     pub device: Arc<Device>,
     pub queue: Arc<Queue>,
+    // Uploads go through a dedicated transfer queue when the device has one, otherwise this is `queue`
+    pub transfer_queue: Arc<Queue>,
     pub instance: Arc<Instance>,
     pub swapchain: Arc<Swapchain>,
     pub images: Vec<Arc<SwapchainImage>>
@@ -43,17 +45,30 @@ impl VulkanInit {
             window.surface().is_supported(q).unwrap_or(false))
             .expect("couldn't find a graphical queue family");
 
+        // A transfer only family is usually backed by a DMA engine that copies alongside rendering
+        let transfer = physical.queue_families().find(|&q| q.supports_transfers() &&
+            !q.supports_graphics() && !q.supports_compute());
+
         let device_ext = vulkano::device::DeviceExtensions {
             khr_swapchain: true,
             ..vulkano::device::DeviceExtensions::none()
         };
 
+        let families = match transfer {
+            Some(transfer) => vec![(queue, 0.5), (transfer, 0.5)],
+            None => vec![(queue, 0.5)]
+        };
+
         let (device, mut queues) = vulkano::device::Device::new(physical, physical.supported_features(),
-                                                                &device_ext, [(queue, 0.5)].iter().cloned())
+                                                                &device_ext, families.into_iter())
             .expect("failed to create device");
 
         //Setup the queue
         let queue = queues.next().unwrap();
+        let transfer_queue = queues.next().unwrap_or(queue.clone());
+        if transfer.is_some() {
+            println!("Using a dedicated transfer queue for uploads");
+        }
 
         let caps = window.surface().capabilities(physical).expect("failed to get surface capabilities");
 
@@ -74,6 +89,7 @@ impl VulkanInit {
             dimensions: dimensions,
             device: device,
             queue: queue,
+            transfer_queue: transfer_queue,
             instance: instance.clone(),
             swapchain: swapchain,
             images: images
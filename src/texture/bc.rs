@@ -0,0 +1,156 @@
+use bcndecode;
+use bcndecode::{BcnDecoderFormat, BcnEncoding};
+use intel_tex;
+use intel_tex::{RSurface, RgSurface, RgbaSurface};
+
+use texture::{TextureData, TextureFormat};
+
+// CPU decoding of block compressed textures, for devices and software rasterizers without BC support,
+// and encoding for the texture cooker
+
+fn decoded_format(format: TextureFormat) -> TextureFormat {
+    match format {
+        TextureFormat::Bc4Unorm => TextureFormat::R8Unorm,
+        f if f.is_srgb() => TextureFormat::R8G8B8A8Srgb,
+        _ => TextureFormat::R8G8B8A8Unorm
+    }
+}
+
+fn encoding(format: TextureFormat) -> BcnEncoding {
+    match format {
+        TextureFormat::Bc1RgbaUnorm | TextureFormat::Bc1RgbaSrgb => BcnEncoding::Bc1,
+        TextureFormat::Bc3Unorm | TextureFormat::Bc3Srgb => BcnEncoding::Bc3,
+        TextureFormat::Bc4Unorm => BcnEncoding::Bc4,
+        TextureFormat::Bc5Unorm => BcnEncoding::Bc5,
+        TextureFormat::Bc7Unorm | TextureFormat::Bc7Srgb => BcnEncoding::Bc7,
+        f => panic!("{:?} is not block compressed", f)
+    }
+}
+
+pub fn decompress(texture: &TextureData) -> Result<TextureData, String> {
+    if !texture.format.is_compressed() {
+        return Ok(TextureData {
+            format: texture.format,
+            width: texture.width,
+            height: texture.height,
+            faces: texture.faces,
+            levels: texture.levels.clone()
+        });
+    }
+
+    let target = decoded_format(texture.format);
+    let output = if target == TextureFormat::R8Unorm { BcnDecoderFormat::LUM } else { BcnDecoderFormat::RGBA };
+
+    let mut levels = Vec::with_capacity(texture.levels.len());
+    for (level, data) in texture.levels.iter().enumerate() {
+        let (width, height) = texture.level_size(level);
+        let too_large = || format!("{:?} level {} is too large", texture.format, level);
+        let decoded_bytes = target.image_bytes(width, height).and_then(|bytes| bytes.checked_mul(texture.faces as usize));
+        let mut decoded = Vec::with_capacity(decoded_bytes.ok_or_else(&too_large)?);
+
+        for face in data.chunks(texture.face_bytes(level).ok_or_else(&too_large)?) {
+            let pixels = bcndecode::decode(face, width as usize, height as usize, encoding(texture.format), output)
+                .map_err(|e| format!("failed to decode {:?} level {}: {:?}", texture.format, level, e))?;
+            decoded.extend(pixels);
+        }
+        levels.push(decoded);
+    }
+
+    Ok(TextureData {
+        format: target,
+        width: texture.width,
+        height: texture.height,
+        faces: texture.faces,
+        levels: levels
+    })
+}
+
+// Widens single channel data to RGBA, so every decoded texture can go through the image crate
+pub fn expand_to_rgba(pixels: &[u8], format: TextureFormat) -> Vec<u8> {
+    match format {
+        TextureFormat::R8Unorm => pixels.iter().flat_map(|&r| vec![r, r, r, 255]).collect(),
+        _ => pixels.to_vec()
+    }
+}
+
+// The encoders work on whole 4x4 blocks, so small mips and odd sizes repeat their last row and column
+fn pad_to_blocks(pixels: &[u8], width: u32, height: u32, channels: usize) -> (Vec<u8>, u32, u32) {
+    let (padded_width, padded_height) = ((width + 3) / 4 * 4, (height + 3) / 4 * 4);
+    let mut padded = Vec::with_capacity((padded_width * padded_height) as usize * channels);
+
+    for y in 0..padded_height {
+        let row = y.min(height - 1) as usize * width as usize;
+        for x in 0..padded_width {
+            let start = (row + x.min(width - 1) as usize) * channels;
+            padded.extend_from_slice(&pixels[start..start + channels]);
+        }
+    }
+
+    (padded, padded_width, padded_height)
+}
+
+// Picks only the given channels of every RGBA pixel
+fn select_channels(pixels: &[u8], channels: &[usize]) -> Vec<u8> {
+    pixels.chunks(4).flat_map(|p| channels.iter().map(|&c| p[c]).collect::<Vec<u8>>()).collect()
+}
+
+fn compress_image(pixels: &[u8], width: u32, height: u32, source: TextureFormat, target: TextureFormat) -> Vec<u8> {
+    match target {
+        TextureFormat::Bc7Unorm | TextureFormat::Bc7Srgb => {
+            let (data, width, height) = pad_to_blocks(pixels, width, height, 4);
+            let opaque = data.chunks(4).all(|p| p[3] == 255);
+            let settings = if opaque { intel_tex::bc7::opaque_basic_settings() } else { intel_tex::bc7::alpha_basic_settings() };
+            intel_tex::bc7::compress_blocks(&settings, &RgbaSurface { data: &data, width: width, height: height, stride: width * 4 })
+        },
+        TextureFormat::Bc5Unorm => {
+            let (data, width, height) = pad_to_blocks(&select_channels(pixels, &[0, 1]), width, height, 2);
+            intel_tex::bc5::compress_blocks(&RgSurface { data: &data, width: width, height: height, stride: width * 2 })
+        },
+        TextureFormat::Bc4Unorm => {
+            let red = if source == TextureFormat::R8Unorm { pixels.to_vec() } else { select_channels(pixels, &[0]) };
+            let (data, width, height) = pad_to_blocks(&red, width, height, 1);
+            intel_tex::bc4::compress_blocks(&RSurface { data: &data, width: width, height: height, stride: width })
+        },
+        f => panic!("encoding {:?} is not supported", f)
+    }
+}
+
+// Compresses every level and face of an uncompressed RGBA8 or R8 texture
+pub fn compress(texture: &TextureData, target: TextureFormat) -> TextureData {
+    let mut levels = Vec::with_capacity(texture.levels.len());
+    for (level, data) in texture.levels.iter().enumerate() {
+        let (width, height) = texture.level_size(level);
+        // The uncompressed levels are in memory already, their sizes and the smaller compressed ones fit
+        let mut compressed = Vec::with_capacity(target.image_bytes(width, height).unwrap() * texture.faces as usize);
+        for face in data.chunks(texture.face_bytes(level).unwrap()) {
+            compressed.extend(compress_image(face, width, height, texture.format, target));
+        }
+        levels.push(compressed);
+    }
+
+    TextureData {
+        format: target,
+        width: texture.width,
+        height: texture.height,
+        faces: texture.faces,
+        levels: levels
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn padding_repeats_the_last_row_and_column() {
+        // 2x1, two channels
+        let (padded, width, height) = pad_to_blocks(&[1, 2, 3, 4], 2, 1, 2);
+        assert_eq!((width, height), (4, 4));
+        for row in padded.chunks(width as usize * 2) {
+            assert_eq!(row, &[1, 2, 3, 4, 3, 4, 3, 4]);
+        }
+
+        assert_eq!(select_channels(&[1, 2, 3, 4, 5, 6, 7, 8], &[0, 1]), vec![1, 2, 5, 6]);
+        assert_eq!(expand_to_rgba(&[7], TextureFormat::R8Unorm), vec![7, 7, 7, 255]);
+    }
+}
@@ -0,0 +1,59 @@
+use texture::{ktx2, TextureData};
+use texture::hash::Fnv64;
+
+use std::fs;
+use std::fs::File;
+use std::io::{Read, Write};
+use std::path::{Path, PathBuf};
+
+// Cooked textures live in a hidden folder next to their source. Each entry records the key it was
+// cooked from, a hash over the source files and how they were interpreted, so edits to a source
+// simply make the entry stale instead of needing an explicit invalidation.
+
+pub const CACHE_FOLDER: &'static str = ".texture_cache";
+const KEY_NAME: &'static str = "vulkan-test.cache-key";
+// Bump when the cooking output changes, so old entries are not picked up anymore
+const COOK_VERSION: &'static str = "1";
+
+pub fn key(sources: &[&Path], descriptor: &str) -> Result<String, String> {
+    let mut hash = Fnv64::new();
+    hash.write(COOK_VERSION.as_bytes());
+    hash.write(descriptor.as_bytes());
+    for source in sources {
+        hash.write_file(source).map_err(|e| format!("{}: {}", source.display(), e))?;
+    }
+    Ok(format!("{:016x}", hash.finish()))
+}
+
+pub fn entry_path(source: &Path, slot: &str) -> PathBuf {
+    let folder = source.parent().unwrap_or(Path::new(".")).join(CACHE_FOLDER);
+    let stem = source.file_stem().map_or("texture".into(), |s| s.to_string_lossy());
+    folder.join(format!("{}.{}.ktx2", stem, slot))
+}
+
+// Returns the cooked texture if there is one and it was cooked from the same key
+pub fn lookup(source: &Path, slot: &str, key: &str) -> Option<TextureData> {
+    let mut bytes = Vec::new();
+    File::open(entry_path(source, slot)).and_then(|mut f| f.read_to_end(&mut bytes)).ok()?;
+
+    let stored = ktx2::read_key_values(&bytes).ok()?.into_iter()
+        .find(|&(ref k, _)| k == KEY_NAME)
+        .map(|(_, v)| v)?;
+    if stored != key.as_bytes() {
+        return None;
+    }
+
+    ktx2::read(&bytes).ok().and_then(|t| t.validate().ok().map(|_| t))
+}
+
+pub fn store(source: &Path, slot: &str, key: &str, texture: &TextureData) -> Result<PathBuf, String> {
+    let path = entry_path(source, slot);
+    let bytes = ktx2::write(texture, &[(KEY_NAME, key.as_bytes())]);
+
+    path.parent().map_or(Ok(()), |p| fs::create_dir_all(p))
+        .and_then(|_| File::create(&path))
+        .and_then(|mut f| f.write_all(&bytes))
+        .map_err(|e| format!("{}: {}", path.display(), e))?;
+
+    Ok(path)
+}
@@ -0,0 +1,209 @@
+use texture::{TextureData, TextureFormat};
+use texture::ktx2::read_u32;
+
+// DirectDraw Surface container, both the legacy header and the DX10 extension
+
+const MAGIC: &'static [u8] = b"DDS ";
+const HEADER_SIZE: usize = 4 + 124;
+const DX10_HEADER_SIZE: usize = 20;
+
+const DDSCAPS2_CUBEMAP: u32 = 0x200;
+const DDPF_FOURCC: u32 = 0x4;
+const DDPF_RGB: u32 = 0x40;
+const DDPF_LUMINANCE: u32 = 0x20000;
+const DX10_MISC_TEXTURECUBE: u32 = 0x4;
+// Enough for a 2^31 texel wide texture, more levels mean a broken header
+const MAX_LEVELS: usize = 32;
+
+fn four_cc(code: &[u8; 4]) -> u32 {
+    read_u32(code, 0)
+}
+
+fn from_dxgi_format(format: u32) -> Option<TextureFormat> {
+    match format {
+        28 => Some(TextureFormat::R8G8B8A8Unorm),
+        29 => Some(TextureFormat::R8G8B8A8Srgb),
+        61 => Some(TextureFormat::R8Unorm),
+        71 => Some(TextureFormat::Bc1RgbaUnorm),
+        72 => Some(TextureFormat::Bc1RgbaSrgb),
+        77 => Some(TextureFormat::Bc3Unorm),
+        78 => Some(TextureFormat::Bc3Srgb),
+        80 => Some(TextureFormat::Bc4Unorm),
+        83 => Some(TextureFormat::Bc5Unorm),
+        98 => Some(TextureFormat::Bc7Unorm),
+        99 => Some(TextureFormat::Bc7Srgb),
+        _ => None
+    }
+}
+
+// The legacy header cannot tell sRGB from linear data, `srgb` picks the variant for color formats
+fn from_pixel_format(bytes: &[u8], srgb: bool) -> Result<TextureFormat, String> {
+    // The pixel format struct starts at offset 76 of the header, counting the magic
+    let flags = read_u32(bytes, 80);
+    let code = read_u32(bytes, 84);
+    let bit_count = read_u32(bytes, 88);
+    let masks = (read_u32(bytes, 92), read_u32(bytes, 96), read_u32(bytes, 100), read_u32(bytes, 104));
+
+    if flags & DDPF_FOURCC != 0 {
+        return match code {
+            c if c == four_cc(b"DXT1") => Ok(if srgb { TextureFormat::Bc1RgbaSrgb } else { TextureFormat::Bc1RgbaUnorm }),
+            c if c == four_cc(b"DXT5") => Ok(if srgb { TextureFormat::Bc3Srgb } else { TextureFormat::Bc3Unorm }),
+            c if c == four_cc(b"ATI1") || c == four_cc(b"BC4U") => Ok(TextureFormat::Bc4Unorm),
+            c if c == four_cc(b"ATI2") || c == four_cc(b"BC5U") => Ok(TextureFormat::Bc5Unorm),
+            c => Err(format!("unsupported FourCC {:?}", String::from_utf8_lossy(&[c as u8, (c >> 8) as u8, (c >> 16) as u8, (c >> 24) as u8])))
+        };
+    }
+
+    match (flags & (DDPF_RGB | DDPF_LUMINANCE) != 0, bit_count, masks) {
+        (true, 32, (0xff, 0xff00, 0xff0000, _)) => Ok(if srgb { TextureFormat::R8G8B8A8Srgb } else { TextureFormat::R8G8B8A8Unorm }),
+        (true, 8, (0xff, _, _, _)) => Ok(TextureFormat::R8Unorm),
+        _ => Err("unsupported uncompressed pixel layout, only RGBA8 and R8 are supported".to_string())
+    }
+}
+
+// DX10 headers name the exact format, `srgb` only decides for legacy headers
+pub fn read(bytes: &[u8], srgb: bool) -> Result<TextureData, String> {
+    if bytes.len() < HEADER_SIZE || &bytes[..4] != MAGIC {
+        return Err("not a DDS file".to_string());
+    }
+
+    let height = read_u32(bytes, 12);
+    let width = read_u32(bytes, 16);
+    let level_count = read_u32(bytes, 28).max(1) as usize;
+    let caps2 = read_u32(bytes, 112);
+    if level_count > MAX_LEVELS {
+        return Err(format!("unexpected mip level count {}", level_count));
+    }
+
+    let is_dx10 = read_u32(bytes, 84) == four_cc(b"DX10");
+    let (format, mut faces, data_offset) = if is_dx10 {
+        if bytes.len() < HEADER_SIZE + DX10_HEADER_SIZE {
+            return Err("truncated DX10 header".to_string());
+        }
+        let dxgi_format = read_u32(bytes, HEADER_SIZE);
+        let misc = read_u32(bytes, HEADER_SIZE + 8);
+        let array_size = read_u32(bytes, HEADER_SIZE + 12).max(1);
+        if array_size > 1 {
+            return Err("texture arrays are not supported".to_string());
+        }
+
+        let format = from_dxgi_format(dxgi_format).ok_or_else(|| format!("unsupported DXGI format {}", dxgi_format))?;
+        (format, if misc & DX10_MISC_TEXTURECUBE != 0 { 6 } else { 1 }, HEADER_SIZE + DX10_HEADER_SIZE)
+    } else {
+        (from_pixel_format(bytes, srgb)?, 1, HEADER_SIZE)
+    };
+
+    if caps2 & DDSCAPS2_CUBEMAP != 0 {
+        faces = 6;
+    }
+
+    let mut texture = TextureData {
+        format: format,
+        width: width,
+        height: height,
+        faces: faces,
+        levels: vec![Vec::new(); level_count]
+    };
+
+    // DDS stores every face with its whole mip chain, TextureData wants the faces per level
+    let mut offset = data_offset;
+    for _ in 0..faces {
+        for level in 0..level_count {
+            let end = texture.face_bytes(level)
+                .and_then(|size| offset.checked_add(size))
+                .filter(|&end| end <= bytes.len())
+                .ok_or_else(|| format!("mip level {} lies outside of the file", level))?;
+            texture.levels[level].extend_from_slice(&bytes[offset..end]);
+            offset = end;
+        }
+    }
+
+    Ok(texture)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn put_u32(bytes: &mut [u8], offset: usize, value: u32) {
+        bytes[offset..offset + 4].copy_from_slice(&[value as u8, (value >> 8) as u8, (value >> 16) as u8, (value >> 24) as u8]);
+    }
+
+    fn header(code: &[u8; 4], width: u32, height: u32, levels: u32) -> Vec<u8> {
+        let mut bytes = vec![0; HEADER_SIZE];
+        bytes[..4].copy_from_slice(MAGIC);
+        put_u32(&mut bytes, 4, 124);
+        put_u32(&mut bytes, 12, height);
+        put_u32(&mut bytes, 16, width);
+        put_u32(&mut bytes, 28, levels);
+        put_u32(&mut bytes, 76, 32);
+        put_u32(&mut bytes, 80, DDPF_FOURCC);
+        bytes[84..88].copy_from_slice(code);
+        bytes
+    }
+
+    #[test]
+    fn legacy_header_takes_srgb_from_the_caller() {
+        // 8x8 DXT1 with a 4x4 mip, four blocks and one
+        let mut bytes = header(b"DXT1", 8, 8, 2);
+        bytes.extend((0..40).map(|i| i as u8));
+
+        let linear = read(&bytes, false).unwrap();
+        assert_eq!(linear.format, TextureFormat::Bc1RgbaUnorm);
+        assert_eq!((linear.width, linear.height, linear.faces), (8, 8, 1));
+        assert_eq!(linear.levels, vec![(0..32).collect::<Vec<u8>>(), (32..40).collect()]);
+
+        assert_eq!(read(&bytes, true).unwrap().format, TextureFormat::Bc1RgbaSrgb);
+        assert_eq!(read(&header(b"ATI2", 4, 4, 1).into_iter().chain(vec![0; 16]).collect::<Vec<u8>>(), true).unwrap().format,
+                   TextureFormat::Bc5Unorm);
+
+        bytes.pop();
+        assert!(read(&bytes, false).is_err());
+        assert!(read(&header(b"DXT3", 4, 4, 1), false).is_err());
+    }
+
+    #[test]
+    fn oversized_levels_are_errors() {
+        let dx10 = |width: u32, height: u32| {
+            let mut bytes = header(b"DX10", width, height, 1);
+            let mut dx10 = vec![0; DX10_HEADER_SIZE];
+            put_u32(&mut dx10, 0, 28);
+            bytes.extend(dx10);
+            bytes.extend(vec![0; 64]);
+            bytes
+        };
+
+        // Width times height times four bytes does not fit in a usize
+        assert_eq!(TextureFormat::R8G8B8A8Unorm.image_bytes(u32::max_value(), u32::max_value()), None);
+        assert!(read(&dx10(u32::max_value(), u32::max_value()), false).is_err());
+        // 4 * (2^62 - 1) bytes fit on 64 bit, but not after the header
+        assert!(read(&dx10(2147483647, 2147483649), false).is_err());
+
+        assert_eq!(TextureFormat::Bc1RgbaUnorm.image_bytes(5, 3), Some(16));
+        assert!(read(&dx10(4, 4), false).is_ok());
+    }
+
+    #[test]
+    fn dx10_cubemap_faces_are_regrouped_by_level() {
+        let mut bytes = header(b"DX10", 2, 2, 2);
+        let mut dx10 = vec![0; DX10_HEADER_SIZE];
+        put_u32(&mut dx10, 0, 29);
+        put_u32(&mut dx10, 8, DX10_MISC_TEXTURECUBE);
+        bytes.extend(dx10);
+        // Each face with its whole mip chain, filled with face * 10 + level
+        for face in 0..6u8 {
+            bytes.extend(vec![face * 10; 2 * 2 * 4]);
+            bytes.extend(vec![face * 10 + 1; 4]);
+        }
+
+        // The DX10 format is explicit, the hint does not change it
+        let texture = read(&bytes, false).unwrap();
+        assert_eq!(texture.format, TextureFormat::R8G8B8A8Srgb);
+        assert_eq!(texture.faces, 6);
+        assert!(texture.validate().is_ok());
+        for face in 0..6 {
+            assert!(texture.levels[0][face * 16..(face + 1) * 16].iter().all(|&b| b == face as u8 * 10));
+            assert!(texture.levels[1][face * 4..(face + 1) * 4].iter().all(|&b| b == face as u8 * 10 + 1));
+        }
+    }
+}
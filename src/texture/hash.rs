@@ -0,0 +1,36 @@
+use std::fs::File;
+use std::io;
+use std::io::Read;
+use std::path::Path;
+
+// 64 bit FNV-1a, stable across runs and platforms unlike the std hashers
+pub struct Fnv64(u64);
+
+impl Fnv64 {
+    pub fn new() -> Fnv64 {
+        Fnv64(0xcbf29ce484222325)
+    }
+
+    pub fn write(&mut self, bytes: &[u8]) {
+        for &b in bytes {
+            self.0 ^= b as u64;
+            self.0 = self.0.wrapping_mul(0x100000001b3);
+        }
+    }
+
+    pub fn write_file(&mut self, path: &Path) -> io::Result<()> {
+        let mut file = File::open(path)?;
+        let mut buffer = vec![0u8; 1 << 16];
+        loop {
+            let read = file.read(&mut buffer)?;
+            if read == 0 {
+                return Ok(());
+            }
+            self.write(&buffer[..read]);
+        }
+    }
+
+    pub fn finish(&self) -> u64 {
+        self.0
+    }
+}
@@ -0,0 +1,355 @@
+use texture::{TextureData, TextureFormat};
+
+// KTX 2.0 container, see https://registry.khronos.org/KTX/specs/2.0/ktxspec.v2.html
+// Only uncompressed and BC payloads without supercompression are supported.
+
+pub const IDENTIFIER: [u8; 12] = [0xAB, 0x4B, 0x54, 0x58, 0x20, 0x32, 0x30, 0xBB, 0x0D, 0x0A, 0x1A, 0x0A];
+
+const HEADER_SIZE: usize = 80;
+const LEVEL_INDEX_ENTRY_SIZE: usize = 24;
+// Enough for a 2^31 texel wide texture, more levels mean a broken header
+const MAX_LEVELS: usize = 32;
+
+pub fn vk_format(format: TextureFormat) -> u32 {
+    match format {
+        TextureFormat::R8Unorm => 9,
+        TextureFormat::R8G8B8A8Unorm => 37,
+        TextureFormat::R8G8B8A8Srgb => 43,
+        TextureFormat::Bc1RgbaUnorm => 133,
+        TextureFormat::Bc1RgbaSrgb => 134,
+        TextureFormat::Bc3Unorm => 137,
+        TextureFormat::Bc3Srgb => 138,
+        TextureFormat::Bc4Unorm => 139,
+        TextureFormat::Bc5Unorm => 141,
+        TextureFormat::Bc7Unorm => 145,
+        TextureFormat::Bc7Srgb => 146
+    }
+}
+
+pub fn from_vk_format(format: u32) -> Option<TextureFormat> {
+    match format {
+        9 => Some(TextureFormat::R8Unorm),
+        37 => Some(TextureFormat::R8G8B8A8Unorm),
+        43 => Some(TextureFormat::R8G8B8A8Srgb),
+        // BC1 without alpha decodes the same, the alpha bit is simply never set
+        131 | 133 => Some(TextureFormat::Bc1RgbaUnorm),
+        132 | 134 => Some(TextureFormat::Bc1RgbaSrgb),
+        137 => Some(TextureFormat::Bc3Unorm),
+        138 => Some(TextureFormat::Bc3Srgb),
+        139 => Some(TextureFormat::Bc4Unorm),
+        141 => Some(TextureFormat::Bc5Unorm),
+        145 => Some(TextureFormat::Bc7Unorm),
+        146 => Some(TextureFormat::Bc7Srgb),
+        _ => None
+    }
+}
+
+pub fn read_u32(bytes: &[u8], offset: usize) -> u32 {
+    (bytes[offset] as u32) | (bytes[offset + 1] as u32) << 8 |
+        (bytes[offset + 2] as u32) << 16 | (bytes[offset + 3] as u32) << 24
+}
+
+fn read_u64(bytes: &[u8], offset: usize) -> u64 {
+    read_u32(bytes, offset) as u64 | (read_u32(bytes, offset + 4) as u64) << 32
+}
+
+pub fn read(bytes: &[u8]) -> Result<TextureData, String> {
+    if bytes.len() < HEADER_SIZE || bytes[..12] != IDENTIFIER {
+        return Err("not a KTX2 file".to_string());
+    }
+
+    let vk_format = read_u32(bytes, 12);
+    let width = read_u32(bytes, 20);
+    let height = read_u32(bytes, 24).max(1);
+    let depth = read_u32(bytes, 28);
+    let layers = read_u32(bytes, 32);
+    let faces = read_u32(bytes, 36);
+    let level_count = read_u32(bytes, 40).max(1) as usize;
+    let supercompression = read_u32(bytes, 44);
+
+    let format = from_vk_format(vk_format).ok_or_else(|| format!("unsupported VkFormat {}", vk_format))?;
+    if level_count > MAX_LEVELS {
+        return Err(format!("unexpected mip level count {}", level_count));
+    }
+    if supercompression != 0 {
+        return Err(format!("unsupported supercompression scheme {}", supercompression));
+    }
+    if depth > 1 || layers > 1 {
+        return Err("3D textures and texture arrays are not supported".to_string());
+    }
+    if faces != 1 && faces != 6 {
+        return Err(format!("unexpected face count {}", faces));
+    }
+
+    let mut texture = TextureData {
+        format: format,
+        width: width,
+        height: height,
+        faces: faces,
+        levels: Vec::with_capacity(level_count)
+    };
+
+    for level in 0..level_count {
+        let entry = HEADER_SIZE + level * LEVEL_INDEX_ENTRY_SIZE;
+        if entry + LEVEL_INDEX_ENTRY_SIZE > bytes.len() {
+            return Err("truncated level index".to_string());
+        }
+
+        // Both come straight from the file, a broken header must not overflow
+        let offset = read_u64(bytes, entry);
+        let end = offset.checked_add(read_u64(bytes, entry + 8))
+            .filter(|&end| end <= bytes.len() as u64)
+            .ok_or_else(|| format!("mip level {} lies outside of the file", level))?;
+
+        // Faces of a level are stored one after another, just like TextureData keeps them
+        texture.levels.push(bytes[offset as usize..end as usize].to_vec());
+    }
+
+    Ok(texture)
+}
+
+pub fn read_key_values(bytes: &[u8]) -> Result<Vec<(String, Vec<u8>)>, String> {
+    if bytes.len() < HEADER_SIZE || bytes[..12] != IDENTIFIER {
+        return Err("not a KTX2 file".to_string());
+    }
+
+    let offset = read_u32(bytes, 56) as usize;
+    let end = offset.checked_add(read_u32(bytes, 60) as usize)
+        .filter(|&end| end <= bytes.len())
+        .ok_or_else(|| "key/value data lies outside of the file".to_string())?;
+
+    let mut key_values = Vec::new();
+    let mut cursor = offset;
+    while cursor + 4 <= end {
+        let size = read_u32(bytes, cursor) as usize;
+        let entry_end = (cursor + 4).checked_add(size)
+            .filter(|&entry_end| entry_end <= end)
+            .ok_or_else(|| "key/value entry lies outside of the key/value data".to_string())?;
+        let entry = &bytes[cursor + 4..entry_end];
+        if let Some(split) = entry.iter().position(|&b| b == 0) {
+            // Values are usually NUL terminated strings, the terminator is not part of the value
+            let value = &entry[split + 1..];
+            let value = if value.last() == Some(&0) { &value[..value.len() - 1] } else { value };
+            key_values.push((String::from_utf8_lossy(&entry[..split]).into_owned(), value.to_vec()));
+        }
+        cursor = align(entry_end, 4);
+    }
+
+    Ok(key_values)
+}
+
+fn align(offset: usize, alignment: usize) -> usize {
+    (offset + alignment - 1) / alignment * alignment
+}
+
+fn push_u32(out: &mut Vec<u8>, value: u32) {
+    out.extend_from_slice(&[value as u8, (value >> 8) as u8, (value >> 16) as u8, (value >> 24) as u8]);
+}
+
+fn push_u64(out: &mut Vec<u8>, value: u64) {
+    push_u32(out, value as u32);
+    push_u32(out, (value >> 32) as u32);
+}
+
+// Data format descriptor values from the Khronos Data Format Specification
+const MODEL_RGBSDA: u32 = 1;
+const MODEL_BC1A: u32 = 128;
+const MODEL_BC3: u32 = 130;
+const MODEL_BC4: u32 = 131;
+const MODEL_BC5: u32 = 132;
+const MODEL_BC7: u32 = 134;
+const TRANSFER_LINEAR: u32 = 1;
+const TRANSFER_SRGB: u32 = 2;
+const CHANNEL_ALPHA: u8 = 15;
+const SAMPLE_LINEAR: u8 = 0x10;
+
+// A basic descriptor block, samples are (bit offset, bit length, channel) triples
+fn data_format_descriptor(format: TextureFormat) -> Vec<u8> {
+    let (model, samples): (u32, Vec<(u32, u32, u8)>) = match format {
+        TextureFormat::R8Unorm => (MODEL_RGBSDA, vec![(0, 8, 0)]),
+        TextureFormat::R8G8B8A8Unorm | TextureFormat::R8G8B8A8Srgb =>
+            (MODEL_RGBSDA, vec![(0, 8, 0), (8, 8, 1), (16, 8, 2), (24, 8, CHANNEL_ALPHA)]),
+        TextureFormat::Bc1RgbaUnorm | TextureFormat::Bc1RgbaSrgb => (MODEL_BC1A, vec![(0, 64, 1)]),
+        TextureFormat::Bc3Unorm | TextureFormat::Bc3Srgb => (MODEL_BC3, vec![(0, 64, CHANNEL_ALPHA), (64, 64, 0)]),
+        TextureFormat::Bc4Unorm => (MODEL_BC4, vec![(0, 64, 0)]),
+        TextureFormat::Bc5Unorm => (MODEL_BC5, vec![(0, 64, 0), (64, 64, 1)]),
+        TextureFormat::Bc7Unorm | TextureFormat::Bc7Srgb => (MODEL_BC7, vec![(0, 128, 0)])
+    };
+
+    let transfer = if format.is_srgb() { TRANSFER_SRGB } else { TRANSFER_LINEAR };
+    let (block_dimensions, bytes_plane) = match format.block_bytes() {
+        Some(bytes) => (3 | 3 << 8, bytes as u32),
+        None => (0, format.bytes_per_pixel() as u32)
+    };
+
+    let block_size = 24 + 16 * samples.len() as u32;
+    let mut dfd = Vec::new();
+    push_u32(&mut dfd, 4 + block_size);
+    push_u32(&mut dfd, 0);
+    push_u32(&mut dfd, 2 | block_size << 16);
+    push_u32(&mut dfd, model | 1 << 8 | transfer << 16);
+    push_u32(&mut dfd, block_dimensions);
+    push_u32(&mut dfd, bytes_plane);
+    push_u32(&mut dfd, 0);
+
+    for (offset, length, channel) in samples {
+        // Alpha is never sRGB encoded
+        let qualifiers = if channel == CHANNEL_ALPHA && format.is_srgb() { SAMPLE_LINEAR } else { 0 };
+        push_u32(&mut dfd, offset | (length - 1) << 16 | ((channel | qualifiers) as u32) << 24);
+        push_u32(&mut dfd, 0);
+        push_u32(&mut dfd, 0);
+        push_u32(&mut dfd, if length >= 32 { 0xffffffff } else { (1 << length) - 1 });
+    }
+
+    dfd
+}
+
+fn key_value_data(key_values: &[(&str, &[u8])]) -> Vec<u8> {
+    let mut kvd = Vec::new();
+    for &(key, value) in key_values {
+        let mut entry = Vec::with_capacity(key.len() + value.len() + 2);
+        entry.extend_from_slice(key.as_bytes());
+        entry.push(0);
+        entry.extend_from_slice(value);
+        entry.push(0);
+
+        push_u32(&mut kvd, entry.len() as u32);
+        kvd.extend(entry);
+        let padded = align(kvd.len(), 4);
+        kvd.resize(padded, 0);
+    }
+    kvd
+}
+
+pub fn write(texture: &TextureData, key_values: &[(&str, &[u8])]) -> Vec<u8> {
+    let level_count = texture.levels.len();
+    let dfd = data_format_descriptor(texture.format);
+    let kvd = key_value_data(key_values);
+
+    let dfd_offset = HEADER_SIZE + level_count * LEVEL_INDEX_ENTRY_SIZE;
+    let kvd_offset = dfd_offset + dfd.len();
+
+    // Mip data has to be aligned to the least common multiple of the texel block size and 4,
+    // and is stored from the smallest level to the largest
+    let alignment = texture.format.block_bytes().unwrap_or(4).max(4);
+    let mut offsets = vec![0; level_count];
+    let mut end = kvd_offset + kvd.len();
+    for level in (0..level_count).rev() {
+        offsets[level] = align(end, alignment);
+        end = offsets[level] + texture.levels[level].len();
+    }
+
+    let mut out = Vec::with_capacity(end);
+    out.extend_from_slice(&IDENTIFIER);
+    push_u32(&mut out, vk_format(texture.format));
+    // typeSize, 1 for block compressed and 8 bit formats alike
+    push_u32(&mut out, 1);
+    push_u32(&mut out, texture.width);
+    push_u32(&mut out, texture.height);
+    push_u32(&mut out, 0);
+    push_u32(&mut out, 0);
+    push_u32(&mut out, texture.faces);
+    push_u32(&mut out, level_count as u32);
+    push_u32(&mut out, 0);
+
+    push_u32(&mut out, dfd_offset as u32);
+    push_u32(&mut out, dfd.len() as u32);
+    push_u32(&mut out, if kvd.is_empty() { 0 } else { kvd_offset as u32 });
+    push_u32(&mut out, kvd.len() as u32);
+    push_u64(&mut out, 0);
+    push_u64(&mut out, 0);
+
+    for (level, data) in texture.levels.iter().enumerate() {
+        push_u64(&mut out, offsets[level] as u64);
+        push_u64(&mut out, data.len() as u64);
+        push_u64(&mut out, data.len() as u64);
+    }
+
+    out.extend(dfd);
+    out.extend(kvd);
+    for level in (0..level_count).rev() {
+        out.resize(offsets[level], 0);
+        out.extend_from_slice(&texture.levels[level]);
+    }
+
+    out
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn put_u64(bytes: &mut [u8], offset: usize, value: u64) {
+        let mut encoded = Vec::new();
+        push_u64(&mut encoded, value);
+        bytes[offset..offset + 8].copy_from_slice(&encoded);
+    }
+
+    // 8x4 BC7 with its three mips, every byte numbered so misplaced data shows
+    fn bc7_texture() -> TextureData {
+        TextureData {
+            format: TextureFormat::Bc7Srgb,
+            width: 8,
+            height: 4,
+            faces: 1,
+            levels: vec![(0..32).collect(), (32..48).collect(), (48..64).collect()]
+        }
+    }
+
+    #[test]
+    fn written_textures_read_back() {
+        let texture = bc7_texture();
+        let bytes = write(&texture, &[("KTXwriter", b"vulkan-test"), ("source", b"stump.png")]);
+
+        let read_back = read(&bytes).unwrap();
+        assert_eq!(read_back.format, texture.format);
+        assert_eq!((read_back.width, read_back.height, read_back.faces), (8, 4, 1));
+        assert_eq!(read_back.levels, texture.levels);
+        assert!(read_back.validate().is_ok());
+
+        assert_eq!(read_key_values(&bytes).unwrap(), vec![
+            ("KTXwriter".to_string(), b"vulkan-test".to_vec()),
+            ("source".to_string(), b"stump.png".to_vec())
+        ]);
+
+        let cubemap = TextureData {
+            format: TextureFormat::R8G8B8A8Unorm,
+            width: 2,
+            height: 2,
+            faces: 6,
+            levels: vec![vec![7; 2 * 2 * 4 * 6], vec![9; 4 * 6]]
+        };
+        let read_back = read(&write(&cubemap, &[])).unwrap();
+        assert_eq!(read_back.faces, 6);
+        assert_eq!(read_back.levels, cubemap.levels);
+    }
+
+    #[test]
+    fn broken_offsets_are_errors() {
+        let bytes = write(&bc7_texture(), &[("KTXwriter", b"vulkan-test")]);
+
+        // Offset plus length overflows
+        let mut broken = bytes.clone();
+        put_u64(&mut broken, HEADER_SIZE, u64::max_value() - 8);
+        assert!(read(&broken).is_err());
+
+        let mut broken = bytes.clone();
+        put_u64(&mut broken, HEADER_SIZE + 8, bytes.len() as u64);
+        assert!(read(&broken).is_err());
+
+        let mut broken = bytes.clone();
+        broken[40..44].copy_from_slice(&[0xff, 0xff, 0xff, 0xff]);
+        assert!(read(&broken).is_err());
+
+        let mut broken = bytes.clone();
+        broken[60..64].copy_from_slice(&[0xff, 0xff, 0xff, 0xff]);
+        assert!(read_key_values(&broken).is_err());
+
+        // A size whose level bytes do not fit in memory fails validation instead of overflowing
+        let mut broken = bytes.clone();
+        broken[20..28].copy_from_slice(&[0xff; 8]);
+        assert!(read(&broken).and_then(|t| t.validate()).is_err());
+
+        assert!(read(&bytes[..HEADER_SIZE + 10]).is_err());
+    }
+}
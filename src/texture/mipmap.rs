@@ -0,0 +1,310 @@
+use image::{GrayImage, RgbaImage};
+
+use texture::{mip_count, TextureData, TextureFormat};
+
+// A mip level as floats, so filtering happens without quantizing in between levels
+pub struct Level {
+    pub width: u32,
+    pub height: u32,
+    pub channels: usize,
+    pub data: Vec<f32>
+}
+
+impl Level {
+    fn from_bytes(width: u32, height: u32, channels: usize, bytes: &[u8], decode: &Fn(usize, u8) -> f32) -> Level {
+        Level {
+            width: width,
+            height: height,
+            channels: channels,
+            data: bytes.iter().enumerate().map(|(i, &b)| decode(i % channels, b)).collect()
+        }
+    }
+
+    fn to_bytes(&self, encode: &Fn(usize, f32) -> f32) -> Vec<u8> {
+        self.data.iter().enumerate()
+            .map(|(i, &v)| (encode(i % self.channels, v).max(0.0).min(1.0) * 255.0).round() as u8)
+            .collect()
+    }
+
+    pub fn get(&self, x: u32, y: u32, channel: usize) -> f32 {
+        let x = x.min(self.width - 1);
+        let y = y.min(self.height - 1);
+        self.data[(y * self.width + x) as usize * self.channels + channel]
+    }
+
+    // 2x2 box filter, the last row or column of odd sized levels is clamped
+    pub fn downsample(&self) -> Level {
+        let (width, height) = ((self.width / 2).max(1), (self.height / 2).max(1));
+        let mut data = Vec::with_capacity((width * height) as usize * self.channels);
+
+        for y in 0..height {
+            for x in 0..width {
+                for c in 0..self.channels {
+                    let sum = self.get(2 * x, 2 * y, c) + self.get(2 * x + 1, 2 * y, c) +
+                        self.get(2 * x, 2 * y + 1, c) + self.get(2 * x + 1, 2 * y + 1, c);
+                    data.push(sum * 0.25);
+                }
+            }
+        }
+
+        Level {
+            width: width,
+            height: height,
+            channels: self.channels,
+            data: data
+        }
+    }
+}
+
+fn chain(first: Level) -> Vec<Level> {
+    let count = mip_count(first.width, first.height);
+    let mut levels = vec![first];
+    for _ in 1..count {
+        let next = levels.last().unwrap().downsample();
+        levels.push(next);
+    }
+    levels
+}
+
+pub fn srgb_to_linear(v: f32) -> f32 {
+    if v <= 0.04045 { v / 12.92 } else { ((v + 0.055) / 1.055).powf(2.4) }
+}
+
+pub fn linear_to_srgb(v: f32) -> f32 {
+    if v <= 0.0031308 { v * 12.92 } else { 1.055 * v.powf(1.0 / 2.4) - 0.055 }
+}
+
+// Color maps are filtered in linear space, otherwise the mips of sRGB textures darken
+pub fn color_mips(image: &RgbaImage, srgb: bool) -> TextureData {
+    let decode = |c: usize, b: u8| {
+        let v = b as f32 / 255.0;
+        if srgb && c < 3 { srgb_to_linear(v) } else { v }
+    };
+    let encode = |c: usize, v: f32| if srgb && c < 3 { linear_to_srgb(v) } else { v };
+
+    let levels = chain(Level::from_bytes(image.width(), image.height(), 4, image, &decode));
+
+    TextureData {
+        format: if srgb { TextureFormat::R8G8B8A8Srgb } else { TextureFormat::R8G8B8A8Unorm },
+        width: image.width(),
+        height: image.height(),
+        faces: 1,
+        levels: levels.iter().map(|l| l.to_bytes(&encode)).collect()
+    }
+}
+
+pub fn scalar_mips(image: &GrayImage) -> TextureData {
+    let decode = |_: usize, b: u8| b as f32 / 255.0;
+    let encode = |_: usize, v: f32| v;
+
+    let levels = chain(Level::from_bytes(image.width(), image.height(), 1, image, &decode));
+
+    TextureData {
+        format: TextureFormat::R8Unorm,
+        width: image.width(),
+        height: image.height(),
+        faces: 1,
+        levels: levels.iter().map(|l| l.to_bytes(&encode)).collect()
+    }
+}
+
+pub struct NormalMips {
+    pub data: TextureData,
+    // Length of the averaged, not yet renormalized normal per texel and level,
+    // shorter normals mean more variation was filtered away
+    pub lengths: Vec<Level>
+}
+
+pub fn normal_mips(image: &RgbaImage) -> NormalMips {
+    let decode = |c: usize, b: u8| if c < 3 { b as f32 / 255.0 * 2.0 - 1.0 } else { b as f32 / 255.0 };
+    let encode = |c: usize, v: f32| if c < 3 { v * 0.5 + 0.5 } else { v };
+
+    let mut first = Level::from_bytes(image.width(), image.height(), 4, image, &decode);
+    normalize(&mut first);
+
+    // The chain keeps the averaged vectors, only the stored copy gets renormalized
+    let levels = chain(first);
+
+    let mut stored = Vec::with_capacity(levels.len());
+    let mut lengths = Vec::with_capacity(levels.len());
+    for level in &levels {
+        let mut normalized = Level {
+            width: level.width,
+            height: level.height,
+            channels: 4,
+            data: level.data.clone()
+        };
+        lengths.push(normalize(&mut normalized));
+        stored.push(normalized.to_bytes(&encode));
+    }
+
+    NormalMips {
+        data: TextureData {
+            format: TextureFormat::R8G8B8A8Unorm,
+            width: image.width(),
+            height: image.height(),
+            faces: 1,
+            levels: stored
+        },
+        lengths: lengths
+    }
+}
+
+fn normalize(level: &mut Level) -> Level {
+    let mut lengths = Vec::with_capacity((level.width * level.height) as usize);
+    for texel in level.data.chunks_mut(4) {
+        let length = (texel[0] * texel[0] + texel[1] * texel[1] + texel[2] * texel[2]).sqrt();
+        if length > 0.0 {
+            texel[0] /= length;
+            texel[1] /= length;
+            texel[2] /= length;
+        } else {
+            texel[0] = 0.0;
+            texel[1] = 0.0;
+            texel[2] = 1.0;
+        }
+        lengths.push(length.min(1.0));
+    }
+
+    Level {
+        width: level.width,
+        height: level.height,
+        channels: 1,
+        data: lengths
+    }
+}
+
+// Widens the GGX alpha by the normal variance lost in filtering (Toksvig, in the vMF form from
+// Hill's "Specular Showdown"), so bumpy surfaces do not turn glossy in the distance. The averaged
+// length r estimates the vMF sharpness kappa, whose lobe adds a variance of 1 / (2 kappa) to alpha^2.
+fn adjust_roughness(roughness: f32, normal_length: f32) -> f32 {
+    if normal_length >= 0.9999 {
+        return roughness;
+    }
+
+    let r = normal_length.max(1e-4);
+    let kappa = (3.0 * r - r * r * r) / (1.0 - r * r);
+    let alpha = roughness * roughness;
+    (alpha * alpha + 1.0 / (2.0 * kappa)).sqrt().min(1.0).sqrt()
+}
+
+pub fn roughness_mips(roughness: &GrayImage, normal_lengths: Option<&[Level]>) -> TextureData {
+    let decode = |_: usize, b: u8| b as f32 / 255.0;
+    let encode = |_: usize, v: f32| v;
+
+    let mut levels = chain(Level::from_bytes(roughness.width(), roughness.height(), 1, roughness, &decode));
+
+    if let Some(normal_lengths) = normal_lengths {
+        // The maps do not need to share a resolution, compare levels covering the same footprint
+        let offset = normal_lengths[0].width as f32 / roughness.width() as f32;
+        let offset = offset.log2().round() as i32;
+
+        for (i, level) in levels.iter_mut().enumerate() {
+            let n = (i as i32 + offset).max(0).min(normal_lengths.len() as i32 - 1) as usize;
+            let lengths = &normal_lengths[n];
+
+            for y in 0..level.height {
+                for x in 0..level.width {
+                    let nx = ((x as f32 + 0.5) / level.width as f32 * lengths.width as f32) as u32;
+                    let ny = ((y as f32 + 0.5) / level.height as f32 * lengths.height as f32) as u32;
+                    let index = (y * level.width + x) as usize;
+                    level.data[index] = adjust_roughness(level.data[index], lengths.get(nx, ny, 0));
+                }
+            }
+        }
+    }
+
+    TextureData {
+        format: TextureFormat::R8Unorm,
+        width: roughness.width(),
+        height: roughness.height(),
+        faces: 1,
+        levels: levels.iter().map(|l| l.to_bytes(&encode)).collect()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    use image::{Luma, Rgba};
+
+    // Normals tilted 37 degrees to either side, (+-0.6, 0, 0.8)
+    fn tilted_normals() -> RgbaImage {
+        RgbaImage::from_fn(2, 1, |x, _| Rgba { data: [if x == 0 { 204 } else { 51 }, 128, 230, 255] })
+    }
+
+    fn level_sizes(texture: &TextureData) -> Vec<(u32, u32)> {
+        (0..texture.levels.len()).map(|level| texture.level_size(level)).collect()
+    }
+
+    #[test]
+    fn srgb_colors_are_averaged_in_linear_space() {
+        // Black and white columns, the alpha channel is linear either way
+        let image = RgbaImage::from_fn(2, 2, |x, _| Rgba { data: if x == 0 { [0; 4] } else { [255; 4] } });
+
+        let srgb = color_mips(&image, true);
+        assert_eq!(srgb.format, TextureFormat::R8G8B8A8Srgb);
+        // Half the light is 0.735 in sRGB, not the 0.5 of averaging the encoded values
+        assert_eq!(srgb.levels[1], vec![188, 188, 188, 128]);
+
+        let linear = color_mips(&image, false);
+        assert_eq!(linear.levels[1], vec![128, 128, 128, 128]);
+
+        for &v in &[0.0, 0.002, 0.04, 0.5, 1.0] {
+            assert!((linear_to_srgb(srgb_to_linear(v)) - v).abs() < 1e-5);
+        }
+    }
+
+    #[test]
+    fn averaged_normals_are_renormalized_and_their_length_kept() {
+        let mips = normal_mips(&tilted_normals());
+
+        assert_eq!(level_sizes(&mips.data), vec![(2, 1), (1, 1)]);
+        assert_eq!(mips.data.levels[1], vec![128, 128, 255, 255]);
+
+        assert_eq!(mips.lengths.len(), 2);
+        assert!(mips.lengths[0].data.iter().all(|&l| (l - 1.0).abs() < 1e-3));
+        assert!((mips.lengths[1].data[0] - 0.8).abs() < 0.01);
+    }
+
+    #[test]
+    fn roughness_grows_as_the_averaged_normal_shortens() {
+        assert_eq!(adjust_roughness(0.3, 1.0), 0.3);
+
+        let adjusted: Vec<f32> = [0.99, 0.9, 0.7, 0.4, 0.0].iter().map(|&l| adjust_roughness(0.3, l)).collect();
+        assert!(adjusted[0] > 0.3);
+        assert!(adjusted.windows(2).all(|w| w[1] > w[0] || w[1] == 1.0));
+        assert!(adjusted.iter().all(|&r| r <= 1.0));
+
+        // A lobe of variance 1 / (2 kappa) on top of alpha^2 = 0.0081
+        let kappa = (3.0 * 0.9 - 0.729) / (1.0 - 0.81);
+        assert!((adjusted[1] - (0.0081f32 + 0.5 / kappa).sqrt().sqrt()).abs() < 1e-5);
+
+        // Only the filtered levels get rougher, the full resolution one is left alone
+        let mips = normal_mips(&tilted_normals());
+        let roughness = GrayImage::from_pixel(2, 1, Luma { data: [77] });
+        let texture = roughness_mips(&roughness, Some(&mips.lengths));
+        assert_eq!(texture.levels[0], vec![77, 77]);
+        assert!(texture.levels[1][0] > 77);
+        assert_eq!(roughness_mips(&roughness, None).levels[1], vec![77]);
+    }
+
+    #[test]
+    fn odd_and_non_power_of_two_levels_halve_down_to_one_texel() {
+        let image = GrayImage::from_pixel(5, 3, Luma { data: [100] });
+        let texture = scalar_mips(&image);
+        assert_eq!(level_sizes(&texture), vec![(5, 3), (2, 1), (1, 1)]);
+        assert!(texture.validate().is_ok());
+        assert!(texture.levels.iter().all(|level| level.iter().all(|&b| b == 100)));
+
+        let color = color_mips(&RgbaImage::new(7, 1), true);
+        assert_eq!(level_sizes(&color), vec![(7, 1), (3, 1), (1, 1)]);
+        assert!(color.validate().is_ok());
+
+        // A one texel wide level keeps its width, the missing column is clamped
+        let strip = Level { width: 1, height: 2, channels: 1, data: vec![0.0, 1.0] };
+        let half = strip.downsample();
+        assert_eq!((half.width, half.height, half.data), (1, 1, vec![0.5]));
+    }
+}
@@ -0,0 +1,167 @@
+pub mod mipmap;
+pub mod ktx2;
+pub mod dds;
+pub mod bc;
+pub mod hash;
+pub mod cache;
+
+use image;
+
+use std::fs::File;
+use std::io::Read;
+use std::path::Path;
+
+// Texture data on the CPU side, independent of the graphics API so it can be shared with tools
+
+#[derive(Debug, Copy, Clone, PartialEq)]
+pub enum TextureFormat {
+    R8Unorm,
+    R8G8B8A8Unorm,
+    R8G8B8A8Srgb,
+    Bc1RgbaUnorm,
+    Bc1RgbaSrgb,
+    Bc3Unorm,
+    Bc3Srgb,
+    Bc4Unorm,
+    Bc5Unorm,
+    Bc7Unorm,
+    Bc7Srgb
+}
+
+impl TextureFormat {
+    pub fn is_compressed(&self) -> bool {
+        self.block_bytes().is_some()
+    }
+
+    pub fn is_srgb(&self) -> bool {
+        match *self {
+            TextureFormat::R8G8B8A8Srgb | TextureFormat::Bc1RgbaSrgb |
+            TextureFormat::Bc3Srgb | TextureFormat::Bc7Srgb => true,
+            _ => false
+        }
+    }
+
+    // Bytes per 4x4 block for block compressed formats
+    pub fn block_bytes(&self) -> Option<usize> {
+        match *self {
+            TextureFormat::Bc1RgbaUnorm | TextureFormat::Bc1RgbaSrgb | TextureFormat::Bc4Unorm => Some(8),
+            TextureFormat::Bc3Unorm | TextureFormat::Bc3Srgb | TextureFormat::Bc5Unorm |
+            TextureFormat::Bc7Unorm | TextureFormat::Bc7Srgb => Some(16),
+            _ => None
+        }
+    }
+
+    pub fn bytes_per_pixel(&self) -> usize {
+        match *self {
+            TextureFormat::R8Unorm => 1,
+            TextureFormat::R8G8B8A8Unorm | TextureFormat::R8G8B8A8Srgb => 4,
+            _ => panic!("{:?} is block compressed", self)
+        }
+    }
+
+    // None when the size does not fit in memory, which only a broken header can ask for
+    pub fn image_bytes(&self, width: u32, height: u32) -> Option<usize> {
+        match self.block_bytes() {
+            Some(block) => ((width as usize + 3) / 4).checked_mul((height as usize + 3) / 4)?.checked_mul(block),
+            None => (width as usize).checked_mul(height as usize)?.checked_mul(self.bytes_per_pixel())
+        }
+    }
+}
+
+pub struct TextureData {
+    pub format: TextureFormat,
+    pub width: u32,
+    pub height: u32,
+    // 1 for regular textures, 6 for cubemaps in +X, -X, +Y, -Y, +Z, -Z order
+    pub faces: u32,
+    // Tightly packed pixels of every mip level, the full resolution one first.
+    // Each level holds all faces one after another.
+    pub levels: Vec<Vec<u8>>
+}
+
+impl TextureData {
+    pub fn level_size(&self, level: usize) -> (u32, u32) {
+        ((self.width >> level).max(1), (self.height >> level).max(1))
+    }
+
+    pub fn face_bytes(&self, level: usize) -> Option<usize> {
+        let (width, height) = self.level_size(level);
+        self.format.image_bytes(width, height)
+    }
+
+    // Checks that every level has exactly the size its format and dimensions call for
+    pub fn validate(&self) -> Result<(), String> {
+        if self.levels.is_empty() {
+            return Err("texture has no mip levels".to_string());
+        }
+
+        for (level, data) in self.levels.iter().enumerate() {
+            let expected = self.face_bytes(level).and_then(|bytes| bytes.checked_mul(self.faces as usize))
+                .ok_or_else(|| format!("mip level {} is too large", level))?;
+            if data.len() != expected {
+                return Err(format!("mip level {} has {} bytes, expected {}", level, data.len(), expected));
+            }
+        }
+
+        Ok(())
+    }
+}
+
+impl TextureData {
+    // Drops the levels larger than max_size, the next smaller level becomes the full resolution one
+    pub fn limit_size(&mut self, max_size: u32) {
+        let skip = (0..self.levels.len() - 1)
+            .take_while(|&level| {
+                let (width, height) = self.level_size(level);
+                width.max(height) > max_size
+            })
+            .count();
+
+        if skip > 0 {
+            let (width, height) = self.level_size(skip);
+            self.width = width;
+            self.height = height;
+            self.levels.drain(..skip);
+        }
+    }
+}
+
+pub fn mip_count(width: u32, height: u32) -> u32 {
+    32 - width.max(height).max(1).leading_zeros()
+}
+
+// Containers carry GPU ready data, including compressed formats and pre-baked mips
+pub fn is_container(path: &Path) -> bool {
+    match path.extension().and_then(|e| e.to_str()).map(|e| e.to_lowercase()) {
+        Some(ref e) if e == "ktx2" || e == "dds" => true,
+        _ => false
+    }
+}
+
+// `srgb` marks color data for containers that do not say whether they are sRGB encoded
+pub fn load_container(path: &Path, srgb: bool) -> Result<TextureData, String> {
+    let mut bytes = Vec::new();
+    File::open(path)
+        .and_then(|mut f| f.read_to_end(&mut bytes))
+        .map_err(|e| format!("{}: {}", path.display(), e))?;
+
+    let is_ktx2 = path.extension().map_or(false, |e| e.to_string_lossy().to_lowercase() == "ktx2");
+    let texture = if is_ktx2 { ktx2::read(&bytes) } else { dds::read(&bytes, srgb) };
+
+    texture
+        .and_then(|t| t.validate().map(|_| t))
+        .map_err(|e| format!("{}: {}", path.display(), e))
+}
+
+// Reads any supported texture as RGBA, decoding block compressed containers on the CPU
+pub fn load_rgba(path: &Path) -> Result<image::RgbaImage, String> {
+    if !is_container(path) {
+        return image::open(path).map(|i| i.to_rgba()).map_err(|e| format!("{}: {}", path.display(), e));
+    }
+
+    let texture = bc::decompress(&load_container(path, false)?)?;
+    let size = texture.face_bytes(0).ok_or_else(|| format!("{}: texture is too large", path.display()))?;
+    let rgba = bc::expand_to_rgba(&texture.levels[0][..size], texture.format);
+    image::RgbaImage::from_raw(texture.width, texture.height, rgba)
+        .ok_or_else(|| format!("{}: unexpected image size", path.display()))
+}